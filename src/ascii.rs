@@ -0,0 +1,880 @@
+//! RFC-style ASCII art diagrams (as in RFC 791).
+//!
+//! Each bit takes two columns. Fixed fields are drawn as boxes sized from their length, fields that
+//! don't fit in the remaining row space continue on the next row and variable fields are drawn
+//! as elastic rows with `~` borders. Diagrams can also be parsed back into field descriptors.
+
+use protoviz::descriptor::{FieldDescriptor, FieldLength, ProtoDescriptor};
+
+use crate::length::{bits_length, fixed_bits};
+
+/// Row widths offered in the UI
+pub const ROW_WIDTHS: [usize; 3] = [16, 32, 64];
+
+/// Height (in lines) of an elastic row
+const ELASTIC_LINES: usize = 3;
+
+pub struct AsciiOptions {
+    /// Number of bits in each row
+    pub row_bits: usize,
+    /// Number of bits in a field length unit (1 if lengths are in bits, 8 if in bytes)
+    pub unit_bits: usize,
+}
+
+impl Default for AsciiOptions {
+    fn default() -> Self {
+        Self {
+            row_bits: 32,
+            unit_bits: 1,
+        }
+    }
+}
+
+/// A piece of a field inside a row
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    field: usize,
+    start: usize,
+    width: usize,
+}
+
+#[derive(Debug)]
+enum Row {
+    Fixed(Vec<Segment>),
+    Elastic(usize),
+}
+
+impl Row {
+    /// Field owning the bit, if any
+    fn owner(&self, bit: usize, row_bits: usize) -> Option<usize> {
+        match self {
+            Row::Fixed(segments) => segments
+                .iter()
+                .find(|s| bit >= s.start && bit < s.start + s.width)
+                .map(|s| s.field),
+            Row::Elastic(field) => Some(*field).filter(|_| bit < row_bits),
+        }
+    }
+
+    /// Range of bits covered by the row
+    fn span(&self, row_bits: usize) -> (usize, usize) {
+        match self {
+            Row::Fixed(segments) => match (segments.first(), segments.last()) {
+                (Some(first), Some(last)) => (first.start, last.start + last.width),
+                _ => (0, 0),
+            },
+            Row::Elastic(_) => (0, row_bits),
+        }
+    }
+}
+
+/// Split the fields into rows
+fn layout(descriptor: &ProtoDescriptor, options: &AsciiOptions) -> Vec<Row> {
+    let unit_bits = options.unit_bits;
+    let mut rows = Vec::new();
+    let mut current = Vec::new();
+    let mut cursor = 0;
+
+    for (i, field) in descriptor.fields.iter().enumerate() {
+        match fixed_bits(&field.length, unit_bits) {
+            Some(bits) => {
+                let mut bits = bits.max(1);
+                while bits > 0 {
+                    let width = bits.min(options.row_bits - cursor);
+                    current.push(Segment {
+                        field: i,
+                        start: cursor,
+                        width,
+                    });
+                    cursor += width;
+                    bits -= width;
+
+                    if cursor == options.row_bits {
+                        rows.push(Row::Fixed(std::mem::take(&mut current)));
+                        cursor = 0;
+                    }
+                }
+            }
+            None => {
+                if cursor != 0 {
+                    rows.push(Row::Fixed(std::mem::take(&mut current)));
+                    cursor = 0;
+                }
+                rows.push(Row::Elastic(i));
+            }
+        }
+
+        if field.wrap && cursor != 0 {
+            rows.push(Row::Fixed(std::mem::take(&mut current)));
+            cursor = 0;
+        }
+    }
+
+    if !current.is_empty() {
+        rows.push(Row::Fixed(current));
+    }
+
+    // Little endian rows grow from the right (LSB)
+    if !descriptor.elements.network_order {
+        for row in rows.iter_mut() {
+            if let Row::Fixed(segments) = row {
+                for segment in segments.iter_mut() {
+                    segment.start = options.row_bits - segment.start - segment.width;
+                }
+                segments.reverse();
+            }
+        }
+    }
+
+    rows
+}
+
+/// Bit ruler lines (tens and units)
+fn ruler(options: &AsciiOptions, network_order: bool) -> [String; 2] {
+    let mut tens = String::new();
+    let mut units = String::new();
+
+    for i in 0..options.row_bits {
+        let bit = if network_order {
+            i
+        } else {
+            options.row_bits - 1 - i
+        };
+
+        tens.push(' ');
+        units.push(' ');
+        if bit % 10 == 0 {
+            tens.push_str(&(bit / 10 % 10).to_string());
+        } else {
+            tens.push(' ');
+        }
+        units.push_str(&(bit % 10).to_string());
+    }
+
+    [tens, units]
+}
+
+/// Write a label centered between two columns (exclusive), truncating it if needed. Returns
+/// whether it was truncated.
+fn write_label(line: &mut [char], start: usize, end: usize, label: &str) -> bool {
+    let space = end.saturating_sub(start);
+    let label: Vec<char> = label.trim().chars().collect();
+    let shown = &label[..label.len().min(space)];
+    let offset = start + (space - shown.len()) / 2;
+
+    line[offset..offset + shown.len()].copy_from_slice(shown);
+    shown.len() < label.len()
+}
+
+/// An ASCII diagram, with the names of the fields too long for their boxes. Their names are
+/// truncated in the diagram, and won't read back as they were.
+pub struct AsciiDiagram {
+    pub text: String,
+    pub truncated: Vec<String>,
+}
+
+/// Render the descriptor as an RFC-style ASCII diagram
+pub fn render_ascii(
+    descriptor: &ProtoDescriptor,
+    options: &AsciiOptions,
+) -> Result<AsciiDiagram, String> {
+    if descriptor.fields.is_empty() {
+        return Err("no fields provided".to_string());
+    }
+    if options.row_bits == 0 || options.unit_bits == 0 {
+        return Err("row width and unit size must be positive".to_string());
+    }
+
+    let rows = layout(descriptor, options);
+    let columns = 2 * options.row_bits + 1;
+
+    let mut lines: Vec<Vec<char>> = Vec::new();
+    // Lines where each row and separator starts
+    let mut separator_lines = Vec::new();
+    let mut row_lines = Vec::new();
+
+    for (r, row) in rows.iter().enumerate() {
+        separator_lines.push(lines.len());
+        lines.push(separator(
+            rows.get(r.wrapping_sub(1)),
+            Some(row),
+            options.row_bits,
+        ));
+
+        row_lines.push(lines.len());
+        match row {
+            Row::Fixed(segments) => {
+                let mut line = vec![' '; columns];
+                for segment in segments {
+                    line[2 * segment.start] = '|';
+                    line[2 * (segment.start + segment.width)] = '|';
+                }
+                lines.push(line);
+            }
+            Row::Elastic(_) => {
+                for _ in 0..ELASTIC_LINES {
+                    let mut line = vec![' '; columns];
+                    line[0] = '~';
+                    line[columns - 1] = '~';
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    lines.push(separator(rows.last(), None, options.row_bits));
+
+    // Place the labels in the middle of the area taken by each field
+    let mut truncated = Vec::new();
+    for (i, field) in descriptor.fields.iter().enumerate() {
+        // (line, start column, end column) of the blank areas taken by the field. Only the first
+        // region (boxes joined by open borders) is labeled, so boxes with no label read as the
+        // continuation of the previous field.
+        let mut areas = Vec::new();
+        let mut previous_segment: Option<Segment> = None;
+
+        'rows: for (r, row) in rows.iter().enumerate() {
+            match row {
+                Row::Fixed(segments) => {
+                    for segment in segments.iter().filter(|s| s.field == i) {
+                        if let Some(previous) = previous_segment {
+                            if segment.start >= previous.start + previous.width
+                                || previous.start >= segment.start + segment.width
+                            {
+                                break 'rows;
+                            }
+                        }
+                        previous_segment = Some(*segment);
+
+                        let start = 2 * segment.start + 1;
+                        let end = 2 * (segment.start + segment.width);
+
+                        // Include the separator above if the field continues over the same columns
+                        let previous = areas.last().copied();
+                        if let Some((line, prev_start, prev_end)) = previous {
+                            if line + 2 == row_lines[r] && prev_start == start && prev_end == end {
+                                areas.push((separator_lines[r], start, end));
+                            }
+                        }
+                        areas.push((row_lines[r], start, end));
+                    }
+                }
+                Row::Elastic(field) if *field == i => {
+                    areas.push((row_lines[r] + ELASTIC_LINES / 2, 1, columns - 1));
+                }
+                Row::Elastic(_) => {}
+            }
+        }
+
+        let widest = areas.iter().map(|(_, start, end)| end - start).max();
+        let candidates: Vec<_> = areas
+            .iter()
+            .filter(|(_, start, end)| Some(end - start) == widest)
+            .collect();
+
+        if let Some((line, start, end)) = candidates.get(candidates.len().saturating_sub(1) / 2) {
+            if write_label(&mut lines[*line], *start, *end, &field.name) {
+                truncated.push(field.name.clone());
+            }
+        }
+    }
+
+    let mut output = String::new();
+    for line in ruler(options, descriptor.elements.network_order) {
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    for line in lines {
+        output.push_str(line.into_iter().collect::<String>().trim_end());
+        output.push('\n');
+    }
+
+    Ok(AsciiDiagram {
+        text: output,
+        truncated,
+    })
+}
+
+/// Create the separator line between two rows. Fields that continue from one row to the next
+/// keep their area open.
+fn separator(above: Option<&Row>, below: Option<&Row>, row_bits: usize) -> Vec<char> {
+    let (start, end) = [above, below]
+        .into_iter()
+        .flatten()
+        .map(|row| row.span(row_bits))
+        .fold((row_bits, 0), |(start, end), (row_start, row_end)| {
+            (start.min(row_start), end.max(row_end))
+        });
+
+    let continues = |bit: usize| match (above, below) {
+        (Some(Row::Fixed(_)), Some(Row::Fixed(_))) => {
+            let owner = above.and_then(|row| row.owner(bit, row_bits));
+            owner.is_some() && owner == below.and_then(|row| row.owner(bit, row_bits))
+        }
+        _ => false,
+    };
+
+    let mut line = vec![' '; 2 * row_bits + 1];
+    for bit in start..end {
+        line[2 * bit + 1] = if continues(bit) { ' ' } else { '-' };
+        line[2 * bit] = if bit > start && continues(bit - 1) && continues(bit) {
+            ' '
+        } else {
+            '+'
+        };
+    }
+    if end > start {
+        line[2 * end] = '+';
+    }
+
+    line
+}
+
+/// Error found while parsing a diagram, with 1-based line and column
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            line: line + 1,
+            column: column + 1,
+            message: message.into(),
+        }
+    }
+
+    /// Offending line of the diagram followed by a caret under the column
+    pub fn snippet(&self, text: &str) -> String {
+        let line: String = text
+            .lines()
+            .nth(self.line - 1)
+            .map(expand_tabs)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        format!("{}\n{}^", line, " ".repeat(self.column - 1))
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// Characters used as walls of elastic (variable length) rows
+const ELASTIC_WALLS: [char; 2] = ['~', '/'];
+
+/// A box of the diagram (one row of a field)
+struct Piece {
+    /// Connected area (boxes joined by open borders) the piece belongs to
+    region: usize,
+    bits: usize,
+    variable: bool,
+    /// Whether the piece is the last one of its row
+    ends_row: bool,
+    line: usize,
+    column: usize,
+}
+
+fn expand_tabs(line: &str) -> Vec<char> {
+    let mut chars = Vec::new();
+    for c in line.chars() {
+        if c == '\t' {
+            chars.resize((chars.len() / 8 + 1) * 8, ' ');
+        } else {
+            chars.push(c);
+        }
+    }
+    chars
+}
+
+fn first_char(line: &[char]) -> Option<(usize, char)> {
+    line.iter().copied().enumerate().find(|(_, c)| *c != ' ')
+}
+
+fn is_border(line: &[char]) -> bool {
+    first_char(line).map(|(_, c)| c) == Some('+')
+}
+
+fn text_between(line: &[char], start: usize, end: usize) -> String {
+    line.get(start..end.min(line.len()))
+        .unwrap_or_default()
+        .iter()
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Append a piece of label to a field name
+fn append_label(name: &mut String, label: &str) {
+    if label.is_empty() {
+        return;
+    }
+    if !name.is_empty() {
+        name.push(' ');
+    }
+    name.push_str(label);
+}
+
+/// Parse an RFC-style ASCII diagram into field descriptors.
+///
+/// Each bit must take two columns and lengths are divided by `unit_bits`, so the diagram can be
+/// imported in bits (1) or bytes (8), with lengths in bits for the fields that don't fill a unit.
+/// Boxes joined by open borders form a single field, and boxes with no label continue the
+/// previous field. Rows drawn with `~` or `/` walls become variable length fields. With a
+/// descending bit ruler, rows are read from right to left.
+pub fn parse_ascii(text: &str, unit_bits: usize) -> Result<Vec<FieldDescriptor>, ParseError> {
+    if unit_bits == 0 {
+        return Err(ParseError::new(0, 0, "unit size must be positive"));
+    }
+
+    let lines: Vec<Vec<char>> = text.lines().map(expand_tabs).collect();
+    let first = lines
+        .iter()
+        .position(|line| is_border(line))
+        .ok_or_else(|| ParseError::new(0, 0, "no '+-+' border found"))?;
+    let last = lines
+        .iter()
+        .rposition(|line| is_border(line))
+        .unwrap_or(first);
+
+    // Left edge of the diagram
+    let origin = lines[first..=last]
+        .iter()
+        .filter(|line| is_border(line))
+        .filter_map(|line| first_char(line).map(|(column, _)| column))
+        .min()
+        .unwrap_or(0);
+
+    // Columns must land on bit boundaries (even offsets from the left edge)
+    let bit_boundary = |line: usize, column: usize| -> Result<usize, ParseError> {
+        if column < origin || (column - origin) % 2 != 0 {
+            return Err(ParseError::new(
+                line,
+                column,
+                "border is not aligned to a bit boundary",
+            ));
+        }
+        Ok((column - origin) / 2)
+    };
+
+    let mut row_bits = 0;
+    for (l, line) in lines.iter().enumerate().take(last + 1).skip(first) {
+        if is_border(line) {
+            for (column, _) in line.iter().enumerate().filter(|(_, c)| **c == '+') {
+                row_bits = row_bits.max(bit_boundary(l, column)?);
+            }
+        }
+    }
+    if row_bits == 0 {
+        return Err(ParseError::new(first, origin, "border is too short"));
+    }
+
+    // A descending ruler (as in "1 0 9 8 ... 1 0") marks a little endian diagram
+    let reversed = lines[..first]
+        .iter()
+        .rev()
+        .find(|line| {
+            line.iter().any(|c| c.is_ascii_digit())
+                && line.iter().all(|c| c.is_ascii_digit() || *c == ' ')
+        })
+        .and_then(|ruler| ruler.get(origin + 1))
+        .is_some_and(|c| c.is_ascii_digit() && *c != '0');
+
+    let mut pieces: Vec<Piece> = Vec::new();
+    // Label of each region
+    let mut labels: Vec<String> = Vec::new();
+    // Piece owning each bit in the row above
+    let mut owners: Vec<Option<usize>> = vec![None; row_bits];
+    // Bits left open by the last border
+    let mut open = vec![false; row_bits];
+    let mut l = first;
+
+    while l <= last {
+        if is_border(&lines[l]) {
+            let line = &lines[l];
+            let corners: Vec<usize> = line
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c == '+')
+                .map(|(column, _)| column)
+                .collect();
+
+            open = vec![false; row_bits];
+            for pair in corners.windows(2) {
+                let (start, end) = (bit_boundary(l, pair[0])?, bit_boundary(l, pair[1])?);
+                let edge = &line[pair[0] + 1..pair[1]];
+                if edge.iter().all(|c| *c == '-') {
+                    continue;
+                }
+                if let Some(dash) = edge.iter().position(|c| *c == '-') {
+                    return Err(ParseError::new(
+                        l,
+                        pair[0] + 1 + dash,
+                        "border is partially open",
+                    ));
+                }
+                open[start..end].iter_mut().for_each(|bit| *bit = true);
+
+                // Texts inside open borders label the field above (as in RFC 8200)
+                let label = text_between(line, pair[0] + 1, pair[1]);
+                match owners[start] {
+                    Some(owner) => append_label(&mut labels[pieces[owner].region], &label),
+                    None if !label.is_empty() => {
+                        return Err(ParseError::new(l, pair[0] + 1, "text outside of a field"));
+                    }
+                    None => {}
+                }
+            }
+
+            l += 1;
+            continue;
+        }
+
+        // Lines between two borders make a row
+        let row_start = l;
+        while l <= last && !is_border(&lines[l]) {
+            l += 1;
+        }
+        let row_lines = &lines[row_start..l];
+
+        let mut elastic = false;
+        for (offset, line) in row_lines.iter().enumerate() {
+            match first_char(line) {
+                Some((_, '|')) | None => {}
+                Some((_, c)) if ELASTIC_WALLS.contains(&c) => elastic = true,
+                Some((column, c)) => {
+                    return Err(ParseError::new(
+                        row_start + offset,
+                        column,
+                        format!("unexpected character '{}'", c),
+                    ));
+                }
+            }
+        }
+
+        if elastic {
+            let mut label = String::new();
+            for line in row_lines {
+                if let Some((start, _)) = first_char(line) {
+                    let end = line.iter().rposition(|c| *c != ' ').unwrap_or(start);
+                    append_label(&mut label, &text_between(line, start + 1, end));
+                }
+            }
+
+            labels.push(label);
+            pieces.push(Piece {
+                region: labels.len() - 1,
+                bits: 0,
+                variable: true,
+                ends_row: false,
+                line: row_start,
+                column: origin,
+            });
+            owners = vec![None; row_bits];
+            continue;
+        }
+
+        // The first line sets the field borders, the others may only hold labels
+        let mut walls = Vec::new();
+        for (column, _) in row_lines[0].iter().enumerate().filter(|(_, c)| **c == '|') {
+            let bit = bit_boundary(row_start, column)?;
+            if bit > row_bits {
+                return Err(ParseError::new(
+                    row_start,
+                    column,
+                    "field is wider than the borders",
+                ));
+            }
+            walls.push((column, bit));
+        }
+        if walls.len() < 2 {
+            return Err(ParseError::new(
+                row_start,
+                origin,
+                "row has no complete field",
+            ));
+        }
+        let right_wall = walls[walls.len() - 1].0;
+        for (offset, line) in row_lines.iter().enumerate() {
+            for (column, c) in line.iter().enumerate() {
+                if *c == '|' && !walls.iter().any(|(wall, _)| *wall == column) {
+                    return Err(ParseError::new(
+                        row_start + offset,
+                        column,
+                        "field border doesn't match the first line of the row",
+                    ));
+                }
+                if *c != ' ' && column > right_wall {
+                    return Err(ParseError::new(
+                        row_start + offset,
+                        column,
+                        "text outside of a field",
+                    ));
+                }
+            }
+        }
+
+        let mut cells: Vec<_> = walls.windows(2).filter(|w| w[0].1 < w[1].1).collect();
+        if reversed {
+            cells.reverse();
+        }
+
+        let mut row_owners = vec![None; row_bits];
+        let cell_count = cells.len();
+        for (c, cell) in cells.into_iter().enumerate() {
+            let ((start_column, start), (end_column, end)) = (cell[0], cell[1]);
+
+            let mut label = String::new();
+            for line in row_lines {
+                append_label(
+                    &mut label,
+                    &text_between(line, start_column + 1, end_column),
+                );
+            }
+
+            // Join the region of the boxes above through the open border
+            let mut above = (start..end)
+                .filter(|bit| open[*bit])
+                .filter_map(|bit| owners[bit])
+                .map(|owner| pieces[owner].region);
+            let region = match above.next() {
+                Some(region) => {
+                    if above.any(|other| other != region) {
+                        return Err(ParseError::new(
+                            row_start,
+                            start_column + 1,
+                            "box is open to more than one field above",
+                        ));
+                    }
+                    append_label(&mut labels[region], &label);
+                    region
+                }
+                None => {
+                    labels.push(label);
+                    labels.len() - 1
+                }
+            };
+
+            pieces.push(Piece {
+                region,
+                bits: end - start,
+                variable: false,
+                ends_row: c + 1 == cell_count,
+                line: row_start,
+                column: start_column,
+            });
+            row_owners[start..end]
+                .iter_mut()
+                .for_each(|owner| *owner = Some(pieces.len() - 1));
+        }
+        owners = row_owners;
+    }
+
+    if pieces.is_empty() {
+        return Err(ParseError::new(first, origin, "diagram has no fields"));
+    }
+
+    // Field index of each region
+    let mut region_fields: Vec<Option<usize>> = vec![None; labels.len()];
+    let mut fields: Vec<(FieldDescriptor, usize)> = Vec::new();
+
+    for (p, piece) in pieces.iter().enumerate() {
+        let index = match region_fields[piece.region] {
+            Some(index) if p > 0 && pieces[p - 1].region == piece.region => index,
+            Some(_) => {
+                return Err(ParseError::new(
+                    piece.line,
+                    piece.column,
+                    "field is split by another field",
+                ));
+            }
+            // Boxes with no label continue the previous field
+            None if labels[piece.region].is_empty()
+                && !piece.variable
+                && fields
+                    .last()
+                    .is_some_and(|(field, _)| matches!(field.length, FieldLength::Fixed(_))) =>
+            {
+                fields.len() - 1
+            }
+            None => {
+                let length = if piece.variable {
+                    FieldLength::Variable("N".to_string())
+                } else {
+                    FieldLength::Fixed(0)
+                };
+                fields.push((
+                    FieldDescriptor {
+                        name: labels[piece.region].clone(),
+                        length,
+                        wrap: false,
+                        color: None,
+                    },
+                    0,
+                ));
+                fields.len() - 1
+            }
+        };
+        region_fields[piece.region] = Some(index);
+
+        let (field, bits) = &mut fields[index];
+        *bits += piece.bits;
+        field.wrap = piece.ends_row;
+    }
+
+    let count = fields.len();
+    Ok(fields
+        .into_iter()
+        .enumerate()
+        .map(|(i, (mut field, bits))| {
+            // Fields that aren't a whole number of units get a length in bits
+            if let FieldLength::Fixed(_) = field.length {
+                field.length = bits_length(bits, unit_bits);
+            }
+            // The last field doesn't need to wrap
+            field.wrap &= i + 1 < count;
+            field
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::TEMPLATES;
+
+    fn field(name: &str, length: FieldLength, wrap: bool) -> FieldDescriptor {
+        FieldDescriptor {
+            name: name.to_string(),
+            length,
+            wrap,
+            color: None,
+        }
+    }
+
+    fn descriptor(fields: Vec<FieldDescriptor>) -> ProtoDescriptor {
+        ProtoDescriptor {
+            fields,
+            ..Default::default()
+        }
+    }
+
+    fn options(row_bits: usize, unit_bits: usize) -> AsciiOptions {
+        AsciiOptions {
+            row_bits,
+            unit_bits,
+        }
+    }
+
+    #[test]
+    fn renders_rfc_diagrams() {
+        let fields = vec![
+            field("Type", FieldLength::Fixed(4), false),
+            field("Flags", FieldLength::Fixed(4), false),
+            field("Length", FieldLength::Fixed(8), true),
+            field("Data", FieldLength::Variable("Length".to_string()), false),
+        ];
+        let ascii = render_ascii(&descriptor(fields), &options(16, 1)).unwrap();
+        assert!(ascii.truncated.is_empty());
+        assert_eq!(
+            ascii.text,
+            concat!(
+                " 0                   1\n",
+                " 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5\n",
+                "+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+\n",
+                "| Type  | Flags |    Length     |\n",
+                "+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+\n",
+                "~                               ~\n",
+                "~             Data              ~\n",
+                "~                               ~\n",
+                "+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+\n",
+            )
+        );
+    }
+
+    #[test]
+    fn round_trips_templates() {
+        for template in TEMPLATES {
+            let descriptor = template.descriptor().unwrap();
+            let ascii = render_ascii(&descriptor, &options(32, template.unit_bits)).unwrap();
+            let fields = parse_ascii(&ascii.text, template.unit_bits)
+                .unwrap_or_else(|e| panic!("{}: {}\n{}", template.name, e, ascii.text));
+
+            assert_eq!(fields.len(), descriptor.fields.len(), "{}", template.name);
+            for (parsed, field) in fields.iter().zip(&descriptor.fields) {
+                if !ascii.truncated.contains(&field.name) {
+                    assert_eq!(parsed.name, field.name, "{}", template.name);
+                }
+                match &field.length {
+                    FieldLength::Fixed(_) => assert_eq!(parsed.length, field.length),
+                    FieldLength::Variable(_) => {
+                        assert_eq!(parsed.length, FieldLength::Variable("N".to_string()))
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reports_truncated_names() {
+        let mqtt = TEMPLATES
+            .iter()
+            .find(|template| template.name == "MQTT Fixed Header")
+            .unwrap();
+        let ascii =
+            render_ascii(&mqtt.descriptor().unwrap(), &options(32, mqtt.unit_bits)).unwrap();
+        assert!(
+            ascii.truncated.contains(&"Packet Type".to_string()),
+            "{:?}",
+            ascii.truncated
+        );
+        let fields = parse_ascii(&ascii.text, mqtt.unit_bits).unwrap();
+        assert_eq!(fields[0].name, "Packet");
+    }
+
+    #[test]
+    fn parses_little_endian_diagrams_and_bits() {
+        let fields = vec![
+            field("Version", FieldLength::Variable("4b".to_string()), false),
+            field("Kind", FieldLength::Variable("4b".to_string()), false),
+            field("Size", FieldLength::Fixed(1), false),
+        ];
+        let mut little_endian = descriptor(fields);
+        little_endian.elements.network_order = false;
+        let ascii = render_ascii(&little_endian, &options(16, 8)).unwrap();
+
+        let parsed = parse_ascii(&ascii.text, 8).unwrap();
+        let parsed: Vec<_> = parsed
+            .iter()
+            .map(|field| (field.name.as_str(), field.length.clone()))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                ("Version", FieldLength::Variable("4b".to_string())),
+                ("Kind", FieldLength::Variable("4b".to_string())),
+                ("Size", FieldLength::Fixed(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        let error = parse_ascii("no diagram here", 1).unwrap_err();
+        assert_eq!(error.message, "no '+-+' border found");
+
+        let misaligned = "+-+-+-+-+\n|  A |   |\n+-+-+-+-+\n";
+        let error = parse_ascii(misaligned, 1).unwrap_err();
+        assert_eq!((error.line, error.column), (2, 6));
+        assert_eq!(error.message, "border is not aligned to a bit boundary");
+    }
+}
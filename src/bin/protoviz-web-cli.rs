@@ -0,0 +1,312 @@
+//! Command line renderer of protoviz-web documents.
+//!
+//! Renders the documents saved by the web app (or plain protoviz descriptors) to SVG or PNG
+//! images, one per frame, for build scripts and CI. With `--check`, nothing is written and the
+//! command fails when an image is missing or doesn't match its document.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use hex_color::HexColor;
+use serde_json::{Map, Value};
+
+use protoviz_web::document::{frame_descriptors, parse_document};
+use protoviz_web::fields::update_svg;
+use protoviz_web::group::field_at;
+use protoviz_web::raster::{render_png, RasterOptions};
+
+const USAGE: &str = "\
+Usage: protoviz-web-cli [OPTIONS] <FILE>...
+
+Render protoviz-web documents (the .json files saved by the web app) to images, one per frame.
+
+Options:
+  -o, --out-dir <DIR>      Write the images to DIR instead of next to each document
+  -f, --format <FORMAT>    Format of the images: svg (default) or png
+      --scale <SCALE>      Scale of the PNG images [default: 1]
+  -s, --style <KEY=VALUE>  Override a style property, e.g. unit_width=30 or field_color=#ffcc00
+      --check              Write nothing, and fail if an image is missing or out of date
+  -h, --help               Print this help
+";
+
+/// Style properties of the descriptors holding colors
+const COLOR_KEYS: [&str; 4] = [
+    "background_color",
+    "field_color",
+    "text_color",
+    "subtitle_color",
+];
+
+/// Style properties of the descriptors holding sizes
+const SIZE_KEYS: [&str; 2] = ["unit_width", "dyn_units"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Svg,
+    Png,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Svg => "svg",
+            Format::Png => "png",
+        }
+    }
+}
+
+struct Options {
+    files: Vec<PathBuf>,
+    out_dir: Option<PathBuf>,
+    format: Format,
+    scale: f64,
+    style: Map<String, Value>,
+    check: bool,
+}
+
+/// Read the options from the arguments, or None when asked for help
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        files: Vec::new(),
+        out_dir: None,
+        format: Format::Svg,
+        scale: 1.0,
+        style: Map::new(),
+        check: false,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            options.files.extend(args.by_ref().map(PathBuf::from));
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            options.files.push(PathBuf::from(arg));
+            continue;
+        }
+
+        // Values are given as the next argument or after '='
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg, None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value of {}", flag))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--check" => options.check = true,
+            "-o" | "--out-dir" => options.out_dir = Some(PathBuf::from(value()?)),
+            "-f" | "--format" => {
+                options.format = match value()?.to_lowercase().as_str() {
+                    "svg" => Format::Svg,
+                    "png" => Format::Png,
+                    format => return Err(format!("unknown format '{}'", format)),
+                }
+            }
+            "--scale" => {
+                let scale = value()?;
+                options.scale = scale
+                    .parse()
+                    .ok()
+                    .filter(|scale: &f64| *scale > 0.0)
+                    .ok_or_else(|| format!("invalid scale '{}'", scale))?;
+            }
+            "-s" | "--style" => {
+                let style = value()?;
+                let Some((key, value)) = style.split_once('=') else {
+                    return Err(format!("invalid style '{}', expected KEY=VALUE", style));
+                };
+                let (key, value) = (key.trim(), value.trim());
+                let value = if SIZE_KEYS.contains(&key) {
+                    let size = value
+                        .parse::<u64>()
+                        .map_err(|_| format!("invalid {} '{}', expected a number", key, value))?;
+                    Value::from(size)
+                } else if COLOR_KEYS.contains(&key) {
+                    // The '#' is optional, and easily lost to the shell
+                    let color = match value.starts_with('#') {
+                        true => value.to_string(),
+                        false => format!("#{}", value),
+                    };
+                    HexColor::parse(&color).map_err(|_| {
+                        format!("invalid {} '{}', expected a hex color", key, value)
+                    })?;
+                    Value::from(color)
+                } else {
+                    return Err(format!(
+                        "unknown style property '{}' (one of {}, {})",
+                        key,
+                        COLOR_KEYS.join(", "),
+                        SIZE_KEYS.join(", ")
+                    ));
+                };
+                options.style.insert(key.to_string(), value);
+            }
+            _ => return Err(format!("unknown option '{}'", flag)),
+        }
+    }
+
+    if options.files.is_empty() {
+        return Err("no documents given".to_string());
+    }
+    Ok(Some(options))
+}
+
+/// Name of a frame usable in a file name
+fn file_name_part(name: &str) -> String {
+    let part: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_lowercase(),
+            false => '-',
+        })
+        .collect();
+    part.split('-')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Images of the frames of the document, with their paths
+fn render_document(path: &Path, options: &Options) -> Result<Vec<(PathBuf, Vec<u8>)>, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut json: Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+    if !options.style.is_empty() {
+        let Some(document) = json.as_object_mut() else {
+            return Err("not a document".to_string());
+        };
+        let style = document
+            .entry("style")
+            .or_insert_with(|| Value::Object(Map::new()));
+        let Some(style) = style.as_object_mut() else {
+            return Err("invalid style".to_string());
+        };
+        style.extend(options.style.clone());
+    }
+    let document = parse_document(json)?;
+
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "diagram".to_string());
+    let dir = match &options.out_dir {
+        Some(dir) => dir.clone(),
+        None => path.parent().unwrap_or(Path::new("")).to_path_buf(),
+    };
+    let extension = options.format.extension();
+
+    let descriptors = frame_descriptors(&document.descriptor, &document.frames, 0);
+    let mut images = Vec::with_capacity(document.frames.len());
+    for (i, (frame, descriptor)) in document.frames.iter().zip(&descriptors).enumerate() {
+        let svg =
+            update_svg(descriptor, &frame.fields, document.unit_bits).map_err(|diagnostic| {
+                let field = diagnostic
+                    .field
+                    .as_deref()
+                    .and_then(|path| field_at(&frame.fields, path));
+                match field {
+                    Some(field) => format!(
+                        "frame '{}', field '{}': {}",
+                        frame.name, field.name, diagnostic.message
+                    ),
+                    None => format!("frame '{}': {}", frame.name, diagnostic.message),
+                }
+            })?;
+        if svg.is_empty() {
+            return Err(format!("frame '{}' has no fields", frame.name));
+        }
+
+        let data = match options.format {
+            Format::Svg => svg.into_bytes(),
+            Format::Png => render_png(
+                &svg,
+                &RasterOptions {
+                    scale: options.scale,
+                    background: Some(descriptor.style.background_color),
+                },
+            )
+            .map_err(|e| format!("frame '{}': {}", frame.name, e))?,
+        };
+
+        // Documents with several frames get an image per frame, named after it
+        let name = if document.frames.len() == 1 {
+            format!("{}.{}", stem, extension)
+        } else {
+            let part = match file_name_part(&frame.name) {
+                part if part.is_empty() => (i + 1).to_string(),
+                part => part,
+            };
+            format!("{}-{}.{}", stem, part, extension)
+        };
+        images.push((dir.join(name), data));
+    }
+    Ok(images)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    if let (Some(dir), false) = (&options.out_dir, options.check) {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("{}: {}", dir.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let mut failed = false;
+    for path in &options.files {
+        let images = match render_document(path, &options) {
+            Ok(images) => images,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                failed = true;
+                continue;
+            }
+        };
+
+        for (image_path, data) in images {
+            if options.check {
+                match fs::read(&image_path) {
+                    Ok(existing) if existing == data => {}
+                    Ok(_) => {
+                        eprintln!("{}: out of date", image_path.display());
+                        failed = true;
+                    }
+                    Err(_) => {
+                        eprintln!("{}: missing", image_path.display());
+                        failed = true;
+                    }
+                }
+            } else if let Err(e) = fs::write(&image_path, &data) {
+                eprintln!("{}: {}", image_path.display(), e);
+                failed = true;
+            } else {
+                println!("{}", image_path.display());
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
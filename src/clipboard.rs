@@ -0,0 +1,76 @@
+//! Fields on the clipboard.
+//!
+//! Copied fields are put on the clipboard as the JSON array of their descriptors, with their
+//! children as in the saved JSON, and as a line of `Name:Length` pairs for other applications.
+//! Pasting accepts either, as well as a whole saved descriptor.
+
+use protoviz::descriptor::{FieldDescriptor, ProtoDescriptor};
+use serde_json::Value;
+
+use crate::fields::{update_field_inputs, FieldInput};
+use crate::group::{fields_value, read_children};
+
+/// Type of the JSON of the fields on the clipboard
+pub const JSON_TYPE: &str = "application/json";
+
+/// JSON of the fields, with their children, given the number of bits in a unit
+pub fn fields_json(
+    descriptor: &ProtoDescriptor,
+    fields: &[FieldInput],
+    unit_bits: usize,
+) -> Result<String, String> {
+    let json = fields_value(descriptor, fields, unit_bits).map_err(|e| e.to_string())?;
+    serde_json::to_string_pretty(&json).map_err(|e| e.to_string())
+}
+
+/// Text of the fields, as `Version:4b, IHL:4b, DSCP:6b`
+pub fn fields_text(fields: &[FieldInput]) -> String {
+    fields
+        .iter()
+        .map(|field| match field.length.as_str() {
+            "" => field.name.clone(),
+            length => format!("{}:{}", field.name, length),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Read the fields from the JSON or the text of copied fields
+pub fn parse_fields(text: &str) -> Result<Vec<FieldInput>, String> {
+    let text = text.trim();
+    if text.starts_with(['[', '{']) {
+        let json: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        // The fields of a whole descriptor
+        let json_fields = match json.get("fields") {
+            Some(fields) => fields.clone(),
+            None => json,
+        };
+        let descriptors: Vec<FieldDescriptor> =
+            serde_json::from_value(json_fields.clone()).map_err(|e| e.to_string())?;
+        let mut fields = update_field_inputs(&descriptors);
+        read_children(&mut fields, &json_fields)?;
+        return Ok(fields);
+    }
+
+    let fields = text
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let (name, length) = item.rsplit_once(':').unwrap_or((item, ""));
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(format!("missing field name in '{}'", item));
+            }
+            Ok(FieldInput {
+                name: name.to_string(),
+                length: length.trim().to_string(),
+                ..Default::default()
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if fields.is_empty() {
+        return Err("there are no fields".to_string());
+    }
+    Ok(fields)
+}
@@ -0,0 +1,358 @@
+//! Packed C struct generator.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use protoviz::descriptor::ProtoDescriptor;
+
+use super::{layout, unique_ident, CodegenOptions, Field, Member};
+
+const KEYWORDS: &[&str] = &[
+    "auto", "bool", "break", "case", "char", "const", "continue", "default", "do", "double",
+    "else", "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long", "register",
+    "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef",
+    "union", "unsigned", "void", "volatile", "while",
+];
+
+/// Integer type holding the given number of bytes, if any
+fn int_type(bytes: usize) -> Option<&'static str> {
+    match bytes {
+        1 => Some("uint8_t"),
+        2 => Some("uint16_t"),
+        4 => Some("uint32_t"),
+        8 => Some("uint64_t"),
+        _ => None,
+    }
+}
+
+/// Smallest integer type holding the given number of bits
+fn value_type(bits: usize) -> &'static str {
+    match bits {
+        0..=8 => "uint8_t",
+        9..=16 => "uint16_t",
+        17..=32 => "uint32_t",
+        _ => "uint64_t",
+    }
+}
+
+/// Mask of the given number of bits as a C literal
+fn mask(bits: usize) -> String {
+    let mask = if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    };
+    if bits > 32 {
+        format!("0x{:X}ULL", mask)
+    } else {
+        format!("0x{:X}U", mask)
+    }
+}
+
+fn bits_label(bits: usize) -> String {
+    if bits == 1 {
+        "1 bit".to_string()
+    } else {
+        format!("{} bits", bits)
+    }
+}
+
+/// Make a name safe to put inside a comment
+fn comment(name: &str) -> String {
+    name.replace("*/", "* /")
+}
+
+/// Read a member value in the host byte order
+fn read(prefix: &str, ident: &str, bytes: usize) -> String {
+    match bytes {
+        1 => format!("(p)->{}", ident),
+        bytes => format!("{}_SWAP{}((p)->{})", prefix, bytes * 8, ident),
+    }
+}
+
+fn swap_macros(out: &mut String, prefix: &str, sizes: &[usize], network_order: bool) {
+    let (identity, swap) = if network_order {
+        ("big endian", "little endian")
+    } else {
+        ("little endian", "big endian")
+    };
+
+    let identity_macros: Vec<String> = sizes
+        .iter()
+        .map(|bits| format!("#define {}_SWAP{}(x) ((uint{}_t)(x))", prefix, bits, bits))
+        .collect();
+    let mut swap_macros: Vec<String> = sizes
+        .iter()
+        .map(|bits| {
+            let body = match bits {
+                16 => "(uint16_t)((((x) & 0xFFU) << 8) | (((x) >> 8) & 0xFFU))".to_string(),
+                32 => "(uint32_t)((((x) & 0xFFU) << 24) | (((x) & 0xFF00U) << 8) | \
+                       (((x) >> 8) & 0xFF00U) | (((x) >> 24) & 0xFFU))"
+                    .to_string(),
+                _ => format!(
+                    "(uint64_t)(((uint64_t){p}_SWAP32((uint32_t)(x)) << 32) | \
+                     {p}_SWAP32((uint32_t)((uint64_t)(x) >> 32)))",
+                    p = prefix
+                ),
+            };
+            format!("#define {}_SWAP{}(x) ({})", prefix, bits, body)
+        })
+        .collect();
+
+    // 64 bit swaps are built on the 32 bit ones
+    if sizes.contains(&64) && !sizes.contains(&32) {
+        swap_macros.insert(
+            0,
+            format!(
+                "#define {}_SWAP32(x) ((uint32_t)((((x) & 0xFFU) << 24) | (((x) & 0xFF00U) << 8) | \
+                 (((x) >> 8) & 0xFF00U) | (((x) >> 24) & 0xFFU)))",
+                prefix
+            ),
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "/* Convert between the frame byte order ({}) and the host byte order */",
+        identity
+    );
+    let _ = writeln!(
+        out,
+        "#if defined(__BYTE_ORDER__) && __BYTE_ORDER__ == __ORDER_{}_ENDIAN__",
+        if network_order { "BIG" } else { "LITTLE" }
+    );
+    for line in identity_macros {
+        let _ = writeln!(out, "{}", line);
+    }
+    let _ = writeln!(out, "#else /* {} host */", swap);
+    for line in swap_macros {
+        let _ = writeln!(out, "{}", line);
+    }
+    let _ = writeln!(out, "#endif\n");
+}
+
+/// Generate a header with a packed struct and accessor macros
+pub fn generate(descriptor: &ProtoDescriptor, options: &CodegenOptions) -> Result<String, String> {
+    let name = unique_ident(&options.name, "frame", KEYWORDS, &mut HashSet::new());
+    let prefix = name.to_uppercase();
+    let members = layout(descriptor, options, KEYWORDS)?;
+
+    // Members after a variable length field have no fixed offset, so they can't be in the struct
+    let (fixed, trailing) = match members
+        .iter()
+        .position(|member| matches!(member, Member::Variable { .. }))
+    {
+        Some(i) => members.split_at(i + 1),
+        None => members.split_at(members.len()),
+    };
+
+    if let [Member::Variable { field, .. }, ..] = fixed {
+        return Err(format!(
+            "the first field ('{}') has a variable length, so the struct would be empty",
+            field.name
+        ));
+    }
+
+    let mut sizes: Vec<usize> = fixed
+        .iter()
+        .filter_map(|member| match member {
+            Member::Bytes { field } => int_type(field.bits / 8).map(|_| field.bits),
+            Member::Bits { bytes, .. } => int_type(*bytes).map(|_| bytes * 8),
+            Member::Variable { .. } => None,
+        })
+        .filter(|bits| *bits > 8)
+        .collect();
+    sizes.sort();
+    sizes.dedup();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "/* Generated by ProtoViz */\n");
+    let _ = writeln!(out, "#ifndef {}_H", prefix);
+    let _ = writeln!(out, "#define {}_H\n", prefix);
+    let _ = writeln!(out, "#include <stdint.h>\n");
+
+    if !sizes.is_empty() {
+        swap_macros(&mut out, &prefix, &sizes, descriptor.elements.network_order);
+    }
+
+    let _ = writeln!(out, "#if defined(__GNUC__) || defined(__clang__)");
+    let _ = writeln!(out, "#define {}_PACKED __attribute__((packed))", prefix);
+    let _ = writeln!(out, "#else");
+    let _ = writeln!(out, "#define {}_PACKED", prefix);
+    let _ = writeln!(out, "#endif\n");
+
+    let _ = writeln!(out, "#pragma pack(push, 1)");
+    let _ = writeln!(out, "typedef struct {}_PACKED {} {{", prefix, name);
+
+    let mut offset = 0;
+    for member in fixed {
+        match member {
+            Member::Bytes { field } => {
+                let bytes = field.bits / 8;
+                let _ = writeln!(out, "    /* Offset {}: {} */", offset, comment(&field.name));
+                match int_type(bytes) {
+                    Some(ty) => {
+                        let _ = writeln!(out, "    {} {};", ty, field.ident);
+                    }
+                    None => {
+                        let _ = writeln!(out, "    uint8_t {}[{}];", field.ident, bytes);
+                    }
+                }
+            }
+            Member::Bits {
+                ident,
+                bytes,
+                fields,
+                padding,
+            } => {
+                let _ = writeln!(out, "    /* Offset {}:", offset);
+                for (field, shift) in fields {
+                    let _ = writeln!(
+                        out,
+                        "     *   {} ({}, shift {})",
+                        comment(&field.name),
+                        bits_label(field.bits),
+                        shift
+                    );
+                }
+                if *padding > 0 {
+                    let _ = writeln!(out, "     *   padding ({})", bits_label(*padding));
+                }
+                let _ = writeln!(out, "     */");
+                match int_type(*bytes) {
+                    Some(ty) => {
+                        let _ = writeln!(out, "    {} {};", ty, ident);
+                    }
+                    None => {
+                        let _ = writeln!(out, "    uint8_t {}[{}];", ident, bytes);
+                    }
+                }
+            }
+            Member::Variable { field, .. } => {
+                let _ = writeln!(
+                    out,
+                    "    /* Offset {}: {} (variable length) */",
+                    offset,
+                    comment(&field.name)
+                );
+                let _ = writeln!(out, "    uint8_t {}[];", field.ident);
+            }
+        }
+        offset += member.bytes().unwrap_or(0);
+    }
+
+    if !trailing.is_empty() {
+        let _ = writeln!(out, "    /* Followed by (variable offset):");
+        for member in trailing {
+            let fields: Vec<&Field> = match member {
+                Member::Bytes { field } | Member::Variable { field, .. } => vec![field],
+                Member::Bits { fields, .. } => fields.iter().map(|(field, _)| field).collect(),
+            };
+            for field in fields {
+                match member {
+                    Member::Variable { .. } => {
+                        let _ =
+                            writeln!(out, "     *   {} (variable length)", comment(&field.name));
+                    }
+                    _ => {
+                        let _ = writeln!(
+                            out,
+                            "     *   {} ({})",
+                            comment(&field.name),
+                            bits_label(field.bits)
+                        );
+                    }
+                }
+            }
+        }
+        let _ = writeln!(out, "     */");
+    }
+
+    let _ = writeln!(out, "}} {}_t;", name);
+    let _ = writeln!(out, "#pragma pack(pop)\n");
+
+    // Accessors for the members that need a byte swap or a shift
+    let mut accessors = Vec::new();
+    for member in fixed {
+        match member {
+            Member::Bytes { field } => {
+                let bytes = field.bits / 8;
+                if let Some(ty) = int_type(bytes).filter(|_| bytes > 1) {
+                    let macro_name = field.ident.to_uppercase();
+                    accessors.push(format!(
+                        "#define {p}_GET_{m}(p) {r}",
+                        p = prefix,
+                        m = macro_name,
+                        r = read(&prefix, &field.ident, bytes)
+                    ));
+                    accessors.push(format!(
+                        "#define {p}_SET_{m}(p, v) ((p)->{i} = {p}_SWAP{b}(({t})(v)))",
+                        p = prefix,
+                        m = macro_name,
+                        i = field.ident,
+                        b = field.bits,
+                        t = ty
+                    ));
+                }
+            }
+            Member::Bits {
+                ident,
+                bytes,
+                fields,
+                ..
+            } => {
+                let Some(ty) = int_type(*bytes) else {
+                    continue;
+                };
+                let value = read(&prefix, ident, *bytes);
+
+                for (field, shift) in fields {
+                    let macro_name = field.ident.to_uppercase();
+                    let field_mask = mask(field.bits);
+                    accessors.push(format!(
+                        "#define {p}_GET_{m}(p) (({vt})(({v} >> {s}) & {k}))",
+                        p = prefix,
+                        m = macro_name,
+                        vt = value_type(field.bits),
+                        v = value,
+                        s = shift,
+                        k = field_mask
+                    ));
+
+                    let updated = format!(
+                        "({t})(({v} & ~(({t}){k} << {s})) | ((({t})(v) & {k}) << {s}))",
+                        t = ty,
+                        v = value,
+                        k = field_mask,
+                        s = shift
+                    );
+                    let store = if *bytes == 1 {
+                        updated
+                    } else {
+                        format!("{}_SWAP{}({})", prefix, bytes * 8, updated)
+                    };
+                    accessors.push(format!(
+                        "#define {p}_SET_{m}(p, v) ((p)->{i} = {s})",
+                        p = prefix,
+                        m = macro_name,
+                        i = ident,
+                        s = store
+                    ));
+                }
+            }
+            Member::Variable { .. } => {}
+        }
+    }
+
+    if !accessors.is_empty() {
+        let _ = writeln!(out, "/* Field accessors (values in the host byte order) */");
+        for accessor in accessors {
+            let _ = writeln!(out, "{}", accessor);
+        }
+        let _ = writeln!(out);
+    }
+
+    let _ = writeln!(out, "#endif /* {}_H */", prefix);
+
+    Ok(out)
+}
@@ -0,0 +1,316 @@
+//! Wireshark Lua dissector generator.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use protoviz::descriptor::ProtoDescriptor;
+
+use super::{layout, length_field, unique_ident, CodegenOptions, Field, Member};
+
+/// Keywords, plus the names used by the dissector function
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while", "buffer",
+    "pinfo", "tree", "subtree", "offset", "f",
+];
+
+/// ProtoField type reading the given number of bytes as an integer, if any
+fn field_type(bytes: usize) -> Option<&'static str> {
+    match bytes {
+        1 => Some("uint8"),
+        2 => Some("uint16"),
+        3 => Some("uint24"),
+        4 => Some("uint32"),
+        5..=8 => Some("uint64"),
+        _ => None,
+    }
+}
+
+/// Wireshark names of the protocols described by the templates, which a dissector named after its
+/// protocol would clash with. Wireshark registers many more, so this is a best effort: other
+/// clashes are reported by Wireshark when it loads the dissector, which then has to be renamed.
+const BUILTIN: &[&str] = &[
+    "frame", "eth", "vlan", "arp", "ip", "ipv6", "icmp", "tcp", "udp", "dns", "mqtt", "mbrtu",
+    "can", "data",
+];
+
+/// Quote a string as a Lua literal
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_ascii_control() => {
+                let _ = write!(quoted, "\\{:03}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Read a member value, in the byte order of the frame
+fn read(bytes: usize, network_order: bool) -> String {
+    let method = match (bytes, network_order) {
+        (1..=4, true) => "uint()",
+        (1..=4, false) => "le_uint()",
+        (_, true) => "uint64():tonumber()",
+        (_, false) => "le_uint64():tonumber()",
+    };
+    format!("buffer(offset, {}):{}", bytes, method)
+}
+
+/// Generate a dissector with a field for each field of the descriptor
+pub fn generate(descriptor: &ProtoDescriptor, options: &CodegenOptions) -> Result<String, String> {
+    let mut name = unique_ident(&options.name, "frame", KEYWORDS, &mut HashSet::new());
+    // Best effort, see BUILTIN
+    if BUILTIN.contains(&name.as_str()) {
+        name.push_str("_custom");
+    }
+    let proto = format!("{}_proto", name.trim_end_matches('_'));
+    let description = match options.name.trim() {
+        "" => name.clone(),
+        description => description.to_string(),
+    };
+    let members = layout(descriptor, options, KEYWORDS)?;
+    let network_order = descriptor.elements.network_order;
+    let add = if network_order { "add" } else { "add_le" };
+
+    // Locals holding the lengths must not clash with the member names
+    let mut used: HashSet<String> = members
+        .iter()
+        .map(|member| member.ident().to_string())
+        .collect();
+    // Members whose value gives the length of a later field
+    let mut referenced = HashSet::new();
+    let mut lengths = Vec::new();
+    for (i, member) in members.iter().enumerate() {
+        let Member::Variable { field, .. } = member else {
+            continue;
+        };
+        let local = format!("{}_len", field.ident.trim_end_matches('_'));
+        let local = unique_ident(&local, "len", KEYWORDS, &mut used);
+        let expr = match length_field(&members, i) {
+            Some((member @ Member::Bytes { .. }, value, None))
+                if field_type(value.bits / 8).is_some() =>
+            {
+                referenced.insert(member.ident());
+                member.ident().to_string()
+            }
+            Some((member @ Member::Bits { bytes, .. }, value, Some(shift)))
+                if field_type(*bytes).is_some() =>
+            {
+                referenced.insert(member.ident());
+                let modulus = 1u128 << value.bits;
+                match shift {
+                    0 => format!("{} % {}", member.ident(), modulus),
+                    shift => format!(
+                        "math.floor({} / {}) % {}",
+                        member.ident(),
+                        1u128 << shift,
+                        modulus
+                    ),
+                }
+            }
+            _ => {
+                // Only the last variable field can take the rest of the buffer
+                if members[i + 1..]
+                    .iter()
+                    .any(|member| matches!(member, Member::Variable { .. }))
+                {
+                    return Err(format!(
+                        "the length of field '{}' names no previous integer field, which only the last variable field may do",
+                        field.name
+                    ));
+                }
+                // The rest of the buffer, leaving room for the fixed fields after it
+                let trailing: usize = members[i + 1..]
+                    .iter()
+                    .filter_map(|member| member.bytes())
+                    .sum();
+                match trailing {
+                    0 => "buffer:len() - offset".to_string(),
+                    trailing => format!("buffer:len() - offset - {}", trailing),
+                }
+            }
+        };
+        lengths.push((local, expr));
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "-- Generated by ProtoViz\n");
+    let _ = writeln!(
+        out,
+        "local {} = Proto({}, {})\n",
+        proto,
+        quote(&name),
+        quote(&description)
+    );
+
+    // Fields
+    let _ = writeln!(out, "local f = {}.fields", proto);
+    let declare = |out: &mut String, field: &Field, ty: Option<&str>, mask: Option<String>| {
+        let abbrev = quote(&format!("{}.{}", name, field.ident));
+        let _ = match (ty, mask) {
+            (Some(ty), Some(mask)) => writeln!(
+                out,
+                "f.{} = ProtoField.{}({}, {}, base.DEC, nil, {})",
+                field.ident,
+                ty,
+                abbrev,
+                quote(&field.name),
+                mask
+            ),
+            (Some(ty), None) => writeln!(
+                out,
+                "f.{} = ProtoField.{}({}, {}, base.DEC)",
+                field.ident,
+                ty,
+                abbrev,
+                quote(&field.name)
+            ),
+            (None, _) => writeln!(
+                out,
+                "f.{} = ProtoField.bytes({}, {})",
+                field.ident,
+                abbrev,
+                quote(&field.name)
+            ),
+        };
+    };
+    for member in &members {
+        match member {
+            Member::Bytes { field } => declare(&mut out, field, field_type(field.bits / 8), None),
+            Member::Bits {
+                ident,
+                bytes,
+                fields,
+                ..
+            } => match field_type(*bytes) {
+                Some(ty) => {
+                    for (field, shift) in fields {
+                        let mask = format!("0x{:X}", ((1u128 << field.bits) - 1) << shift);
+                        declare(&mut out, field, Some(ty), Some(mask));
+                    }
+                }
+                None => {
+                    // Too long for a bit mask, so the fields are shown together
+                    let names: Vec<&str> = fields
+                        .iter()
+                        .map(|(field, _)| field.name.as_str())
+                        .collect();
+                    let field = Field {
+                        name: names.join(", "),
+                        ident: ident.clone(),
+                        bits: bytes * 8,
+                    };
+                    declare(&mut out, &field, None, None);
+                }
+            },
+            Member::Variable { field, .. } => declare(&mut out, field, None, None),
+        }
+    }
+    let _ = writeln!(out);
+
+    // Dissector
+    let fixed_size: usize = members.iter().filter_map(|member| member.bytes()).sum();
+    let _ = writeln!(out, "function {}.dissector(buffer, pinfo, tree)", proto);
+    if fixed_size > 0 {
+        let _ = writeln!(out, "    if buffer:len() < {} then", fixed_size);
+        let _ = writeln!(out, "        return 0");
+        let _ = writeln!(out, "    end\n");
+    }
+    let _ = writeln!(out, "    pinfo.cols.protocol = {}.name", proto);
+    let _ = writeln!(out, "    local subtree = tree:add({}, buffer())", proto);
+    let _ = writeln!(out, "    local offset = 0\n");
+
+    let mut lengths = lengths.iter();
+    for member in &members {
+        match member {
+            Member::Bytes { field } => {
+                let bytes = field.bits / 8;
+                if referenced.contains(member.ident()) {
+                    let _ = writeln!(
+                        out,
+                        "    local {} = {}",
+                        field.ident,
+                        read(bytes, network_order)
+                    );
+                }
+                let method = if field_type(bytes).is_some() {
+                    add
+                } else {
+                    "add"
+                };
+                let _ = writeln!(
+                    out,
+                    "    subtree:{}(f.{}, buffer(offset, {}))",
+                    method, field.ident, bytes
+                );
+                let _ = writeln!(out, "    offset = offset + {}", bytes);
+            }
+            Member::Bits {
+                ident,
+                bytes,
+                fields,
+                ..
+            } => {
+                if referenced.contains(member.ident()) {
+                    let _ = writeln!(out, "    local {} = {}", ident, read(*bytes, network_order));
+                }
+                if field_type(*bytes).is_some() {
+                    for (field, _) in fields {
+                        let _ = writeln!(
+                            out,
+                            "    subtree:{}(f.{}, buffer(offset, {}))",
+                            add, field.ident, bytes
+                        );
+                    }
+                } else {
+                    let _ = writeln!(
+                        out,
+                        "    subtree:add(f.{}, buffer(offset, {}))",
+                        ident, bytes
+                    );
+                }
+                let _ = writeln!(out, "    offset = offset + {}", bytes);
+            }
+            Member::Variable { field, .. } => {
+                let Some((local, expr)) = lengths.next() else {
+                    continue;
+                };
+                let _ = writeln!(out, "    local {} = {}", local, expr);
+                let _ = writeln!(out, "    if {} > 0 then", local);
+                let _ = writeln!(
+                    out,
+                    "        subtree:add(f.{}, buffer(offset, {}))",
+                    field.ident, local
+                );
+                let _ = writeln!(out, "    end");
+                let _ = writeln!(out, "    offset = offset + {}", local);
+            }
+        }
+    }
+    let _ = writeln!(out, "\n    return offset");
+    let _ = writeln!(out, "end\n");
+
+    let _ = writeln!(
+        out,
+        "-- Offer the dissector in \"Decode As...\" for TCP and UDP ports"
+    );
+    let _ = writeln!(
+        out,
+        "DissectorTable.get(\"tcp.port\"):add_for_decode_as({})",
+        proto
+    );
+    let _ = writeln!(
+        out,
+        "DissectorTable.get(\"udp.port\"):add_for_decode_as({})",
+        proto
+    );
+
+    Ok(out)
+}
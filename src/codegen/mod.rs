@@ -0,0 +1,417 @@
+//! Source code generators for the fields of a descriptor.
+//!
+//! The fields are laid out in byte aligned members: fields that don't start or end on a byte
+//! boundary are packed together in a single member, with the shift of each field inside it.
+
+mod c;
+mod lua;
+mod rust;
+
+use std::collections::HashSet;
+
+use protoviz::descriptor::ProtoDescriptor;
+
+use crate::ident::{names_field, to_ident};
+use crate::kaitai::render_ksy;
+use crate::length::fixed_bits;
+
+/// Languages offered in the "Generate Code" menu
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    C,
+    Rust,
+    Lua,
+    Kaitai,
+}
+
+impl Language {
+    pub const ALL: [Language; 4] = [Language::C, Language::Rust, Language::Lua, Language::Kaitai];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::C => "C",
+            Language::Rust => "Rust",
+            Language::Lua => "Wireshark Lua",
+            Language::Kaitai => "Kaitai Struct",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Language::C => "h",
+            Language::Rust => "rs",
+            Language::Lua => "lua",
+            Language::Kaitai => "ksy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Language> {
+        Self::ALL
+            .into_iter()
+            .find(|language| language.name() == name)
+    }
+}
+
+pub struct CodegenOptions {
+    /// Name of the generated type
+    pub name: String,
+    /// Number of bits in a field length unit (1 if lengths are in bits, 8 if in bytes)
+    pub unit_bits: usize,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        Self {
+            name: "frame".to_string(),
+            unit_bits: 1,
+        }
+    }
+}
+
+/// A field of the descriptor, with the identifier used in the generated code
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    ident: String,
+    /// Length in bits
+    bits: usize,
+}
+
+/// A byte aligned member of the generated type
+#[derive(Debug)]
+enum Member {
+    /// Field starting and ending on a byte boundary
+    Bytes { field: Field },
+    /// Fields sharing a member, with their shift from the least significant bit of the member
+    /// value (read in the frame byte order)
+    Bits {
+        ident: String,
+        bytes: usize,
+        fields: Vec<(Field, usize)>,
+        /// Bits added to end the member on a byte boundary
+        padding: usize,
+    },
+    /// Variable length field, with its length as written in the descriptor
+    Variable { field: Field, length: String },
+}
+
+impl Member {
+    /// Size in bytes, or None for variable length members
+    fn bytes(&self) -> Option<usize> {
+        match self {
+            Member::Bytes { field } => Some(field.bits / 8),
+            Member::Bits { bytes, .. } => Some(*bytes),
+            Member::Variable { .. } => None,
+        }
+    }
+
+    /// Identifier of the member in the generated type
+    fn ident(&self) -> &str {
+        match self {
+            Member::Bytes { field } | Member::Variable { field, .. } => &field.ident,
+            Member::Bits { ident, .. } => ident,
+        }
+    }
+}
+
+/// Create a unique identifier, avoiding the keywords of the language
+fn unique_ident(
+    name: &str,
+    fallback: &str,
+    keywords: &[&str],
+    used: &mut HashSet<String>,
+) -> String {
+    let mut ident = to_ident(name);
+    if ident.is_empty() {
+        ident = fallback.to_string();
+    }
+    if ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    if keywords.contains(&ident.as_str()) {
+        ident.push('_');
+    }
+
+    let mut unique = ident.clone();
+    let mut suffix = 2;
+    while used.contains(&unique) {
+        unique = format!("{}_{}", ident, suffix);
+        suffix += 1;
+    }
+    used.insert(unique.clone());
+    unique
+}
+
+/// Pack fields (with their offset in bits) into a member, padding it to a byte boundary
+fn pack(
+    fields: Vec<(Field, usize)>,
+    network_order: bool,
+    keywords: &[&str],
+    used: &mut HashSet<String>,
+) -> Member {
+    let bits: usize = fields.iter().map(|(field, _)| field.bits).sum();
+    let padding = (8 - bits % 8) % 8;
+    let total = bits + padding;
+
+    let fields: Vec<(Field, usize)> = fields
+        .into_iter()
+        .map(|(field, offset)| {
+            // The first field takes the most significant bits in network order
+            let shift = if network_order {
+                total - offset - field.bits
+            } else {
+                offset
+            };
+            (field, shift)
+        })
+        .collect();
+
+    let names: Vec<&str> = fields
+        .iter()
+        .map(|(field, _)| field.ident.as_str())
+        .collect();
+    Member::Bits {
+        ident: unique_ident(&names.join("_"), "bits", keywords, used),
+        bytes: total / 8,
+        fields,
+        padding,
+    }
+}
+
+/// Split the fields into byte aligned members
+fn layout(
+    descriptor: &ProtoDescriptor,
+    options: &CodegenOptions,
+    keywords: &[&str],
+) -> Result<Vec<Member>, String> {
+    if descriptor.fields.is_empty() {
+        return Err("no fields provided".to_string());
+    }
+    if options.unit_bits == 0 {
+        return Err("unit size must be positive".to_string());
+    }
+
+    let mut used = HashSet::new();
+    let mut members = Vec::new();
+    // Fields of the member being packed, with their offset in bits
+    let mut packed: Vec<(Field, usize)> = Vec::new();
+    let mut packed_bits = 0;
+    let network_order = descriptor.elements.network_order;
+    let unit_bits = options.unit_bits;
+
+    // Field identifiers are reserved first, so the packed members get the suffixes
+    let mut fields = Vec::new();
+    for (i, field) in descriptor.fields.iter().enumerate() {
+        let fallback = format!("field_{}", i + 1);
+        let ident = unique_ident(&field.name, &fallback, keywords, &mut used);
+        let bits = match fixed_bits(&field.length, unit_bits) {
+            Some(0) => {
+                return Err(format!("field '{}' has no length", field.name));
+            }
+            Some(bits) => Ok(bits),
+            None => Err(field.length.to_string()),
+        };
+        fields.push((field.name.clone(), ident, bits));
+    }
+
+    for (name, ident, bits) in fields {
+        match bits {
+            Ok(bits) if packed.is_empty() && bits % 8 == 0 => {
+                members.push(Member::Bytes {
+                    field: Field { name, ident, bits },
+                });
+            }
+            Ok(bits) => {
+                packed.push((Field { name, ident, bits }, packed_bits));
+                packed_bits += bits;
+                if packed_bits % 8 == 0 {
+                    let fields = std::mem::take(&mut packed);
+                    members.push(pack(fields, network_order, keywords, &mut used));
+                    packed_bits = 0;
+                }
+            }
+            Err(length) => {
+                if !packed.is_empty() {
+                    let fields = std::mem::take(&mut packed);
+                    members.push(pack(fields, network_order, keywords, &mut used));
+                    packed_bits = 0;
+                }
+                members.push(Member::Variable {
+                    field: Field {
+                        name,
+                        ident,
+                        bits: 0,
+                    },
+                    length,
+                });
+            }
+        }
+    }
+    if !packed.is_empty() {
+        members.push(pack(packed, network_order, keywords, &mut used));
+    }
+
+    Ok(members)
+}
+
+/// Find the fixed field named by the length of the variable member at `index`, with the member
+/// holding it and its shift if it's packed
+fn length_field(members: &[Member], index: usize) -> Option<(&Member, &Field, Option<usize>)> {
+    let Member::Variable { length, .. } = &members[index] else {
+        return None;
+    };
+    let named = |field: &Field| names_field(length, &field.name);
+
+    members[..index]
+        .iter()
+        .rev()
+        .find_map(|member| match member {
+            Member::Bytes { field } => named(field).then_some((member, field, None)),
+            Member::Bits { fields, .. } => fields
+                .iter()
+                .rev()
+                .find(|(field, _)| named(field))
+                .map(|(field, shift)| (member, field, Some(*shift))),
+            Member::Variable { .. } => None,
+        })
+}
+
+/// Generate the source code of the descriptor fields
+pub fn generate(
+    descriptor: &ProtoDescriptor,
+    language: Language,
+    options: &CodegenOptions,
+) -> Result<String, String> {
+    match language {
+        Language::C => c::generate(descriptor, options),
+        Language::Rust => rust::generate(descriptor, options),
+        Language::Lua => lua::generate(descriptor, options),
+        Language::Kaitai => render_ksy(descriptor, &options.name, options.unit_bits),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use protoviz::descriptor::{FieldDescriptor, FieldLength};
+
+    use super::*;
+    use crate::templates::TEMPLATES;
+
+    /// Compare the code generated for a template with its snapshot in `snapshots`. Run the tests
+    /// with `UPDATE_SNAPSHOTS=1` to write the snapshots after an intended change.
+    fn check_snapshot(template: &str, language: Language) {
+        let template = TEMPLATES
+            .iter()
+            .find(|candidate| candidate.name == template)
+            .unwrap();
+        let options = CodegenOptions {
+            name: template.name.to_string(),
+            unit_bits: template.unit_bits,
+        };
+        let code = generate(&template.descriptor().unwrap(), language, &options).unwrap();
+
+        let file = format!("{}.{}", to_ident(template.name), language.extension());
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/codegen/snapshots")
+            .join(&file);
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(&path, &code).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("{}: {} (run with UPDATE_SNAPSHOTS=1)", file, e));
+        assert!(
+            code == expected,
+            "{} changed (run with UPDATE_SNAPSHOTS=1 to accept):\n{}",
+            file,
+            code
+        );
+    }
+
+    #[test]
+    fn packs_bit_fields() {
+        let ipv4 = TEMPLATES
+            .iter()
+            .find(|template| template.name == "IPv4")
+            .unwrap();
+        let options = CodegenOptions {
+            name: "ipv4".to_string(),
+            unit_bits: ipv4.unit_bits,
+        };
+        let members = layout(&ipv4.descriptor().unwrap(), &options, &[]).unwrap();
+        let sizes: Vec<_> = members.iter().map(Member::bytes).collect();
+        // Version and IHL share a byte, Flags and Fragment Offset two
+        assert_eq!(sizes[..3], [Some(1), Some(1), Some(2)]);
+        assert!(
+            matches!(&members[0], Member::Bits { fields, padding: 0, .. } if fields.len() == 2)
+        );
+        assert_eq!(members.last().and_then(Member::bytes), None);
+    }
+
+    #[test]
+    fn generates_c() {
+        check_snapshot("IPv4", Language::C);
+        check_snapshot("Modbus RTU", Language::C);
+    }
+
+    #[test]
+    fn generates_rust() {
+        check_snapshot("IPv4", Language::Rust);
+        check_snapshot("Modbus RTU", Language::Rust);
+    }
+
+    #[test]
+    fn generates_lua() {
+        check_snapshot("UDP", Language::Lua);
+        check_snapshot("Modbus RTU", Language::Lua);
+    }
+
+    #[test]
+    fn takes_the_rest_of_the_buffer_in_the_last_lua_field() {
+        let field = |name: &str, length: FieldLength| FieldDescriptor {
+            name: name.to_string(),
+            length,
+            wrap: false,
+            color: None,
+        };
+        let variable = |length: &str| FieldLength::Variable(length.to_string());
+        let options = CodegenOptions {
+            name: "packet".to_string(),
+            unit_bits: 8,
+        };
+
+        let descriptor = ProtoDescriptor {
+            fields: vec![
+                field("Length", FieldLength::Fixed(1)),
+                field("Payload", variable("Length")),
+                field("Data", variable("N")),
+                field("CRC", FieldLength::Fixed(2)),
+            ],
+            ..Default::default()
+        };
+        let code = generate(&descriptor, Language::Lua, &options).unwrap();
+        assert!(code.contains("local payload_len = length\n"), "{}", code);
+        assert!(
+            code.contains("local data_len = buffer:len() - offset - 2\n"),
+            "{}",
+            code
+        );
+
+        let ipv4 = TEMPLATES
+            .iter()
+            .find(|template| template.name == "IPv4")
+            .unwrap();
+        let options = CodegenOptions {
+            name: "ipv4".to_string(),
+            unit_bits: ipv4.unit_bits,
+        };
+        assert_eq!(
+            generate(&ipv4.descriptor().unwrap(), Language::Lua, &options)
+                .err()
+                .as_deref(),
+            Some("the length of field 'Options' names no previous integer field, which only the last variable field may do")
+        );
+    }
+}
@@ -0,0 +1,516 @@
+//! Rust struct and parser generator.
+//!
+//! Values are kept in the host byte order and converted by `parse` and `write`. Variable length
+//! fields become slices, sized from the field named by their length when there is one.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use protoviz::descriptor::ProtoDescriptor;
+
+use super::{layout, length_field, unique_ident, CodegenOptions, Member};
+
+/// Keywords, plus the names used by the generated functions
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+    "pub", "ref", "return", "self", "static", "struct", "super", "trait", "true", "try", "type",
+    "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "macro",
+    "override", "priv", "typeof", "unsized", "virtual", "yield", "data", "offset", "buf", "bytes",
+    "parse", "write", "size",
+];
+
+/// Integer type holding the given number of bytes, if any
+fn int_type(bytes: usize) -> Option<&'static str> {
+    match bytes {
+        1 => Some("u8"),
+        2 => Some("u16"),
+        4 => Some("u32"),
+        8 => Some("u64"),
+        16 => Some("u128"),
+        _ => None,
+    }
+}
+
+/// Smallest integer type holding the given number of bytes, with its size
+fn storage_type(bytes: usize) -> Option<(&'static str, usize)> {
+    [1, 2, 4, 8, 16]
+        .into_iter()
+        .find(|size| *size >= bytes)
+        .and_then(|size| int_type(size).map(|ty| (ty, size)))
+}
+
+/// Smallest integer type holding the given number of bits
+fn value_type(bits: usize) -> &'static str {
+    match bits {
+        0..=8 => "u8",
+        9..=16 => "u16",
+        17..=32 => "u32",
+        33..=64 => "u64",
+        _ => "u128",
+    }
+}
+
+/// Mask of the given number of bits, shifted left
+fn mask_at(bits: usize, shift: usize) -> String {
+    let mask: u128 = if bits >= 128 {
+        u128::MAX
+    } else {
+        (1 << bits) - 1
+    };
+    format!("0x{:X}", mask << shift)
+}
+
+fn mask(bits: usize) -> String {
+    mask_at(bits, 0)
+}
+
+fn bits_label(bits: usize) -> String {
+    if bits == 1 {
+        "1 bit".to_string()
+    } else {
+        format!("{} bits", bits)
+    }
+}
+
+/// Expression extracting a field from a packed member value
+fn extract(value: &str, shift: usize, bits: usize) -> String {
+    match shift {
+        0 => format!("{} & {}", value, mask(bits)),
+        shift => format!("({} >> {}) & {}", value, shift, mask(bits)),
+    }
+}
+
+/// Expression shifting a field value into its place in a packed member
+fn shifted(value: &str, shift: usize, bits: usize) -> String {
+    match shift {
+        0 => format!("({} & {})", value, mask(bits)),
+        shift => format!("(({} & {}) << {})", value, mask(bits), shift),
+    }
+}
+
+/// Convert a snake case identifier to camel case
+fn camel_case(ident: &str) -> String {
+    ident
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// How the length of a variable field is found when parsing
+enum SliceLength {
+    /// Expression using a previous field
+    Field { expr: String, name: String },
+    /// Parameter of `parse`
+    Parameter(String),
+    /// The rest of the data
+    Rest,
+}
+
+/// Generate a module-level struct with `parse` and `write` functions
+pub fn generate(descriptor: &ProtoDescriptor, options: &CodegenOptions) -> Result<String, String> {
+    let ident = unique_ident(&options.name, "frame", KEYWORDS, &mut HashSet::new());
+    let name = camel_case(ident.trim_start_matches('_'));
+    let name = if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("Frame{}", name)
+    } else {
+        name
+    };
+    let error = format!("{}Error", name);
+    let members = layout(descriptor, options, KEYWORDS)?;
+    let endian = if descriptor.elements.network_order {
+        "be"
+    } else {
+        "le"
+    };
+
+    let has_slices = members
+        .iter()
+        .any(|member| matches!(member, Member::Variable { .. }));
+    let lifetime = if has_slices { "<'a>" } else { "" };
+
+    // Parameters of `parse` must not clash with the member names
+    let mut used: HashSet<String> = members
+        .iter()
+        .map(|member| member.ident().to_string())
+        .collect();
+    let mut lengths = Vec::new();
+    for (i, member) in members.iter().enumerate() {
+        let Member::Variable { field, .. } = member else {
+            continue;
+        };
+        let length = match length_field(&members, i) {
+            Some((Member::Bytes { .. }, value, None)) if int_type(value.bits / 8).is_some() => {
+                SliceLength::Field {
+                    expr: format!("{} as usize", value.ident),
+                    name: value.ident.clone(),
+                }
+            }
+            Some((Member::Bits { ident, bytes, .. }, value, Some(shift)))
+                if storage_type(*bytes).is_some() =>
+            {
+                SliceLength::Field {
+                    expr: format!("({}) as usize", extract(ident, shift, value.bits)),
+                    name: value.ident.clone(),
+                }
+            }
+            _ if i + 1 == members.len() => SliceLength::Rest,
+            _ => {
+                let parameter = format!("{}_len", field.ident.trim_end_matches('_'));
+                SliceLength::Parameter(unique_ident(&parameter, "len", KEYWORDS, &mut used))
+            }
+        };
+        lengths.push(length);
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "// Generated by ProtoViz\n");
+
+    let _ = writeln!(out, "/// Error returned when a buffer is too short");
+    let _ = writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq)]");
+    let _ = writeln!(out, "pub struct {} {{", error);
+    let _ = writeln!(out, "    pub needed: usize,");
+    let _ = writeln!(out, "    pub available: usize,");
+    let _ = writeln!(out, "}}\n");
+
+    let _ = writeln!(out, "impl core::fmt::Display for {} {{", error);
+    let _ = writeln!(
+        out,
+        "    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {{"
+    );
+    let _ = writeln!(
+        out,
+        "        write!(f, \"buffer too short ({{}} bytes needed, {{}} available)\", self.needed, self.available)"
+    );
+    let _ = writeln!(out, "    }}");
+    let _ = writeln!(out, "}}\n");
+
+    // Struct
+    let _ = writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq)]");
+    let _ = writeln!(out, "pub struct {}{} {{", name, lifetime);
+    let mut slices = lengths.iter();
+    for member in &members {
+        match member {
+            Member::Bytes { field } => {
+                let bytes = field.bits / 8;
+                let _ = writeln!(out, "    /// {} ({})", field.name, bits_label(field.bits));
+                match int_type(bytes) {
+                    Some(ty) => {
+                        let _ = writeln!(out, "    pub {}: {},", field.ident, ty);
+                    }
+                    None => {
+                        let _ = writeln!(out, "    pub {}: [u8; {}],", field.ident, bytes);
+                    }
+                }
+            }
+            Member::Bits {
+                ident,
+                bytes,
+                fields,
+                padding,
+            } => {
+                let names: Vec<String> = fields
+                    .iter()
+                    .map(|(field, _)| format!("{} ({})", field.name, bits_label(field.bits)))
+                    .collect();
+                let _ = write!(out, "    /// {}", names.join(", "));
+                if *padding > 0 {
+                    let _ = write!(out, ", padding ({})", bits_label(*padding));
+                }
+                let _ = writeln!(out);
+                match storage_type(*bytes) {
+                    Some((ty, _)) => {
+                        let _ = writeln!(out, "    pub {}: {},", ident, ty);
+                    }
+                    None => {
+                        let _ = writeln!(out, "    pub {}: [u8; {}],", ident, bytes);
+                    }
+                }
+            }
+            Member::Variable { field, .. } => {
+                let source = match slices.next() {
+                    Some(SliceLength::Field { name, .. }) => format!("length from `{}`", name),
+                    Some(SliceLength::Parameter(parameter)) => {
+                        format!("length given by `{}`", parameter)
+                    }
+                    _ => "rest of the data".to_string(),
+                };
+                let _ = writeln!(out, "    /// {} ({})", field.name, source);
+                let _ = writeln!(out, "    pub {}: &'a [u8],", field.ident);
+            }
+        }
+    }
+    let _ = writeln!(out, "}}\n");
+
+    let fixed_size: usize = members.iter().filter_map(|member| member.bytes()).sum();
+
+    let _ = writeln!(out, "impl{} {}{} {{", lifetime, name, lifetime);
+    let _ = writeln!(out, "    /// Size of the fixed length fields, in bytes");
+    let _ = writeln!(out, "    pub const FIXED_SIZE: usize = {};\n", fixed_size);
+
+    // parse
+    let mut parameters = vec![format!(
+        "data: &{}[u8]",
+        if has_slices { "'a " } else { "" }
+    )];
+    for length in &lengths {
+        if let SliceLength::Parameter(parameter) = length {
+            parameters.push(format!("{}: usize", parameter));
+        }
+    }
+    let _ = writeln!(out, "    /// Parse the fields from the start of `data`");
+    let _ = writeln!(
+        out,
+        "    pub fn parse({}) -> Result<Self, {}> {{",
+        parameters.join(", "),
+        error
+    );
+    let _ = writeln!(out, "        let mut offset = 0;");
+    let mut slices = lengths.iter();
+    for member in &members {
+        match member {
+            Member::Bytes { field } => {
+                let bytes = field.bits / 8;
+                match int_type(bytes) {
+                    Some(ty) => {
+                        let _ = writeln!(
+                            out,
+                            "        let {} = {}::from_{}_bytes(Self::array(data, &mut offset)?);",
+                            field.ident, ty, endian
+                        );
+                    }
+                    None => {
+                        let _ = writeln!(
+                            out,
+                            "        let {}: [u8; {}] = Self::array(data, &mut offset)?;",
+                            field.ident, bytes
+                        );
+                    }
+                }
+            }
+            Member::Bits { ident, bytes, .. } => match storage_type(*bytes) {
+                Some((ty, size)) if size == *bytes => {
+                    let _ = writeln!(
+                        out,
+                        "        let {} = {}::from_{}_bytes(Self::array(data, &mut offset)?);",
+                        ident, ty, endian
+                    );
+                }
+                Some((ty, size)) => {
+                    let range = if descriptor.elements.network_order {
+                        format!("{}..", size - bytes)
+                    } else {
+                        format!("..{}", bytes)
+                    };
+                    let _ = writeln!(out, "        let {} = {{", ident);
+                    let _ = writeln!(out, "            let mut bytes = [0; {}];", size);
+                    let _ = writeln!(
+                        out,
+                        "            bytes[{}].copy_from_slice(&Self::array::<{}>(data, &mut offset)?);",
+                        range, bytes
+                    );
+                    let _ = writeln!(out, "            {}::from_{}_bytes(bytes)", ty, endian);
+                    let _ = writeln!(out, "        }};");
+                }
+                None => {
+                    let _ = writeln!(
+                        out,
+                        "        let {}: [u8; {}] = Self::array(data, &mut offset)?;",
+                        ident, bytes
+                    );
+                }
+            },
+            Member::Variable { field, .. } => {
+                let length = match slices.next() {
+                    Some(SliceLength::Field { expr, .. }) => expr.clone(),
+                    Some(SliceLength::Parameter(parameter)) => parameter.clone(),
+                    _ => "data.len() - offset".to_string(),
+                };
+                let _ = writeln!(
+                    out,
+                    "        let {} = Self::slice(data, {}, &mut offset)?;",
+                    field.ident, length
+                );
+            }
+        }
+    }
+    let _ = writeln!(out, "        Ok(Self {{");
+    for member in &members {
+        let _ = writeln!(out, "            {},", member.ident());
+    }
+    let _ = writeln!(out, "        }})");
+    let _ = writeln!(out, "    }}\n");
+
+    // write
+    let _ = writeln!(
+        out,
+        "    /// Write the fields to the start of `buf`, returning the number of bytes written"
+    );
+    let _ = writeln!(
+        out,
+        "    pub fn write(&self, buf: &mut [u8]) -> Result<usize, {}> {{",
+        error
+    );
+    let _ = writeln!(out, "        let mut offset = 0;");
+    for member in &members {
+        let bytes = match member {
+            Member::Bytes { field } => {
+                if int_type(field.bits / 8).is_some() {
+                    format!("&self.{}.to_{}_bytes()", field.ident, endian)
+                } else {
+                    format!("&{{ self.{} }}", field.ident)
+                }
+            }
+            Member::Bits { ident, bytes, .. } => match storage_type(*bytes) {
+                Some((_, size)) if size == *bytes => {
+                    format!("&self.{}.to_{}_bytes()", ident, endian)
+                }
+                Some((_, size)) => {
+                    let range = if descriptor.elements.network_order {
+                        format!("{}..", size - bytes)
+                    } else {
+                        format!("..{}", bytes)
+                    };
+                    format!("&self.{}.to_{}_bytes()[{}]", ident, endian, range)
+                }
+                None => format!("&{{ self.{} }}", ident),
+            },
+            Member::Variable { field, .. } => format!("self.{}", field.ident),
+        };
+        let _ = writeln!(out, "        Self::put(buf, &mut offset, {})?;", bytes);
+    }
+    let _ = writeln!(out, "        Ok(offset)");
+    let _ = writeln!(out, "    }}\n");
+
+    // size
+    let _ = writeln!(out, "    /// Size of the encoded fields, in bytes");
+    let _ = writeln!(out, "    pub fn size(&self) -> usize {{");
+    let _ = write!(out, "        Self::FIXED_SIZE");
+    for member in &members {
+        if let Member::Variable { field, .. } = member {
+            let _ = write!(out, " + self.{}.len()", field.ident);
+        }
+    }
+    let _ = writeln!(out, "\n    }}");
+
+    // Accessors of the packed fields
+    for member in &members {
+        let Member::Bits {
+            ident,
+            bytes,
+            fields,
+            ..
+        } = member
+        else {
+            continue;
+        };
+        let Some((ty, _)) = storage_type(*bytes) else {
+            continue;
+        };
+
+        for (field, shift) in fields {
+            let value = value_type(field.bits);
+            let member = format!("self.{}", ident);
+            let converted = if value == ty {
+                "value".to_string()
+            } else {
+                format!("{}::from(value)", ty)
+            };
+            let _ = writeln!(out);
+            let _ = writeln!(out, "    /// {} ({})", field.name, bits_label(field.bits));
+            let _ = writeln!(out, "    pub fn {}(&self) -> {} {{", field.ident, value);
+            if value == ty {
+                let _ = writeln!(out, "        {}", extract(&member, *shift, field.bits));
+            } else {
+                let _ = writeln!(
+                    out,
+                    "        ({}) as {}",
+                    extract(&member, *shift, field.bits),
+                    value
+                );
+            }
+            let _ = writeln!(out, "    }}\n");
+            let _ = writeln!(
+                out,
+                "    /// Set {} ({})",
+                field.name,
+                bits_label(field.bits)
+            );
+            let _ = writeln!(
+                out,
+                "    pub fn set_{}(&mut self, value: {}) {{",
+                field.ident, value
+            );
+            let _ = writeln!(
+                out,
+                "        {m} = ({m} & !{k}) | {v};",
+                m = member,
+                k = mask_at(field.bits, *shift),
+                v = shifted(&converted, *shift, field.bits)
+            );
+            let _ = writeln!(out, "    }}");
+        }
+    }
+
+    // Helpers
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "    fn slice<'b>(data: &'b [u8], len: usize, offset: &mut usize) -> Result<&'b [u8], {}> {{",
+        error
+    );
+    let _ = writeln!(out, "        let end = offset.saturating_add(len);");
+    let _ = writeln!(out, "        if end > data.len() {{");
+    let _ = writeln!(
+        out,
+        "            return Err({} {{ needed: end, available: data.len() }});",
+        error
+    );
+    let _ = writeln!(out, "        }}");
+    let _ = writeln!(out, "        let slice = &data[*offset..end];");
+    let _ = writeln!(out, "        *offset = end;");
+    let _ = writeln!(out, "        Ok(slice)");
+    let _ = writeln!(out, "    }}\n");
+
+    if fixed_size > 0 {
+        let _ = writeln!(
+            out,
+            "    fn array<const N: usize>(data: &[u8], offset: &mut usize) -> Result<[u8; N], {}> {{",
+            error
+        );
+        let _ = writeln!(out, "        let mut bytes = [0; N];");
+        let _ = writeln!(
+            out,
+            "        bytes.copy_from_slice(Self::slice(data, N, offset)?);"
+        );
+        let _ = writeln!(out, "        Ok(bytes)");
+        let _ = writeln!(out, "    }}\n");
+    }
+
+    let _ = writeln!(
+        out,
+        "    fn put(buf: &mut [u8], offset: &mut usize, bytes: &[u8]) -> Result<(), {}> {{",
+        error
+    );
+    let _ = writeln!(out, "        let end = offset.saturating_add(bytes.len());");
+    let _ = writeln!(out, "        if end > buf.len() {{");
+    let _ = writeln!(
+        out,
+        "            return Err({} {{ needed: end, available: buf.len() }});",
+        error
+    );
+    let _ = writeln!(out, "        }}");
+    let _ = writeln!(out, "        buf[*offset..end].copy_from_slice(bytes);");
+    let _ = writeln!(out, "        *offset = end;");
+    let _ = writeln!(out, "        Ok(())");
+    let _ = writeln!(out, "    }}");
+    let _ = writeln!(out, "}}");
+
+    Ok(out)
+}
@@ -0,0 +1,79 @@
+/* Generated by ProtoViz */
+
+#ifndef IPV4_H
+#define IPV4_H
+
+#include <stdint.h>
+
+/* Convert between the frame byte order (big endian) and the host byte order */
+#if defined(__BYTE_ORDER__) && __BYTE_ORDER__ == __ORDER_BIG_ENDIAN__
+#define IPV4_SWAP16(x) ((uint16_t)(x))
+#define IPV4_SWAP32(x) ((uint32_t)(x))
+#else /* little endian host */
+#define IPV4_SWAP16(x) ((uint16_t)((((x) & 0xFFU) << 8) | (((x) >> 8) & 0xFFU)))
+#define IPV4_SWAP32(x) ((uint32_t)((((x) & 0xFFU) << 24) | (((x) & 0xFF00U) << 8) | (((x) >> 8) & 0xFF00U) | (((x) >> 24) & 0xFFU)))
+#endif
+
+#if defined(__GNUC__) || defined(__clang__)
+#define IPV4_PACKED __attribute__((packed))
+#else
+#define IPV4_PACKED
+#endif
+
+#pragma pack(push, 1)
+typedef struct IPV4_PACKED ipv4 {
+    /* Offset 0:
+     *   Version (4 bits, shift 4)
+     *   IHL (4 bits, shift 0)
+     */
+    uint8_t version_ihl;
+    /* Offset 1: TOS */
+    uint8_t tos;
+    /* Offset 2: Total Length */
+    uint16_t total_length;
+    /* Offset 4: Identification */
+    uint16_t identification;
+    /* Offset 6:
+     *   Flags (3 bits, shift 13)
+     *   Fragment Offset (13 bits, shift 0)
+     */
+    uint16_t flags_fragment_offset;
+    /* Offset 8: Time To Live */
+    uint8_t time_to_live;
+    /* Offset 9: Protocol */
+    uint8_t protocol;
+    /* Offset 10: Header Checksum */
+    uint16_t header_checksum;
+    /* Offset 12: Source Address */
+    uint32_t source_address;
+    /* Offset 16: Destination Address */
+    uint32_t destination_address;
+    /* Offset 20: Options (variable length) */
+    uint8_t options[];
+    /* Followed by (variable offset):
+     *   Data (variable length)
+     */
+} ipv4_t;
+#pragma pack(pop)
+
+/* Field accessors (values in the host byte order) */
+#define IPV4_GET_VERSION(p) ((uint8_t)(((p)->version_ihl >> 4) & 0xFU))
+#define IPV4_SET_VERSION(p, v) ((p)->version_ihl = (uint8_t)(((p)->version_ihl & ~((uint8_t)0xFU << 4)) | (((uint8_t)(v) & 0xFU) << 4)))
+#define IPV4_GET_IHL(p) ((uint8_t)(((p)->version_ihl >> 0) & 0xFU))
+#define IPV4_SET_IHL(p, v) ((p)->version_ihl = (uint8_t)(((p)->version_ihl & ~((uint8_t)0xFU << 0)) | (((uint8_t)(v) & 0xFU) << 0)))
+#define IPV4_GET_TOTAL_LENGTH(p) IPV4_SWAP16((p)->total_length)
+#define IPV4_SET_TOTAL_LENGTH(p, v) ((p)->total_length = IPV4_SWAP16((uint16_t)(v)))
+#define IPV4_GET_IDENTIFICATION(p) IPV4_SWAP16((p)->identification)
+#define IPV4_SET_IDENTIFICATION(p, v) ((p)->identification = IPV4_SWAP16((uint16_t)(v)))
+#define IPV4_GET_FLAGS(p) ((uint8_t)((IPV4_SWAP16((p)->flags_fragment_offset) >> 13) & 0x7U))
+#define IPV4_SET_FLAGS(p, v) ((p)->flags_fragment_offset = IPV4_SWAP16((uint16_t)((IPV4_SWAP16((p)->flags_fragment_offset) & ~((uint16_t)0x7U << 13)) | (((uint16_t)(v) & 0x7U) << 13))))
+#define IPV4_GET_FRAGMENT_OFFSET(p) ((uint16_t)((IPV4_SWAP16((p)->flags_fragment_offset) >> 0) & 0x1FFFU))
+#define IPV4_SET_FRAGMENT_OFFSET(p, v) ((p)->flags_fragment_offset = IPV4_SWAP16((uint16_t)((IPV4_SWAP16((p)->flags_fragment_offset) & ~((uint16_t)0x1FFFU << 0)) | (((uint16_t)(v) & 0x1FFFU) << 0))))
+#define IPV4_GET_HEADER_CHECKSUM(p) IPV4_SWAP16((p)->header_checksum)
+#define IPV4_SET_HEADER_CHECKSUM(p, v) ((p)->header_checksum = IPV4_SWAP16((uint16_t)(v)))
+#define IPV4_GET_SOURCE_ADDRESS(p) IPV4_SWAP32((p)->source_address)
+#define IPV4_SET_SOURCE_ADDRESS(p, v) ((p)->source_address = IPV4_SWAP32((uint32_t)(v)))
+#define IPV4_GET_DESTINATION_ADDRESS(p) IPV4_SWAP32((p)->destination_address)
+#define IPV4_SET_DESTINATION_ADDRESS(p, v) ((p)->destination_address = IPV4_SWAP32((uint32_t)(v)))
+
+#endif /* IPV4_H */
@@ -0,0 +1,167 @@
+// Generated by ProtoViz
+
+/// Error returned when a buffer is too short
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Error {
+    pub needed: usize,
+    pub available: usize,
+}
+
+impl core::fmt::Display for Ipv4Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "buffer too short ({} bytes needed, {} available)", self.needed, self.available)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4<'a> {
+    /// Version (4 bits), IHL (4 bits)
+    pub version_ihl: u8,
+    /// TOS (8 bits)
+    pub tos: u8,
+    /// Total Length (16 bits)
+    pub total_length: u16,
+    /// Identification (16 bits)
+    pub identification: u16,
+    /// Flags (3 bits), Fragment Offset (13 bits)
+    pub flags_fragment_offset: u16,
+    /// Time To Live (8 bits)
+    pub time_to_live: u8,
+    /// Protocol (8 bits)
+    pub protocol: u8,
+    /// Header Checksum (16 bits)
+    pub header_checksum: u16,
+    /// Source Address (32 bits)
+    pub source_address: u32,
+    /// Destination Address (32 bits)
+    pub destination_address: u32,
+    /// Options (length given by `options_len`)
+    pub options: &'a [u8],
+    /// Data (rest of the data)
+    pub data_: &'a [u8],
+}
+
+impl<'a> Ipv4<'a> {
+    /// Size of the fixed length fields, in bytes
+    pub const FIXED_SIZE: usize = 20;
+
+    /// Parse the fields from the start of `data`
+    pub fn parse(data: &'a [u8], options_len: usize) -> Result<Self, Ipv4Error> {
+        let mut offset = 0;
+        let version_ihl = u8::from_be_bytes(Self::array(data, &mut offset)?);
+        let tos = u8::from_be_bytes(Self::array(data, &mut offset)?);
+        let total_length = u16::from_be_bytes(Self::array(data, &mut offset)?);
+        let identification = u16::from_be_bytes(Self::array(data, &mut offset)?);
+        let flags_fragment_offset = u16::from_be_bytes(Self::array(data, &mut offset)?);
+        let time_to_live = u8::from_be_bytes(Self::array(data, &mut offset)?);
+        let protocol = u8::from_be_bytes(Self::array(data, &mut offset)?);
+        let header_checksum = u16::from_be_bytes(Self::array(data, &mut offset)?);
+        let source_address = u32::from_be_bytes(Self::array(data, &mut offset)?);
+        let destination_address = u32::from_be_bytes(Self::array(data, &mut offset)?);
+        let options = Self::slice(data, options_len, &mut offset)?;
+        let data_ = Self::slice(data, data.len() - offset, &mut offset)?;
+        Ok(Self {
+            version_ihl,
+            tos,
+            total_length,
+            identification,
+            flags_fragment_offset,
+            time_to_live,
+            protocol,
+            header_checksum,
+            source_address,
+            destination_address,
+            options,
+            data_,
+        })
+    }
+
+    /// Write the fields to the start of `buf`, returning the number of bytes written
+    pub fn write(&self, buf: &mut [u8]) -> Result<usize, Ipv4Error> {
+        let mut offset = 0;
+        Self::put(buf, &mut offset, &self.version_ihl.to_be_bytes())?;
+        Self::put(buf, &mut offset, &self.tos.to_be_bytes())?;
+        Self::put(buf, &mut offset, &self.total_length.to_be_bytes())?;
+        Self::put(buf, &mut offset, &self.identification.to_be_bytes())?;
+        Self::put(buf, &mut offset, &self.flags_fragment_offset.to_be_bytes())?;
+        Self::put(buf, &mut offset, &self.time_to_live.to_be_bytes())?;
+        Self::put(buf, &mut offset, &self.protocol.to_be_bytes())?;
+        Self::put(buf, &mut offset, &self.header_checksum.to_be_bytes())?;
+        Self::put(buf, &mut offset, &self.source_address.to_be_bytes())?;
+        Self::put(buf, &mut offset, &self.destination_address.to_be_bytes())?;
+        Self::put(buf, &mut offset, self.options)?;
+        Self::put(buf, &mut offset, self.data_)?;
+        Ok(offset)
+    }
+
+    /// Size of the encoded fields, in bytes
+    pub fn size(&self) -> usize {
+        Self::FIXED_SIZE + self.options.len() + self.data_.len()
+    }
+
+    /// Version (4 bits)
+    pub fn version(&self) -> u8 {
+        (self.version_ihl >> 4) & 0xF
+    }
+
+    /// Set Version (4 bits)
+    pub fn set_version(&mut self, value: u8) {
+        self.version_ihl = (self.version_ihl & !0xF0) | ((value & 0xF) << 4);
+    }
+
+    /// IHL (4 bits)
+    pub fn ihl(&self) -> u8 {
+        self.version_ihl & 0xF
+    }
+
+    /// Set IHL (4 bits)
+    pub fn set_ihl(&mut self, value: u8) {
+        self.version_ihl = (self.version_ihl & !0xF) | (value & 0xF);
+    }
+
+    /// Flags (3 bits)
+    pub fn flags(&self) -> u8 {
+        ((self.flags_fragment_offset >> 13) & 0x7) as u8
+    }
+
+    /// Set Flags (3 bits)
+    pub fn set_flags(&mut self, value: u8) {
+        self.flags_fragment_offset = (self.flags_fragment_offset & !0xE000) | ((u16::from(value) & 0x7) << 13);
+    }
+
+    /// Fragment Offset (13 bits)
+    pub fn fragment_offset(&self) -> u16 {
+        self.flags_fragment_offset & 0x1FFF
+    }
+
+    /// Set Fragment Offset (13 bits)
+    pub fn set_fragment_offset(&mut self, value: u16) {
+        self.flags_fragment_offset = (self.flags_fragment_offset & !0x1FFF) | (value & 0x1FFF);
+    }
+
+    fn slice<'b>(data: &'b [u8], len: usize, offset: &mut usize) -> Result<&'b [u8], Ipv4Error> {
+        let end = offset.saturating_add(len);
+        if end > data.len() {
+            return Err(Ipv4Error { needed: end, available: data.len() });
+        }
+        let slice = &data[*offset..end];
+        *offset = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(data: &[u8], offset: &mut usize) -> Result<[u8; N], Ipv4Error> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(Self::slice(data, N, offset)?);
+        Ok(bytes)
+    }
+
+    fn put(buf: &mut [u8], offset: &mut usize, bytes: &[u8]) -> Result<(), Ipv4Error> {
+        let end = offset.saturating_add(bytes.len());
+        if end > buf.len() {
+            return Err(Ipv4Error { needed: end, available: buf.len() });
+        }
+        buf[*offset..end].copy_from_slice(bytes);
+        *offset = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+/* Generated by ProtoViz */
+
+#ifndef MODBUS_RTU_H
+#define MODBUS_RTU_H
+
+#include <stdint.h>
+
+#if defined(__GNUC__) || defined(__clang__)
+#define MODBUS_RTU_PACKED __attribute__((packed))
+#else
+#define MODBUS_RTU_PACKED
+#endif
+
+#pragma pack(push, 1)
+typedef struct MODBUS_RTU_PACKED modbus_rtu {
+    /* Offset 0: Address */
+    uint8_t address;
+    /* Offset 1: Function Code */
+    uint8_t function_code;
+    /* Offset 2: Data (variable length) */
+    uint8_t data[];
+    /* Followed by (variable offset):
+     *   CRC (16 bits)
+     */
+} modbus_rtu_t;
+#pragma pack(pop)
+
+#endif /* MODBUS_RTU_H */
@@ -0,0 +1,37 @@
+-- Generated by ProtoViz
+
+local modbus_rtu_proto = Proto("modbus_rtu", "Modbus RTU")
+
+local f = modbus_rtu_proto.fields
+f.address = ProtoField.uint8("modbus_rtu.address", "Address", base.DEC)
+f.function_code = ProtoField.uint8("modbus_rtu.function_code", "Function Code", base.DEC)
+f.data = ProtoField.bytes("modbus_rtu.data", "Data")
+f.crc = ProtoField.uint16("modbus_rtu.crc", "CRC", base.DEC)
+
+function modbus_rtu_proto.dissector(buffer, pinfo, tree)
+    if buffer:len() < 4 then
+        return 0
+    end
+
+    pinfo.cols.protocol = modbus_rtu_proto.name
+    local subtree = tree:add(modbus_rtu_proto, buffer())
+    local offset = 0
+
+    subtree:add(f.address, buffer(offset, 1))
+    offset = offset + 1
+    subtree:add(f.function_code, buffer(offset, 1))
+    offset = offset + 1
+    local data_len = buffer:len() - offset - 2
+    if data_len > 0 then
+        subtree:add(f.data, buffer(offset, data_len))
+    end
+    offset = offset + data_len
+    subtree:add(f.crc, buffer(offset, 2))
+    offset = offset + 2
+
+    return offset
+end
+
+-- Offer the dissector in "Decode As..." for TCP and UDP ports
+DissectorTable.get("tcp.port"):add_for_decode_as(modbus_rtu_proto)
+DissectorTable.get("udp.port"):add_for_decode_as(modbus_rtu_proto)
@@ -0,0 +1,87 @@
+// Generated by ProtoViz
+
+/// Error returned when a buffer is too short
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModbusRtuError {
+    pub needed: usize,
+    pub available: usize,
+}
+
+impl core::fmt::Display for ModbusRtuError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "buffer too short ({} bytes needed, {} available)", self.needed, self.available)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModbusRtu<'a> {
+    /// Address (8 bits)
+    pub address: u8,
+    /// Function Code (8 bits)
+    pub function_code: u8,
+    /// Data (length given by `data_len`)
+    pub data_: &'a [u8],
+    /// CRC (16 bits)
+    pub crc: u16,
+}
+
+impl<'a> ModbusRtu<'a> {
+    /// Size of the fixed length fields, in bytes
+    pub const FIXED_SIZE: usize = 4;
+
+    /// Parse the fields from the start of `data`
+    pub fn parse(data: &'a [u8], data_len: usize) -> Result<Self, ModbusRtuError> {
+        let mut offset = 0;
+        let address = u8::from_be_bytes(Self::array(data, &mut offset)?);
+        let function_code = u8::from_be_bytes(Self::array(data, &mut offset)?);
+        let data_ = Self::slice(data, data_len, &mut offset)?;
+        let crc = u16::from_be_bytes(Self::array(data, &mut offset)?);
+        Ok(Self {
+            address,
+            function_code,
+            data_,
+            crc,
+        })
+    }
+
+    /// Write the fields to the start of `buf`, returning the number of bytes written
+    pub fn write(&self, buf: &mut [u8]) -> Result<usize, ModbusRtuError> {
+        let mut offset = 0;
+        Self::put(buf, &mut offset, &self.address.to_be_bytes())?;
+        Self::put(buf, &mut offset, &self.function_code.to_be_bytes())?;
+        Self::put(buf, &mut offset, self.data_)?;
+        Self::put(buf, &mut offset, &self.crc.to_be_bytes())?;
+        Ok(offset)
+    }
+
+    /// Size of the encoded fields, in bytes
+    pub fn size(&self) -> usize {
+        Self::FIXED_SIZE + self.data_.len()
+    }
+
+    fn slice<'b>(data: &'b [u8], len: usize, offset: &mut usize) -> Result<&'b [u8], ModbusRtuError> {
+        let end = offset.saturating_add(len);
+        if end > data.len() {
+            return Err(ModbusRtuError { needed: end, available: data.len() });
+        }
+        let slice = &data[*offset..end];
+        *offset = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(data: &[u8], offset: &mut usize) -> Result<[u8; N], ModbusRtuError> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(Self::slice(data, N, offset)?);
+        Ok(bytes)
+    }
+
+    fn put(buf: &mut [u8], offset: &mut usize, bytes: &[u8]) -> Result<(), ModbusRtuError> {
+        let end = offset.saturating_add(bytes.len());
+        if end > buf.len() {
+            return Err(ModbusRtuError { needed: end, available: buf.len() });
+        }
+        buf[*offset..end].copy_from_slice(bytes);
+        *offset = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+-- Generated by ProtoViz
+
+local udp_custom_proto = Proto("udp_custom", "UDP")
+
+local f = udp_custom_proto.fields
+f.source_port = ProtoField.uint16("udp_custom.source_port", "Source Port", base.DEC)
+f.destination_port = ProtoField.uint16("udp_custom.destination_port", "Destination Port", base.DEC)
+f.length = ProtoField.uint16("udp_custom.length", "Length", base.DEC)
+f.checksum = ProtoField.uint16("udp_custom.checksum", "Checksum", base.DEC)
+f.data = ProtoField.bytes("udp_custom.data", "Data")
+
+function udp_custom_proto.dissector(buffer, pinfo, tree)
+    if buffer:len() < 8 then
+        return 0
+    end
+
+    pinfo.cols.protocol = udp_custom_proto.name
+    local subtree = tree:add(udp_custom_proto, buffer())
+    local offset = 0
+
+    subtree:add(f.source_port, buffer(offset, 2))
+    offset = offset + 2
+    subtree:add(f.destination_port, buffer(offset, 2))
+    offset = offset + 2
+    subtree:add(f.length, buffer(offset, 2))
+    offset = offset + 2
+    subtree:add(f.checksum, buffer(offset, 2))
+    offset = offset + 2
+    local data_len = buffer:len() - offset
+    if data_len > 0 then
+        subtree:add(f.data, buffer(offset, data_len))
+    end
+    offset = offset + data_len
+
+    return offset
+end
+
+-- Offer the dissector in "Decode As..." for TCP and UDP ports
+DissectorTable.get("tcp.port"):add_for_decode_as(udp_custom_proto)
+DissectorTable.get("udp.port"):add_for_decode_as(udp_custom_proto)
@@ -0,0 +1,214 @@
+//! Diagnostics.
+//!
+//! Problems rendering the diagram, reading files and exporting are kept as values and listed in a
+//! panel below the header instead of blocking dialogs. A render problem is linked to the field
+//! causing it, which is highlighted in the field list, and goes away with the next successful
+//! render. The others stay until they are dismissed.
+
+use protoviz::descriptor::ProtoDescriptor;
+use protoviz::errors::Error;
+use tracing::error;
+
+use crate::fields::{create_field_descriptors, with_fields, FieldInput};
+use crate::group::{render_groups, FieldPath};
+use crate::length::render_svg;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// The diagram can't be drawn from the fields
+    Render,
+    /// A file, a template or pasted text can't be read
+    Parse,
+    /// A file can't be made or downloaded, or text copied
+    Export,
+    /// An edit can't be applied
+    Edit,
+}
+
+impl DiagnosticKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiagnosticKind::Render => "Render",
+            DiagnosticKind::Parse => "Parse",
+            DiagnosticKind::Export => "Export",
+            DiagnosticKind::Edit => "Edit",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    /// Path of the field causing the problem
+    pub field: Option<FieldPath>,
+}
+
+impl Diagnostic {
+    pub fn new(kind: DiagnosticKind, message: impl Into<String>) -> Self {
+        Diagnostic {
+            kind,
+            message: message.into(),
+            field: None,
+        }
+    }
+}
+
+/// Problems shown in the diagnostics panel, oldest first
+#[derive(Debug, Default)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    /// Show a problem, in place of an identical one
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        error!("{} error: {}", diagnostic.kind.name(), diagnostic.message);
+        self.0
+            .retain(|shown| shown.kind != diagnostic.kind || shown.message != diagnostic.message);
+        self.0.push(diagnostic);
+    }
+
+    /// Remove the problems of a kind, once they are fixed
+    pub fn clear(&mut self, kind: DiagnosticKind) {
+        self.0.retain(|shown| shown.kind != kind);
+    }
+
+    pub fn dismiss(&mut self, index: usize) {
+        if index < self.0.len() {
+            self.0.remove(index);
+        }
+    }
+
+    pub fn list(&self) -> &[Diagnostic] {
+        &self.0
+    }
+
+    /// The problem caused by the field at the path, if any
+    pub fn at_field(&self, path: &[usize]) -> Option<&Diagnostic> {
+        self.0
+            .iter()
+            .find(|shown| shown.field.as_deref() == Some(path))
+    }
+}
+
+/// Message of a protoviz error
+pub fn error_message(e: &Error) -> String {
+    match e {
+        Error::FormatError(message) => message.clone(),
+        Error::TeraError(e) => format!("template error: {}", e),
+        Error::Unknown => "unknown error".to_string(),
+    }
+}
+
+/// Problem rendering the fields, linked to the first field that can't be drawn
+pub fn render_diagnostic(
+    descriptor: &ProtoDescriptor,
+    fields: &[FieldInput],
+    unit_bits: usize,
+    message: String,
+) -> Diagnostic {
+    Diagnostic {
+        kind: DiagnosticKind::Render,
+        message,
+        field: failing_field(descriptor, fields, unit_bits),
+    }
+}
+
+/// Path of the first field that can't be drawn after the ones before it, or None if the problem
+/// isn't in the fields
+fn failing_field(
+    descriptor: &ProtoDescriptor,
+    fields: &[FieldInput],
+    unit_bits: usize,
+) -> Option<FieldPath> {
+    let renders = |fields: &[FieldInput]| {
+        render_svg(
+            &with_fields(descriptor, create_field_descriptors(fields)),
+            unit_bits,
+        )
+        .is_ok()
+    };
+
+    // With a style protoviz rejects, no field can be drawn
+    let sample = FieldInput {
+        name: "Field".to_string(),
+        length: "1".to_string(),
+        ..Default::default()
+    };
+    if !renders(&[sample]) {
+        return None;
+    }
+
+    // Binary search for the shortest prefix that can't be drawn: the fields up to `low` can be
+    // drawn (or there are none), the ones up to `high` can't
+    if !renders(fields) {
+        let (mut low, mut high) = (0, fields.len());
+        while high - low > 1 {
+            let middle = (low + high) / 2;
+            if renders(&fields[..middle]) {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        return Some(vec![high - 1]);
+    }
+    fields.iter().enumerate().find_map(|(i, field)| {
+        if !field.expanded || field.children.is_empty() {
+            return None;
+        }
+        render_groups(descriptor, &field.children, unit_bits).err()?;
+        let mut path = vec![i];
+        path.extend(failing_field(descriptor, &field.children, unit_bits).unwrap_or_default());
+        Some(path)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, length: &str) -> FieldInput {
+        FieldInput {
+            name: name.to_string(),
+            length: length.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn failing(fields: &[FieldInput]) -> Option<FieldPath> {
+        render_diagnostic(&ProtoDescriptor::default(), fields, 8, String::new()).field
+    }
+
+    #[test]
+    fn links_the_first_failing_field() {
+        let mut fields: Vec<FieldInput> = (0..40)
+            .map(|i| field(&format!("Field {}", i), "1"))
+            .collect();
+        assert_eq!(failing(&fields), None);
+
+        fields[27].length = "0".to_string();
+        fields[33].length = "0".to_string();
+        assert_eq!(failing(&fields), Some(vec![27]));
+        assert_eq!(failing(&fields[28..]), Some(vec![5]));
+        assert_eq!(failing(&fields[..27]), None);
+
+        fields[0].length = "0".to_string();
+        assert_eq!(failing(&fields), Some(vec![0]));
+    }
+
+    #[test]
+    fn links_fields_of_groups() {
+        let group = FieldInput {
+            children: vec![field("A", "1"), field("B", "0"), field("C", "1")],
+            expanded: true,
+            ..field("Group", "3")
+        };
+        let fields = vec![field("Type", "1"), group];
+        assert_eq!(failing(&fields), Some(vec![1, 1]));
+
+        let mut style = ProtoDescriptor::default();
+        style.style.unit_width = 1;
+        let diagnostic = render_diagnostic(&style, &fields, 8, String::new());
+        assert_eq!(diagnostic.field, None);
+    }
+}
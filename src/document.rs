@@ -0,0 +1,336 @@
+//! Documents with several frames.
+//!
+//! A document holds named frames (the message types of a protocol), each with its own fields,
+//! sharing the elements and style of the diagram:
+//! `{"elements": {..}, "style": {..}, "frames": [{"name": "Request", "fields": [..]}, ..]}`.
+//! Files holding a single descriptor, written before documents had frames, open as a document with
+//! one frame. The number of bits in a unit of the lengths is saved along (`"unit_bits": 8`); files
+//! written before it was get the unit they were drawn with.
+
+use protoviz::descriptor::{ElementsDescriptor, FieldDescriptor, ProtoDescriptor, StyleDescriptor};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::fields::{
+    copy_fields, create_field_descriptors, default_descriptor, update_field_inputs, with_fields,
+    FieldInput,
+};
+use crate::group::{fields_value, read_children};
+use crate::history::History;
+use crate::kaitai::parse_ksy;
+use crate::length::legacy_unit_bits;
+
+/// Name of the frame of a document opened from a single descriptor
+pub const DEFAULT_FRAME_NAME: &str = "Frame 1";
+
+/// A frame of the open document
+#[derive(Debug, Default)]
+pub struct Frame {
+    pub name: String,
+    pub fields: Vec<FieldInput>,
+    /// Edits of the fields, kept while another frame is shown
+    pub history: History,
+}
+
+impl Frame {
+    pub fn new(name: &str, fields: Vec<FieldInput>) -> Self {
+        Frame {
+            name: name.to_string(),
+            fields,
+            history: History::default(),
+        }
+    }
+}
+
+/// A document read from a file
+pub struct Document {
+    /// Elements and style of the document, with the fields of the first frame
+    pub descriptor: ProtoDescriptor,
+    pub frames: Vec<Frame>,
+    /// Number of bits in a unit of the lengths
+    pub unit_bits: usize,
+}
+
+impl Document {
+    /// Document with a single frame holding the fields of the descriptor
+    pub fn single(descriptor: ProtoDescriptor, name: &str, unit_bits: usize) -> Self {
+        let fields = update_field_inputs(&descriptor.fields);
+        Document {
+            descriptor,
+            frames: vec![Frame::new(name, fields)],
+            unit_bits,
+        }
+    }
+
+    /// Whether the document is the one a new file starts with, holding no work to lose
+    pub fn is_default(&self) -> bool {
+        let value = |document: &Document| {
+            let fields = document
+                .frames
+                .first()
+                .map_or(&[][..], |frame| &frame.fields[..]);
+            document_value(
+                &document.descriptor,
+                &document.frames,
+                0,
+                fields,
+                document.unit_bits,
+            )
+            .ok()
+        };
+        value(self) == value(&default_document())
+    }
+}
+
+/// Document of a new file
+pub fn default_document() -> Document {
+    Document::single(default_descriptor(), DEFAULT_FRAME_NAME, 1)
+}
+
+/// A frame name differing from the names of the frames, numbering `name` if it's taken
+pub fn unique_frame_name(frames: &[Frame], name: &str) -> String {
+    let taken = |candidate: &str| frames.iter().any(|frame| frame.name == candidate);
+    let mut unique = name.to_string();
+    let mut suffix = 2;
+    while taken(&unique) {
+        unique = format!("{} {}", name, suffix);
+        suffix += 1;
+    }
+    unique
+}
+
+#[derive(Deserialize)]
+struct DocumentFile {
+    #[serde(default)]
+    elements: ElementsDescriptor,
+    #[serde(default)]
+    style: StyleDescriptor,
+    unit_bits: Option<usize>,
+    frames: Vec<FrameFile>,
+}
+
+#[derive(Deserialize)]
+struct FrameFile {
+    name: String,
+    fields: Vec<FieldDescriptor>,
+}
+
+/// Number of bits in a unit saved in the document, or the unit of the fields when it wasn't saved
+fn saved_unit_bits(unit_bits: Option<usize>, fields: &[FieldDescriptor]) -> Result<usize, String> {
+    match unit_bits {
+        Some(0) => Err("the unit has no bits".to_string()),
+        Some(unit_bits) => Ok(unit_bits),
+        None => Ok(legacy_unit_bits(fields)),
+    }
+}
+
+/// Read a document, or a single descriptor
+pub fn parse_document(json: Value) -> Result<Document, String> {
+    // The children of the fields are dropped by the descriptors, and read from the JSON
+    if json.get("frames").is_none() {
+        let json_fields = json.get("fields").cloned().unwrap_or_default();
+        let unit_bits = match json.get("unit_bits") {
+            Some(unit_bits) => {
+                Some(serde_json::from_value(unit_bits.clone()).map_err(|e| e.to_string())?)
+            }
+            None => None,
+        };
+        let descriptor: ProtoDescriptor =
+            serde_json::from_value(json).map_err(|e| e.to_string())?;
+        let unit_bits = saved_unit_bits(unit_bits, &descriptor.fields)?;
+        let mut document = Document::single(descriptor, DEFAULT_FRAME_NAME, unit_bits);
+        read_children(&mut document.frames[0].fields, &json_fields)?;
+        return Ok(document);
+    }
+
+    let json_fields: Vec<Value> = json["frames"]
+        .as_array()
+        .map(|frames| frames.iter().map(|frame| frame["fields"].clone()).collect())
+        .unwrap_or_default();
+    let file: DocumentFile = serde_json::from_value(json).map_err(|e| e.to_string())?;
+    let all_fields: Vec<FieldDescriptor> = file
+        .frames
+        .iter()
+        .flat_map(|frame| copy_fields(&frame.fields))
+        .collect();
+    let unit_bits = saved_unit_bits(file.unit_bits, &all_fields)?;
+    let mut frames = file.frames.into_iter();
+    let Some(first) = frames.next() else {
+        return Err("the document has no frames".to_string());
+    };
+
+    let mut document = Document::single(
+        ProtoDescriptor {
+            elements: file.elements,
+            style: file.style,
+            fields: first.fields,
+        },
+        &first.name,
+        unit_bits,
+    );
+    document
+        .frames
+        .extend(frames.map(|frame| Frame::new(&frame.name, update_field_inputs(&frame.fields))));
+    for (frame, json_fields) in document.frames.iter_mut().zip(&json_fields) {
+        read_children(&mut frame.fields, json_fields)?;
+    }
+    Ok(document)
+}
+
+/// Read a document from the text of an opened file: a Kaitai Struct format for `.ksy` files,
+/// given the number of bits in a unit selected by the user, and JSON otherwise
+pub fn read_document(file_name: &str, text: &str, unit_bits: usize) -> Result<Document, String> {
+    if let Some(name) = file_name.strip_suffix(".ksy") {
+        let format =
+            parse_ksy(text, unit_bits).map_err(|e| format!("invalid Kaitai format ({})", e))?;
+        let mut descriptor = default_descriptor();
+        descriptor.elements.network_order = format.network_order;
+        descriptor.fields = format.fields;
+        return Ok(Document::single(descriptor, name, unit_bits));
+    }
+
+    let json: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    parse_document(json)
+}
+
+/// Descriptors of the frames, with the elements and style of the document. The fields of the frame
+/// at `active` are taken from the descriptor.
+pub fn frame_descriptors(
+    descriptor: &ProtoDescriptor,
+    frames: &[Frame],
+    active: usize,
+) -> Vec<ProtoDescriptor> {
+    frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let fields = if i == active {
+                copy_fields(&descriptor.fields)
+            } else {
+                create_field_descriptors(&frame.fields)
+            };
+            with_fields(descriptor, fields)
+        })
+        .collect()
+}
+
+/// Serialize the document, with the elements and style of the descriptor and the number of bits in
+/// a unit. The fields of the frame at `active` are `active_fields`.
+pub fn document_value(
+    descriptor: &ProtoDescriptor,
+    frames: &[Frame],
+    active: usize,
+    active_fields: &[FieldInput],
+    unit_bits: usize,
+) -> serde_json::Result<Value> {
+    let mut frame_values = Vec::with_capacity(frames.len());
+    for (i, frame) in frames.iter().enumerate() {
+        let fields = if i == active {
+            active_fields
+        } else {
+            &frame.fields
+        };
+        frame_values.push(json!({
+            "name": frame.name,
+            "fields": fields_value(descriptor, fields, unit_bits)?,
+        }));
+    }
+
+    Ok(json!({
+        "elements": descriptor.elements,
+        "style": descriptor.style,
+        "unit_bits": unit_bits,
+        "frames": frame_values,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, length: &str) -> FieldInput {
+        FieldInput {
+            name: name.to_string(),
+            length: length.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reads_the_unit() {
+        let document = parse_document(json!({
+            "unit_bits": 8,
+            "fields": [{"name": "Type", "length": 2}],
+        }))
+        .unwrap();
+        assert_eq!(document.unit_bits, 8);
+
+        let error =
+            parse_document(json!({"unit_bits": 0, "frames": [{"name": "Request", "fields": []}]}));
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn infers_the_unit_of_legacy_documents() {
+        let bits = parse_document(json!({"fields": [{"name": "Version", "length": 4}]})).unwrap();
+        assert_eq!(bits.unit_bits, 1);
+
+        // Fields with lengths in bits were drawn in bytes, even in another frame
+        let bytes = parse_document(json!({
+            "frames": [
+                {"name": "Request", "fields": [{"name": "Type", "length": 1}]},
+                {"name": "Reply", "fields": [{"name": "Flags", "length": "4b"}]},
+            ],
+        }))
+        .unwrap();
+        assert_eq!(bytes.unit_bits, 8);
+    }
+
+    #[test]
+    fn saves_whole_units_as_units() {
+        let mut document = default_document();
+        let fields = vec![
+            field("Type", "8b"),
+            field("Flags", "4b"),
+            field("Length", "2"),
+        ];
+        document.frames[0].fields = fields.clone();
+
+        let value = document_value(&document.descriptor, &document.frames, 0, &fields, 8).unwrap();
+        assert_eq!(value["unit_bits"], 8);
+        let lengths: Vec<_> = value["frames"][0]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["length"].clone())
+            .collect();
+        assert_eq!(lengths, [json!(1), json!("4b"), json!(2)]);
+
+        let reopened = parse_document(value).unwrap();
+        assert_eq!(reopened.unit_bits, 8);
+        let lengths: Vec<_> = reopened.frames[0]
+            .fields
+            .iter()
+            .map(|field| field.length.as_str())
+            .collect();
+        assert_eq!(lengths, ["1", "4b", "2"]);
+    }
+
+    #[test]
+    fn tells_default_documents() {
+        let document = default_document();
+        assert!(document.is_default());
+
+        // An autosaved new document is still the default one
+        let fields = &document.frames[0].fields;
+        let value = document_value(&document.descriptor, &document.frames, 0, fields, 1).unwrap();
+        assert!(parse_document(value).unwrap().is_default());
+
+        let mut edited = default_document();
+        edited.frames[0].fields[0].name = "Type".to_string();
+        assert!(!edited.is_default());
+        let mut renamed = default_document();
+        renamed.frames[0].name = "Request".to_string();
+        assert!(!renamed.is_default());
+    }
+}
@@ -0,0 +1,521 @@
+//! Length expressions.
+//!
+//! Variable lengths can be arithmetic expressions over numbers and the names of other fields
+//! (`IHL*4 - 20`, `Length`, `N+2`). Names may contain spaces between words (`Data Offset * 4`) and
+//! match a field case-insensitively. A single capital letter that isn't a field name (`N`, `K`)
+//! is a free variable, as in the templates. The diagram shows the length as written, and the
+//! saved JSON also has the parsed expression of each field in `length_expr`.
+
+use protoviz::descriptor::{FieldLength, ProtoDescriptor};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::ident::names_field;
+use crate::length::parse_bits;
+
+/// Key of the parsed length expression in the fields of the saved JSON
+const EXPR_KEY: &str = "length_expr";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Op {
+    #[serde(rename = "+")]
+    Add,
+    #[serde(rename = "-")]
+    Sub,
+    #[serde(rename = "*")]
+    Mul,
+    #[serde(rename = "/")]
+    Div,
+}
+
+/// A parsed length expression
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Expr {
+    Number(u64),
+    /// Name of a field, or a free variable
+    Field(String),
+    Binary {
+        op: Op,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(u64),
+    Name(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+/// Split an expression into tokens, with the column (1-based) where each one starts
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        let token = match c {
+            ' ' | '\t' => {
+                i += 1;
+                continue;
+            }
+            '+' => Token::Op(Op::Add),
+            '-' => Token::Op(Op::Sub),
+            '*' => Token::Op(Op::Mul),
+            '/' => Token::Op(Op::Div),
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '0'..='9' => {
+                let start = i;
+                let hex = c == '0' && matches!(chars.get(i + 1), Some('x' | 'X'));
+                if hex {
+                    i += 2;
+                }
+                while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                    i += 1;
+                }
+                let digits: String = chars[start..i].iter().collect();
+                let number = if hex {
+                    u64::from_str_radix(&digits[2..], 16)
+                } else {
+                    digits.parse()
+                };
+                match number {
+                    Ok(number) => tokens.push((Token::Number(number), column)),
+                    Err(_) => {
+                        return Err(format!("invalid number '{}' at column {}", digits, column))
+                    }
+                }
+                continue;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                // Names continue over single spaces between words
+                let start = i;
+                let is_word = |c: char| c.is_alphanumeric() || c == '_';
+                while i < chars.len()
+                    && (is_word(chars[i])
+                        || (chars[i] == ' ' && chars.get(i + 1).is_some_and(|c| is_word(*c))))
+                {
+                    i += 1;
+                }
+                tokens.push((Token::Name(chars[start..i].iter().collect()), column));
+                continue;
+            }
+            c => return Err(format!("unexpected '{}' at column {}", c, column)),
+        };
+        tokens.push((token, column));
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    /// Column after the last character, for errors at the end of the expression
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.end, |(_, column)| *column)
+    }
+
+    /// Sum or difference of terms
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Op(op @ (Op::Add | Op::Sub))) = self.peek() {
+            let op = *op;
+            self.position += 1;
+            let rhs = self.term()?;
+            lhs = Expr::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+        Ok(lhs)
+    }
+
+    /// Product or quotient of factors
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.factor()?;
+        while let Some(Token::Op(op @ (Op::Mul | Op::Div))) = self.peek() {
+            let op = *op;
+            self.position += 1;
+            let rhs = self.factor()?;
+            lhs = Expr::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        let column = self.column();
+        let Some((token, _)) = self.tokens.get(self.position).cloned() else {
+            return Err(format!("expected a value at column {}", column));
+        };
+        self.position += 1;
+
+        match token {
+            Token::Number(number) => Ok(Expr::Number(number)),
+            Token::Name(name) => Ok(Expr::Field(name)),
+            Token::Open => {
+                let expr = self.expr()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.position += 1;
+                        Ok(expr)
+                    }
+                    _ => Err(format!("expected ')' at column {}", self.column())),
+                }
+            }
+            Token::Op(_) | Token::Close => Err(format!("expected a value at column {}", column)),
+        }
+    }
+}
+
+/// Parse a length expression
+pub fn parse_expr(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+        end: text.chars().count() + 1,
+    };
+    if parser.tokens.is_empty() {
+        return Err("the expression is empty".to_string());
+    }
+
+    let expr = parser.expr()?;
+    if parser.position < parser.tokens.len() {
+        return Err(format!("unexpected token at column {}", parser.column()));
+    }
+    Ok(expr)
+}
+
+impl Expr {
+    /// Names used by the expression, in order of appearance
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::Field(name) => vec![name],
+            Expr::Binary { lhs, rhs, .. } => {
+                let mut names = lhs.names();
+                names.extend(rhs.names());
+                names
+            }
+        }
+    }
+
+    /// Value of the expression, given the value of each field (None if it isn't known).
+    /// Returns None if a value is missing or the result isn't a natural number.
+    pub fn eval(&self, field_names: &[&str], values: &[Option<u64>]) -> Option<u64> {
+        match self {
+            Expr::Number(number) => Some(*number),
+            Expr::Field(name) => values
+                .get(field_index(name, field_names)?)
+                .copied()
+                .flatten(),
+            Expr::Binary { op, lhs, rhs } => {
+                let lhs = lhs.eval(field_names, values)?;
+                let rhs = rhs.eval(field_names, values)?;
+                match op {
+                    Op::Add => lhs.checked_add(rhs),
+                    Op::Sub => lhs.checked_sub(rhs),
+                    Op::Mul => lhs.checked_mul(rhs),
+                    Op::Div => lhs.checked_div(rhs),
+                }
+            }
+        }
+    }
+
+    /// Replace the names matching a field with the name of the field, as written in the descriptor
+    fn resolve(&mut self, field_names: &[&str]) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Field(name) => {
+                if let Some(field_name) = find_field(name, field_names) {
+                    *name = field_name.to_string();
+                }
+            }
+            Expr::Binary { lhs, rhs, .. } => {
+                lhs.resolve(field_names);
+                rhs.resolve(field_names);
+            }
+        }
+    }
+}
+
+/// Find the index of the field with the given name, ignoring case and punctuation
+fn field_index(name: &str, field_names: &[&str]) -> Option<usize> {
+    field_names
+        .iter()
+        .position(|field_name| names_field(name, field_name))
+}
+
+/// Find the field with the given name, ignoring case and punctuation
+fn find_field<'a>(name: &str, field_names: &[&'a str]) -> Option<&'a str> {
+    field_index(name, field_names).map(|index| field_names[index])
+}
+
+/// Whether a name that isn't a field is a free variable, like the `N` of the templates
+fn is_variable(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!((chars.next(), chars.next()), (Some(c), None) if c.is_ascii_uppercase())
+}
+
+/// Parse a length that is neither a number of units nor of bits.
+/// A length naming a field is taken as a whole, even if the name isn't a valid expression.
+pub fn parse_length(length: &str, field_names: &[&str]) -> Result<Expr, String> {
+    match find_field(length, field_names) {
+        Some(field_name) => Ok(Expr::Field(field_name.to_string())),
+        None => parse_expr(length),
+    }
+}
+
+/// Problems of the length of the field at `index`, given the names of all the fields
+pub fn check_length(length: &str, field_names: &[&str], index: usize) -> Vec<String> {
+    let length = length.trim();
+    if length.is_empty() || length.parse::<usize>().is_ok() || parse_bits(length).is_some() {
+        return Vec::new();
+    }
+
+    let expr = match parse_length(length, field_names) {
+        Ok(expr) => expr,
+        Err(e) => return vec![e],
+    };
+    let own_name = field_names.get(index).copied();
+    let mut problems = Vec::new();
+    for name in expr.names() {
+        let problem = match find_field(name, field_names) {
+            Some(field_name) if Some(field_name) == own_name => format!("'{}' is this field", name),
+            Some(_) => continue,
+            None if is_variable(name) => continue,
+            None => format!("unknown field '{}'", name),
+        };
+        if !problems.contains(&problem) {
+            problems.push(problem);
+        }
+    }
+    problems
+}
+
+/// Serialize the descriptor for saving, with the parsed length expressions of the fields
+pub fn descriptor_value(descriptor: &ProtoDescriptor) -> serde_json::Result<Value> {
+    let field_names: Vec<&str> = descriptor
+        .fields
+        .iter()
+        .map(|field| field.name.as_str())
+        .collect();
+    let mut json = serde_json::to_value(descriptor)?;
+
+    if let Some(fields) = json
+        .get_mut("fields")
+        .and_then(|fields| fields.as_array_mut())
+    {
+        for (json_field, field) in fields.iter_mut().zip(&descriptor.fields) {
+            let FieldLength::Variable(length) = &field.length else {
+                continue;
+            };
+            if parse_bits(length).is_some() {
+                continue;
+            }
+            if let (Ok(mut expr), Some(json_field)) = (
+                parse_length(length, &field_names),
+                json_field.as_object_mut(),
+            ) {
+                expr.resolve(&field_names);
+                json_field.insert(EXPR_KEY.to_string(), serde_json::to_value(expr)?);
+            }
+        }
+    }
+
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(number: u64) -> Expr {
+        Expr::Number(number)
+    }
+
+    fn field(name: &str) -> Expr {
+        Expr::Field(name.to_string())
+    }
+
+    fn binary(op: Op, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        }
+    }
+
+    #[test]
+    fn tokenizes_expressions() {
+        let tokens: Vec<_> = tokenize("Data Offset*0x4 -(N)")
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                (Token::Name("Data Offset".to_string()), 1),
+                (Token::Op(Op::Mul), 12),
+                (Token::Number(4), 13),
+                (Token::Op(Op::Sub), 17),
+                (Token::Open, 18),
+                (Token::Name("N".to_string()), 19),
+                (Token::Close, 20),
+            ]
+        );
+        assert_eq!(
+            tokenize("0x1G").unwrap_err(),
+            "invalid number '0x1G' at column 1"
+        );
+        assert_eq!(tokenize("4 % 2").unwrap_err(), "unexpected '%' at column 3");
+    }
+
+    #[test]
+    fn parses_with_precedence() {
+        assert_eq!(
+            parse_expr("IHL*4 - 20").unwrap(),
+            binary(
+                Op::Sub,
+                binary(Op::Mul, field("IHL"), number(4)),
+                number(20)
+            )
+        );
+        assert_eq!(
+            parse_expr("2 + 3 * 4").unwrap(),
+            binary(Op::Add, number(2), binary(Op::Mul, number(3), number(4)))
+        );
+        assert_eq!(
+            parse_expr("(2 + 3) * 4").unwrap(),
+            binary(Op::Mul, binary(Op::Add, number(2), number(3)), number(4))
+        );
+        // Operators of the same precedence apply from left to right
+        assert_eq!(
+            parse_expr("10 - 4 - 2").unwrap(),
+            binary(Op::Sub, binary(Op::Sub, number(10), number(4)), number(2))
+        );
+        assert_eq!(parse_expr("0x10").unwrap(), number(16));
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        assert_eq!(parse_expr("").unwrap_err(), "the expression is empty");
+        assert_eq!(
+            parse_expr("4 +").unwrap_err(),
+            "expected a value at column 4"
+        );
+        assert_eq!(
+            parse_expr("(4 + 2").unwrap_err(),
+            "expected ')' at column 7"
+        );
+        assert_eq!(
+            parse_expr("4 2").unwrap_err(),
+            "unexpected token at column 3"
+        );
+        assert_eq!(
+            parse_expr("* 2").unwrap_err(),
+            "expected a value at column 1"
+        );
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let names = ["IHL", "Data Offset", "Length"];
+        let values = [Some(5), Some(8), None];
+        let eval = |text| parse_expr(text).unwrap().eval(&names, &values);
+
+        assert_eq!(eval("IHL*4 - 20"), Some(0));
+        assert_eq!(eval("data offset * 4"), Some(32));
+        assert_eq!(eval("(IHL + 1) / 2"), Some(3));
+        // Unknown values, negative results and division by zero have no value
+        assert_eq!(eval("Length + 1"), None);
+        assert_eq!(eval("N"), None);
+        assert_eq!(eval("IHL - 6"), None);
+        assert_eq!(eval("IHL / 0"), None);
+        assert_eq!(eval("0xFFFFFFFFFFFFFFFF + 1"), None);
+    }
+
+    #[test]
+    fn checks_lengths() {
+        let names = [
+            "Type",
+            "Data Offset",
+            "Options",
+            "Payload Length",
+            "Payload",
+        ];
+        assert!(check_length("Data Offset * 4 - 20", &names, 2).is_empty());
+        assert!(check_length("payload length", &names, 4).is_empty());
+        assert!(check_length("N + 2", &names, 4).is_empty());
+        assert!(check_length("12", &names, 4).is_empty());
+        assert!(check_length("4b", &names, 4).is_empty());
+        assert_eq!(
+            check_length("Payload - 1", &names, 4),
+            ["'Payload' is this field"]
+        );
+        assert_eq!(
+            check_length("Size + Size", &names, 4),
+            ["unknown field 'Size'"]
+        );
+        assert_eq!(
+            check_length("Type + * 2", &names, 4),
+            ["expected a value at column 8"]
+        );
+    }
+
+    #[test]
+    fn saves_resolved_expressions() {
+        let descriptor = ProtoDescriptor {
+            fields: vec![
+                protoviz::descriptor::FieldDescriptor {
+                    name: "Header Length".to_string(),
+                    length: FieldLength::Fixed(4),
+                    wrap: false,
+                    color: None,
+                },
+                protoviz::descriptor::FieldDescriptor {
+                    name: "Options".to_string(),
+                    length: FieldLength::Variable("header length*4".to_string()),
+                    wrap: false,
+                    color: None,
+                },
+            ],
+            ..Default::default()
+        };
+        let json = descriptor_value(&descriptor).unwrap();
+        assert!(json["fields"][0].get(EXPR_KEY).is_none());
+        assert_eq!(
+            json["fields"][1][EXPR_KEY],
+            serde_json::json!({
+                "binary": {
+                    "op": "*",
+                    "lhs": {"field": "Header Length"},
+                    "rhs": {"number": 4},
+                },
+            })
+        );
+    }
+}
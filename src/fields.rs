@@ -0,0 +1,152 @@
+//! Fields of the editor.
+//!
+//! The fields are edited as text (`FieldInput`) and turned into protoviz descriptors to be drawn.
+
+use hex_color::HexColor;
+use protoviz::descriptor::{
+    ElementsDescriptor, FieldDescriptor, FieldLength, ProtoDescriptor, StyleDescriptor,
+};
+
+use crate::diagnostics::{error_message, render_diagnostic, Diagnostic};
+use crate::group::{has_expanded_groups, render_groups};
+use crate::length::{parse_bits, render_svg};
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FieldInput {
+    pub name: String,
+    pub length: String,
+    pub wrap: bool,
+    pub color: Option<HexColor>,
+    /// Parts of the field, drawn below the diagram when it's expanded
+    pub children: Vec<FieldInput>,
+    pub expanded: bool,
+}
+
+/// Fields shown when starting a new document
+pub fn default_field_inputs() -> Vec<FieldInput> {
+    vec![
+        FieldInput {
+            name: "Field 1".to_string(),
+            length: "2".to_string(),
+            wrap: false,
+            color: None,
+            ..Default::default()
+        },
+        FieldInput {
+            name: "Field 2".to_string(),
+            length: "N".to_string(),
+            wrap: false,
+            color: Some(HexColor::rgb(120, 180, 240)),
+            ..Default::default()
+        },
+        FieldInput {
+            name: "Field 3".to_string(),
+            length: "1".to_string(),
+            wrap: false,
+            color: Some(HexColor::rgb(240, 180, 120)),
+            ..Default::default()
+        },
+    ]
+}
+
+/// Descriptor of a new document
+pub fn default_descriptor() -> ProtoDescriptor {
+    ProtoDescriptor {
+        fields: create_field_descriptors(&default_field_inputs()),
+        ..Default::default()
+    }
+}
+
+/// Descriptor with the elements and style of another one, and the given fields
+pub fn with_fields(descriptor: &ProtoDescriptor, fields: Vec<FieldDescriptor>) -> ProtoDescriptor {
+    let elements = &descriptor.elements;
+    let style = &descriptor.style;
+    ProtoDescriptor {
+        elements: ElementsDescriptor {
+            network_order: elements.network_order,
+            inner_subtitles: elements.inner_subtitles,
+            field_position: elements.field_position,
+            field_length: elements.field_length,
+            wrap_line: elements.wrap_line,
+            start_symbol: elements.start_symbol,
+        },
+        style: StyleDescriptor {
+            background_color: style.background_color,
+            field_color: style.field_color,
+            text_color: style.text_color,
+            subtitle_color: style.subtitle_color,
+            unit_width: style.unit_width,
+            dyn_units: style.dyn_units,
+        },
+        fields,
+    }
+}
+
+/// Copy of the fields of a descriptor
+pub fn copy_fields(fields: &[FieldDescriptor]) -> Vec<FieldDescriptor> {
+    fields
+        .iter()
+        .map(|field| FieldDescriptor {
+            name: field.name.clone(),
+            length: field.length.clone(),
+            wrap: field.wrap,
+            color: field.color,
+        })
+        .collect()
+}
+
+pub fn create_field_descriptors(input_fields: &[FieldInput]) -> Vec<FieldDescriptor> {
+    input_fields
+        .iter()
+        .map(|field| {
+            FieldDescriptor {
+                name: field.name.clone(),
+                length: if !field.length.is_empty() {
+                    match (field.length.parse::<usize>(), parse_bits(&field.length)) {
+                        (Ok(len), _) => FieldLength::Fixed(len),
+                        (_, Some(bits)) => FieldLength::Variable(format!("{}b", bits)),
+                        _ => FieldLength::Variable(field.length.clone()),
+                    }
+                } else {
+                    FieldLength::Fixed(1) // If no length is provided, default to 1
+                },
+                wrap: field.wrap,
+                color: field.color,
+            }
+        })
+        .collect()
+}
+
+pub fn update_field_inputs(descriptors: &[FieldDescriptor]) -> Vec<FieldInput> {
+    descriptors
+        .iter()
+        .map(|field| FieldInput {
+            name: field.name.clone(),
+            length: match &field.length {
+                FieldLength::Fixed(len) => len.to_string(),
+                FieldLength::Variable(len) => len.clone(),
+            },
+            wrap: field.wrap,
+            color: field.color,
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Render the fields with the given number of bits in a unit, or the problem rendering them
+pub fn update_svg(
+    descriptor: &ProtoDescriptor,
+    fields: &[FieldInput],
+    unit_bits: usize,
+) -> Result<String, Diagnostic> {
+    if descriptor.fields.is_empty() {
+        return Ok(String::new());
+    }
+
+    let svg = if has_expanded_groups(fields) {
+        render_groups(descriptor, fields, unit_bits)
+    } else {
+        render_svg(descriptor, unit_bits).map_err(|e| error_message(&e))
+    };
+    svg.map_err(|e| render_diagnostic(descriptor, fields, unit_bits, e))
+}
@@ -0,0 +1,679 @@
+//! Field groups.
+//!
+//! A field can hold child fields describing its parts (the bits of a flags byte, the parts of an
+//! address), which can hold children too. An expanded group is drawn as a zoomed-in diagram below
+//! the one of its parent, with guide lines from the box of the field. In the saved JSON the
+//! children are in the `children` key of the field, next to `expanded`, which protoviz ignores.
+
+use hex_color::Display;
+use protoviz::descriptor::{FieldDescriptor, FieldLength, ProtoDescriptor};
+use serde_json::Value;
+
+use crate::expr::descriptor_value;
+use crate::fields::{create_field_descriptors, update_field_inputs, with_fields, FieldInput};
+use crate::length::{bit_width, bits_length, fixed_bits, has_bit_lengths, unit_fields};
+use crate::stack::{dashed_line, render_layer, Rect};
+
+/// Keys of the children of a field in the saved JSON
+const CHILDREN_KEY: &str = "children";
+const EXPANDED_KEY: &str = "expanded";
+
+/// Space between a diagram and the diagrams of its expanded groups
+const GROUP_SPACING: f64 = 40.0;
+
+/// Space between the diagrams of groups side by side
+const DETAIL_SPACING: f64 = 20.0;
+
+/// Maximum zoom of the diagram of a group
+const MAX_ZOOM: f64 = 4.0;
+
+/// Indices of a field in the tree of fields, from the top level
+pub type FieldPath = Vec<usize>;
+
+/// Fields with the given parent (the top level fields for an empty path)
+pub fn children_of<'a>(fields: &'a [FieldInput], parent: &[usize]) -> Option<&'a [FieldInput]> {
+    match parent.split_first() {
+        None => Some(fields),
+        Some((&index, rest)) => children_of(&fields.get(index)?.children, rest),
+    }
+}
+
+pub fn children_of_mut<'a>(
+    fields: &'a mut Vec<FieldInput>,
+    parent: &[usize],
+) -> Option<&'a mut Vec<FieldInput>> {
+    match parent.split_first() {
+        None => Some(fields),
+        Some((&index, rest)) => children_of_mut(&mut fields.get_mut(index)?.children, rest),
+    }
+}
+
+pub fn field_at<'a>(fields: &'a [FieldInput], path: &[usize]) -> Option<&'a FieldInput> {
+    let (&index, parent) = path.split_last()?;
+    children_of(fields, parent)?.get(index)
+}
+
+pub fn field_at_mut<'a>(
+    fields: &'a mut Vec<FieldInput>,
+    path: &[usize],
+) -> Option<&'a mut FieldInput> {
+    let (&index, parent) = path.split_last()?;
+    children_of_mut(fields, parent)?.get_mut(index)
+}
+
+/// Paths of the fields shown in the field list: the children of the expanded fields are shown
+/// after them
+pub fn field_rows(fields: &[FieldInput]) -> Vec<FieldPath> {
+    fn visit(fields: &[FieldInput], parent: &mut FieldPath, rows: &mut Vec<FieldPath>) {
+        for (i, field) in fields.iter().enumerate() {
+            parent.push(i);
+            rows.push(parent.clone());
+            if field.expanded {
+                visit(&field.children, parent, rows);
+            }
+            parent.pop();
+        }
+    }
+
+    let mut rows = Vec::new();
+    visit(fields, &mut Vec::new(), &mut rows);
+    rows
+}
+
+/// Fields with the field at the path moved to the end of the children of the field before it
+pub fn indent(fields: &[FieldInput], path: &[usize]) -> Option<Vec<FieldInput>> {
+    let (&index, parent) = path.split_last()?;
+    let previous = index.checked_sub(1)?;
+    let mut new = fields.to_vec();
+    let siblings = children_of_mut(&mut new, parent)?;
+    if index >= siblings.len() {
+        return None;
+    }
+    let field = siblings.remove(index);
+    siblings[previous].children.push(field);
+    siblings[previous].expanded = true;
+    Some(new)
+}
+
+/// Fields with the field at the path moved out of its parent, after it
+pub fn outdent(fields: &[FieldInput], path: &[usize]) -> Option<Vec<FieldInput>> {
+    let (&index, parent) = path.split_last()?;
+    let (&parent_index, grandparent) = parent.split_last()?;
+    let mut new = fields.to_vec();
+    let siblings = children_of_mut(&mut new, grandparent)?;
+    let children = &mut siblings.get_mut(parent_index)?.children;
+    if index >= children.len() {
+        return None;
+    }
+    let field = children.remove(index);
+    siblings.insert(parent_index + 1, field);
+    Some(new)
+}
+
+/// The paths without the ones inside others (a field goes along with its parent), in the order of
+/// the field list
+fn outer_paths(paths: &[FieldPath]) -> Vec<&FieldPath> {
+    let mut outer: Vec<&FieldPath> = paths
+        .iter()
+        .filter(|path| {
+            !paths
+                .iter()
+                .any(|other| other.len() < path.len() && path.starts_with(other))
+        })
+        .collect();
+    outer.sort();
+    outer.dedup();
+    outer
+}
+
+/// Fields without the fields at the paths, and the paths of the fields after them updated to the
+/// new positions of the fields
+fn take_fields(
+    fields: &[FieldInput],
+    paths: &[&FieldPath],
+    after: &mut [&mut FieldPath],
+) -> Option<Vec<FieldInput>> {
+    // Removing the last fields first keeps the paths of the others valid
+    let mut new = fields.to_vec();
+    for path in paths.iter().rev() {
+        let (&index, parent) = path.split_last()?;
+        let siblings = children_of_mut(&mut new, parent)?;
+        if index >= siblings.len() {
+            return None;
+        }
+        siblings.remove(index);
+        // The fields after the removed one move up
+        let depth = parent.len();
+        for other in after.iter_mut() {
+            if other.len() > depth && other.starts_with(parent) && other[depth] > index {
+                other[depth] -= 1;
+            }
+        }
+    }
+    Some(new)
+}
+
+/// Fields with the fields at the paths moved, with their children, before or after the field at
+/// the target path, and the new paths of the moved fields. Returns None if the target is one of
+/// the moved fields.
+pub fn move_fields(
+    fields: &[FieldInput],
+    paths: &[FieldPath],
+    target: &[usize],
+    after: bool,
+) -> Option<(Vec<FieldInput>, Vec<FieldPath>)> {
+    let paths = outer_paths(paths);
+    if paths.iter().any(|path| target.starts_with(path)) {
+        return None;
+    }
+    let moved = paths
+        .iter()
+        .map(|path| field_at(fields, path).cloned())
+        .collect::<Option<Vec<_>>>()?;
+
+    let mut target = target.to_vec();
+    let mut new = take_fields(fields, &paths, &mut [&mut target])?;
+    let (&index, parent) = target.split_last()?;
+    let siblings = children_of_mut(&mut new, parent)?;
+    let index = if after { index + 1 } else { index };
+    if index > siblings.len() {
+        return None;
+    }
+    let moved_paths = (index..index + moved.len())
+        .map(|i| [parent, &[i]].concat())
+        .collect();
+    siblings.splice(index..index, moved);
+    Some((new, moved_paths))
+}
+
+/// Copies of the fields at the paths, with their children, in the order of the field list
+pub fn fields_at(fields: &[FieldInput], paths: &[FieldPath]) -> Option<Vec<FieldInput>> {
+    outer_paths(paths)
+        .into_iter()
+        .map(|path| field_at(fields, path).cloned())
+        .collect()
+}
+
+/// Fields with the new fields after the field at the path (at the end without one), and the paths
+/// of the new fields
+pub fn insert_fields(
+    fields: &[FieldInput],
+    after: Option<&[usize]>,
+    new_fields: Vec<FieldInput>,
+) -> Option<(Vec<FieldInput>, Vec<FieldPath>)> {
+    let mut new = fields.to_vec();
+    let (parent, index) = match after.and_then(<[usize]>::split_last) {
+        Some((&index, parent)) => (parent, index + 1),
+        None => (&[][..], fields.len()),
+    };
+    let siblings = children_of_mut(&mut new, parent)?;
+    if index > siblings.len() {
+        return None;
+    }
+    let paths = (index..index + new_fields.len())
+        .map(|i| [parent, &[i]].concat())
+        .collect();
+    siblings.splice(index..index, new_fields);
+    Some((new, paths))
+}
+
+/// Fields without the fields at the paths and their children
+pub fn remove_fields(fields: &[FieldInput], paths: &[FieldPath]) -> Option<Vec<FieldInput>> {
+    take_fields(fields, &outer_paths(paths), &mut [])
+}
+
+/// Fields with a copy of each field at the paths after it
+pub fn duplicate_fields(fields: &[FieldInput], paths: &[FieldPath]) -> Option<Vec<FieldInput>> {
+    let mut new = fields.to_vec();
+    // Copying the last fields first keeps the paths of the others valid
+    for path in outer_paths(paths).into_iter().rev() {
+        let (&index, parent) = path.split_last()?;
+        let siblings = children_of_mut(&mut new, parent)?;
+        let copy = siblings.get(index)?.clone();
+        siblings.insert(index + 1, copy);
+    }
+    Some(new)
+}
+
+/// Fields with the fields at the paths moved into a new field with the given name, in place of
+/// the first one. Returns None if the fields don't have the same parent.
+pub fn group_fields(
+    fields: &[FieldInput],
+    paths: &[FieldPath],
+    name: &str,
+    unit_bits: usize,
+) -> Option<Vec<FieldInput>> {
+    let paths = outer_paths(paths);
+    let (&index, parent) = paths.first()?.split_last()?;
+    if paths
+        .iter()
+        .any(|path| path.split_last().map(|(_, p)| p) != Some(parent))
+    {
+        return None;
+    }
+    let children = paths
+        .iter()
+        .map(|path| field_at(fields, path).cloned())
+        .collect::<Option<Vec<_>>>()?;
+
+    let mut new = take_fields(fields, &paths, &mut [])?;
+    let group = FieldInput {
+        name: name.to_string(),
+        length: group_length(&children, unit_bits),
+        children,
+        expanded: true,
+        ..Default::default()
+    };
+    children_of_mut(&mut new, parent)?.insert(index, group);
+    Some(new)
+}
+
+/// Length of a field made of the fields: their total length when they are fixed, the sum of their
+/// lengths otherwise
+fn group_length(children: &[FieldInput], unit_bits: usize) -> String {
+    let bits: Option<usize> = create_field_descriptors(children)
+        .iter()
+        .map(|field| fixed_bits(&field.length, unit_bits))
+        .sum();
+    match bits.map(|bits| bits_length(bits, unit_bits)) {
+        Some(FieldLength::Fixed(length)) => length.to_string(),
+        Some(FieldLength::Variable(length)) => length,
+        None => children
+            .iter()
+            .map(|field| match field.length.as_str() {
+                "" => "1",
+                length => length,
+            })
+            .collect::<Vec<_>>()
+            .join(" + "),
+    }
+}
+
+/// Fields with the change applied to each field at the paths
+pub fn edit_fields(
+    fields: &[FieldInput],
+    paths: &[FieldPath],
+    change: impl Fn(&mut FieldInput),
+) -> Option<Vec<FieldInput>> {
+    let mut new = fields.to_vec();
+    for path in paths {
+        change(field_at_mut(&mut new, path)?);
+    }
+    Some(new)
+}
+
+/// Whether some fields have children to draw
+pub fn has_expanded_groups(fields: &[FieldInput]) -> bool {
+    fields
+        .iter()
+        .any(|field| field.expanded && !field.children.is_empty())
+}
+
+/// Serialize the fields for saving, with their children and their lengths in bits in units when
+/// they are a whole number of them
+pub fn fields_value(
+    descriptor: &ProtoDescriptor,
+    fields: &[FieldInput],
+    unit_bits: usize,
+) -> serde_json::Result<Value> {
+    let descriptors = unit_fields(&create_field_descriptors(fields), unit_bits);
+    let mut json = descriptor_value(&with_fields(descriptor, descriptors))?;
+    let mut json_fields = json["fields"].take();
+
+    if let Some(json_fields) = json_fields.as_array_mut() {
+        for (json_field, field) in json_fields.iter_mut().zip(fields) {
+            let Some(json_field) = json_field.as_object_mut() else {
+                continue;
+            };
+            if !field.children.is_empty() {
+                json_field.insert(
+                    CHILDREN_KEY.to_string(),
+                    fields_value(descriptor, &field.children, unit_bits)?,
+                );
+                json_field.insert(EXPANDED_KEY.to_string(), Value::Bool(field.expanded));
+            }
+        }
+    }
+
+    Ok(json_fields)
+}
+
+/// Read the children of the fields from the saved JSON fields
+pub fn read_children(fields: &mut [FieldInput], json_fields: &Value) -> Result<(), String> {
+    let Some(json_fields) = json_fields.as_array() else {
+        return Ok(());
+    };
+
+    for (field, json_field) in fields.iter_mut().zip(json_fields) {
+        let Some(children) = json_field.get(CHILDREN_KEY) else {
+            continue;
+        };
+        let descriptors: Vec<FieldDescriptor> = serde_json::from_value(children.clone())
+            .map_err(|e| format!("invalid children of field '{}': {}", field.name, e))?;
+        field.children = update_field_inputs(&descriptors);
+        field.expanded = json_field
+            .get(EXPANDED_KEY)
+            .and_then(Value::as_bool)
+            .unwrap_or(true);
+        read_children(&mut field.children, children)?;
+    }
+
+    Ok(())
+}
+
+/// Diagram of fields with the diagrams of their expanded groups
+struct Block {
+    body: String,
+    width: f64,
+    height: f64,
+    /// Box around the fields of the top diagram
+    fields: Rect,
+}
+
+/// Width of a unit of the group, zoomed in so its diagram is about as wide as `width`
+fn zoomed_unit_width(
+    group: &ProtoDescriptor,
+    width: f64,
+    unit_bits: usize,
+) -> Result<usize, String> {
+    let fields = render_layer(group, unit_bits)?.fields;
+    let zoom = (width / (fields.right - fields.left)).clamp(1.0, MAX_ZOOM);
+    let unit_width = group.style.unit_width;
+    let zoomed = if has_bit_lengths(&unit_fields(&group.fields, unit_bits), unit_bits) {
+        // Bits are drawn with a width of their own
+        bit_width(unit_width, unit_bits) as f64 * zoom * unit_bits as f64
+    } else {
+        unit_width as f64 * zoom
+    };
+    Ok(zoomed as usize)
+}
+
+/// Render the fields with the elements and style of the descriptor, and their expanded groups
+/// below them
+fn render_block(
+    descriptor: &ProtoDescriptor,
+    fields: &[FieldInput],
+    unit_bits: usize,
+) -> Result<Block, String> {
+    let descriptor = with_fields(descriptor, create_field_descriptors(fields));
+    let layer = render_layer(&descriptor, unit_bits)?;
+
+    let mut groups = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        if field.expanded && !field.children.is_empty() {
+            let block = (|| {
+                let mut group = with_fields(&descriptor, create_field_descriptors(&field.children));
+                group.style.unit_width =
+                    zoomed_unit_width(&group, layer.fields.right - layer.fields.left, unit_bits)?;
+                render_block(&group, &field.children, unit_bits)
+            })()
+            .map_err(|e| format!("group '{}': {}", field.name, e))?;
+            groups.push((layer.boxes[i], block));
+        }
+    }
+    if groups.is_empty() {
+        return Ok(Block {
+            body: layer.body,
+            width: layer.width,
+            height: layer.height,
+            fields: layer.fields,
+        });
+    }
+
+    // The groups are side by side below the diagram, everything centered
+    let groups_width = groups.iter().map(|(_, block)| block.width).sum::<f64>()
+        + DETAIL_SPACING * (groups.len() - 1) as f64;
+    let groups_height = groups
+        .iter()
+        .map(|(_, block)| block.height)
+        .fold(0.0, f64::max);
+    let width = layer.width.max(groups_width);
+    let layer_x = (width - layer.width) / 2.0;
+    let groups_y = layer.height + GROUP_SPACING;
+
+    // The guide lines are drawn first, so they go under the fields of the diagram
+    let mut body = String::new();
+    let mut groups_body = String::new();
+    let mut x = (width - groups_width) / 2.0;
+    for (field_box, block) in &groups {
+        groups_body.push_str(&format!(
+            "\n<g transform=\"translate({},{})\">",
+            x, groups_y
+        ));
+        groups_body.push_str(&block.body);
+        groups_body.push_str("\n</g>");
+
+        // Guide lines from the corners of the field to the fields of the group
+        for (from, to) in [
+            (field_box.left, block.fields.left),
+            (field_box.right, block.fields.right),
+        ] {
+            body.push_str(&dashed_line(
+                (layer_x + from, field_box.bottom),
+                (x + to, groups_y + block.fields.top),
+                descriptor.style.text_color,
+            ));
+        }
+        x += block.width + DETAIL_SPACING;
+    }
+    body.push_str(&format!("\n<g transform=\"translate({},0)\">", layer_x));
+    body.push_str(&layer.body);
+    body.push_str("\n</g>");
+    body.push_str(&groups_body);
+
+    Ok(Block {
+        body,
+        width,
+        height: groups_y + groups_height,
+        fields: Rect {
+            left: layer_x + layer.fields.left,
+            right: layer_x + layer.fields.right,
+            ..layer.fields
+        },
+    })
+}
+
+/// Render the fields with the elements and style of the descriptor, with the diagrams of their
+/// expanded groups
+pub fn render_groups(
+    descriptor: &ProtoDescriptor,
+    fields: &[FieldInput],
+    unit_bits: usize,
+) -> Result<String, String> {
+    let block = render_block(descriptor, fields, unit_bits)?;
+    Ok(format!(
+        "<svg viewBox=\"0 0 {} {}\" xmlns=\"http://www.w3.org/2000/svg\" style=\"background-color:{}\">{}\n</svg>",
+        block.width,
+        block.height,
+        Display::new(descriptor.style.background_color),
+        block.body
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, length: &str) -> FieldInput {
+        FieldInput {
+            name: name.to_string(),
+            length: length.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn group(name: &str, length: &str, children: Vec<FieldInput>) -> FieldInput {
+        FieldInput {
+            children,
+            expanded: true,
+            ..field(name, length)
+        }
+    }
+
+    /// A, B (C, D (E)), F
+    fn fields() -> Vec<FieldInput> {
+        vec![
+            field("A", "1"),
+            group(
+                "B",
+                "2",
+                vec![field("C", "1"), group("D", "1", vec![field("E", "1")])],
+            ),
+            field("F", "3"),
+        ]
+    }
+
+    /// Names of the fields, with the children of a field in parentheses after it
+    fn tree(fields: &[FieldInput]) -> String {
+        fields
+            .iter()
+            .map(|field| {
+                if field.children.is_empty() {
+                    field.name.clone()
+                } else {
+                    format!("{}({})", field.name, tree(&field.children))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn indents_and_outdents_fields() {
+        let fields = fields();
+        assert_eq!(tree(&indent(&fields, &[2]).unwrap()), "A B(C D(E) F)");
+        assert_eq!(tree(&indent(&fields, &[1, 1]).unwrap()), "A B(C(D(E))) F");
+        let indented = indent(&fields, &[1]).unwrap();
+        assert_eq!(tree(&indented), "A(B(C D(E))) F");
+        assert!(indented[0].expanded);
+
+        assert_eq!(tree(&outdent(&fields, &[1, 0]).unwrap()), "A B(D(E)) C F");
+        assert_eq!(tree(&outdent(&fields, &[1, 1, 0]).unwrap()), "A B(C D E) F");
+
+        // Nothing before the first field or above the top level, and stale paths
+        assert!(indent(&fields, &[0]).is_none());
+        assert!(indent(&fields, &[1, 0]).is_none());
+        assert!(outdent(&fields, &[0]).is_none());
+        for path in [&[][..], &[3], &[1, 2], &[4, 1], &[1, 1, 1]] {
+            assert!(indent(&fields, path).is_none(), "{:?}", path);
+            assert!(outdent(&fields, path).is_none(), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn moves_fields() {
+        let fields = fields();
+        let (moved, paths) = move_fields(&fields, &[vec![0]], &[2], true).unwrap();
+        assert_eq!(tree(&moved), "B(C D(E)) F A");
+        assert_eq!(paths, [vec![2]]);
+
+        let (moved, paths) = move_fields(&fields, &[vec![1, 1, 0], vec![2]], &[0], false).unwrap();
+        assert_eq!(tree(&moved), "E F A B(C D)");
+        assert_eq!(paths, [vec![0], vec![1]]);
+
+        // Into a group, whose path moves up when a field before it is taken
+        let (moved, paths) = move_fields(&fields, &[vec![0]], &[1, 1, 0], true).unwrap();
+        assert_eq!(tree(&moved), "B(C D(E A)) F");
+        assert_eq!(paths, [vec![0, 1, 1]]);
+
+        // A field goes along with its parent
+        let (moved, paths) = move_fields(&fields, &[vec![1], vec![1, 0]], &[2], true).unwrap();
+        assert_eq!(tree(&moved), "A F B(C D(E))");
+        assert_eq!(paths, [vec![2]]);
+    }
+
+    #[test]
+    fn refuses_invalid_moves() {
+        let fields = fields();
+        // Into the moved fields
+        assert!(move_fields(&fields, &[vec![1]], &[1, 1, 0], true).is_none());
+        assert!(move_fields(&fields, &[vec![1]], &[1], false).is_none());
+        // Stale paths
+        assert!(move_fields(&fields, &[vec![3]], &[0], false).is_none());
+        assert!(move_fields(&fields, &[vec![1, 5]], &[0], false).is_none());
+        assert!(move_fields(&fields, &[vec![0]], &[7], true).is_none());
+        assert!(move_fields(&fields, &[vec![0]], &[2, 0], true).is_none());
+        assert!(move_fields(&fields, &[vec![0]], &[], true).is_none());
+    }
+
+    #[test]
+    fn groups_fields() {
+        let fields = fields();
+        let grouped = group_fields(&fields, &[vec![2], vec![0]], "G", 8).unwrap();
+        assert_eq!(tree(&grouped), "G(A F) B(C D(E))");
+        assert_eq!(grouped[0].length, "4");
+        assert!(grouped[0].expanded);
+
+        let mut variable = fields.clone();
+        variable[1].children[0].length = "N".to_string();
+        let grouped = group_fields(&variable, &[vec![1, 0], vec![1, 1]], "G", 8).unwrap();
+        assert_eq!(tree(&grouped), "A B(G(C D(E))) F");
+        assert_eq!(grouped[1].children[0].length, "N + 1");
+
+        // Fields of different parents, and stale paths
+        assert!(group_fields(&fields, &[vec![0], vec![1, 0]], "G", 8).is_none());
+        assert!(group_fields(&fields, &[vec![0], vec![5]], "G", 8).is_none());
+        assert!(group_fields(&fields, &[vec![1, 3]], "G", 8).is_none());
+        assert!(group_fields(&fields, &[], "G", 8).is_none());
+    }
+
+    #[test]
+    fn removes_and_duplicates_fields() {
+        let fields = fields();
+        let removed = remove_fields(&fields, &[vec![2], vec![1, 0]]).unwrap();
+        assert_eq!(tree(&removed), "A B(D(E))");
+        assert_eq!(
+            tree(&remove_fields(&fields, &[vec![1, 1], vec![1]]).unwrap()),
+            "A F"
+        );
+
+        let duplicated = duplicate_fields(&fields, &[vec![1, 1], vec![0]]).unwrap();
+        assert_eq!(tree(&duplicated), "A A B(C D(E) D(E)) F");
+
+        for paths in [
+            vec![vec![3]],
+            vec![vec![1, 1, 4]],
+            vec![vec![0], vec![8, 0]],
+        ] {
+            assert!(remove_fields(&fields, &paths).is_none(), "{:?}", paths);
+            assert!(duplicate_fields(&fields, &paths).is_none(), "{:?}", paths);
+        }
+    }
+
+    #[test]
+    fn inserts_fields() {
+        let fields = fields();
+        let new = vec![field("X", "1"), field("Y", "1")];
+
+        let (inserted, paths) = insert_fields(&fields, Some(&[1, 0]), new.clone()).unwrap();
+        assert_eq!(tree(&inserted), "A B(C X Y D(E)) F");
+        assert_eq!(paths, [vec![1, 1], vec![1, 2]]);
+
+        let (inserted, paths) = insert_fields(&fields, None, new.clone()).unwrap();
+        assert_eq!(tree(&inserted), "A B(C D(E)) F X Y");
+        assert_eq!(paths, [vec![3], vec![4]]);
+
+        assert!(insert_fields(&fields, Some(&[3]), new.clone()).is_none());
+        assert!(insert_fields(&fields, Some(&[5, 0]), new).is_none());
+    }
+
+    #[test]
+    fn renders_expanded_groups() {
+        let descriptor = ProtoDescriptor::default();
+        let mut fields = fields();
+        let svg = render_groups(&descriptor, &fields, 8).unwrap();
+        assert!(svg.starts_with("<svg viewBox=\"0 0 "), "{}", svg);
+        // Guide lines to B and to D, below it
+        assert_eq!(svg.matches("stroke-dasharray").count(), 4, "{}", svg);
+        assert!(svg.contains(">E<"), "{}", svg);
+
+        fields[1].children[1].expanded = false;
+        let svg = render_groups(&descriptor, &fields, 8).unwrap();
+        assert_eq!(svg.matches("stroke-dasharray").count(), 2, "{}", svg);
+        assert!(!svg.contains(">E<"), "{}", svg);
+
+        fields[1].children[0].length = "0".to_string();
+        let error = render_groups(&descriptor, &fields, 8).unwrap_err();
+        assert!(error.starts_with("group 'B': "), "{}", error);
+    }
+}
@@ -0,0 +1,717 @@
+use hex_color::HexColor;
+use protoviz::descriptor::ProtoDescriptor;
+
+use crate::fields::FieldInput;
+use crate::group::{children_of_mut, field_at_mut, FieldPath};
+
+/// Maximum number of edits kept in the undo stack
+const MAX_HISTORY: usize = 200;
+
+/// Color properties of the style descriptor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StyleColor {
+    Background,
+    Field,
+    Text,
+    Subtitle,
+}
+
+impl StyleColor {
+    fn get(self, descriptor: &ProtoDescriptor) -> HexColor {
+        match self {
+            StyleColor::Background => descriptor.style.background_color,
+            StyleColor::Field => descriptor.style.field_color,
+            StyleColor::Text => descriptor.style.text_color,
+            StyleColor::Subtitle => descriptor.style.subtitle_color,
+        }
+    }
+
+    fn set(self, descriptor: &mut ProtoDescriptor, value: HexColor) {
+        match self {
+            StyleColor::Background => descriptor.style.background_color = value,
+            StyleColor::Field => descriptor.style.field_color = value,
+            StyleColor::Text => descriptor.style.text_color = value,
+            StyleColor::Subtitle => descriptor.style.subtitle_color = value,
+        }
+    }
+}
+
+/// Numeric properties of the style descriptor (sliders)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StyleSize {
+    UnitWidth,
+    DynUnits,
+}
+
+impl StyleSize {
+    fn get(self, descriptor: &ProtoDescriptor) -> usize {
+        match self {
+            StyleSize::UnitWidth => descriptor.style.unit_width,
+            StyleSize::DynUnits => descriptor.style.dyn_units,
+        }
+    }
+
+    fn set(self, descriptor: &mut ProtoDescriptor, value: usize) {
+        match self {
+            StyleSize::UnitWidth => descriptor.style.unit_width = value,
+            StyleSize::DynUnits => descriptor.style.dyn_units = value,
+        }
+    }
+}
+
+/// Toggles of the elements descriptor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ElementFlag {
+    NetworkOrder,
+    InnerSubtitles,
+    FieldPosition,
+    FieldLength,
+    WrapLine,
+    StartSymbol,
+}
+
+impl ElementFlag {
+    fn get(self, descriptor: &ProtoDescriptor) -> bool {
+        match self {
+            ElementFlag::NetworkOrder => descriptor.elements.network_order,
+            ElementFlag::InnerSubtitles => descriptor.elements.inner_subtitles,
+            ElementFlag::FieldPosition => descriptor.elements.field_position,
+            ElementFlag::FieldLength => descriptor.elements.field_length,
+            ElementFlag::WrapLine => descriptor.elements.wrap_line,
+            ElementFlag::StartSymbol => descriptor.elements.start_symbol,
+        }
+    }
+
+    fn set(self, descriptor: &mut ProtoDescriptor, value: bool) {
+        match self {
+            ElementFlag::NetworkOrder => descriptor.elements.network_order = value,
+            ElementFlag::InnerSubtitles => descriptor.elements.inner_subtitles = value,
+            ElementFlag::FieldPosition => descriptor.elements.field_position = value,
+            ElementFlag::FieldLength => descriptor.elements.field_length = value,
+            ElementFlag::WrapLine => descriptor.elements.wrap_line = value,
+            ElementFlag::StartSymbol => descriptor.elements.start_symbol = value,
+        }
+    }
+}
+
+/// A single reversible change to the field list or the descriptor options.
+/// Fields are found by their path in the tree of fields.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    AddField {
+        index: FieldPath,
+        field: FieldInput,
+    },
+    RemoveField {
+        index: FieldPath,
+        field: FieldInput,
+    },
+    /// Swap two children of the field at `parent`
+    SwapFields {
+        parent: FieldPath,
+        a: usize,
+        b: usize,
+    },
+    ReplaceFields {
+        old: Vec<FieldInput>,
+        new: Vec<FieldInput>,
+    },
+    Rename {
+        index: FieldPath,
+        old: String,
+        new: String,
+    },
+    Length {
+        index: FieldPath,
+        old: String,
+        new: String,
+    },
+    Wrap {
+        index: FieldPath,
+        old: bool,
+        new: bool,
+    },
+    Color {
+        index: FieldPath,
+        old: Option<HexColor>,
+        new: Option<HexColor>,
+    },
+    /// Show or hide the children of a field
+    Expand {
+        index: FieldPath,
+        old: bool,
+        new: bool,
+    },
+    StyleColor {
+        property: StyleColor,
+        old: HexColor,
+        new: HexColor,
+    },
+    StyleSize {
+        property: StyleSize,
+        old: usize,
+        new: usize,
+    },
+    Element {
+        property: ElementFlag,
+        old: bool,
+        new: bool,
+    },
+    /// Change the number of bits in a unit of the lengths of the document
+    Unit {
+        old: usize,
+        new: usize,
+    },
+}
+
+impl Edit {
+    /// Create an edit that changes a style color, taking the old value from the descriptor
+    pub fn style_color(descriptor: &ProtoDescriptor, property: StyleColor, new: HexColor) -> Self {
+        Edit::StyleColor {
+            property,
+            old: property.get(descriptor),
+            new,
+        }
+    }
+
+    /// Create an edit that changes a style size, taking the old value from the descriptor
+    pub fn style_size(descriptor: &ProtoDescriptor, property: StyleSize, new: usize) -> Self {
+        Edit::StyleSize {
+            property,
+            old: property.get(descriptor),
+            new,
+        }
+    }
+
+    /// Create an edit that toggles an element, taking the old value from the descriptor
+    pub fn element(descriptor: &ProtoDescriptor, property: ElementFlag, new: bool) -> Self {
+        Edit::Element {
+            property,
+            old: property.get(descriptor),
+            new,
+        }
+    }
+
+    /// Apply the edit to the field inputs, the descriptor and the unit of the lengths
+    fn apply(
+        &self,
+        fields: &mut Vec<FieldInput>,
+        descriptor: &mut ProtoDescriptor,
+        unit_bits: &mut usize,
+    ) {
+        match self {
+            Edit::AddField { index, field } => {
+                if let Some((&i, parent)) = index.split_last() {
+                    if let Some(siblings) = children_of_mut(fields, parent) {
+                        siblings.insert(i.min(siblings.len()), field.clone());
+                    }
+                }
+            }
+            Edit::RemoveField { index, .. } => {
+                if let Some((&i, parent)) = index.split_last() {
+                    if let Some(siblings) = children_of_mut(fields, parent) {
+                        if i < siblings.len() {
+                            siblings.remove(i);
+                        }
+                    }
+                }
+            }
+            Edit::SwapFields { parent, a, b } => {
+                if let Some(siblings) = children_of_mut(fields, parent) {
+                    if *a < siblings.len() && *b < siblings.len() {
+                        siblings.swap(*a, *b);
+                    }
+                }
+            }
+            Edit::ReplaceFields { new, .. } => *fields = new.clone(),
+            Edit::Rename { index, new, .. } => {
+                if let Some(field) = field_at_mut(fields, index) {
+                    field.name = new.clone();
+                }
+            }
+            Edit::Length { index, new, .. } => {
+                if let Some(field) = field_at_mut(fields, index) {
+                    field.length = new.clone();
+                }
+            }
+            Edit::Wrap { index, new, .. } => {
+                if let Some(field) = field_at_mut(fields, index) {
+                    field.wrap = *new;
+                }
+            }
+            Edit::Color { index, new, .. } => {
+                if let Some(field) = field_at_mut(fields, index) {
+                    field.color = *new;
+                }
+            }
+            Edit::Expand { index, new, .. } => {
+                if let Some(field) = field_at_mut(fields, index) {
+                    field.expanded = *new;
+                }
+            }
+            Edit::StyleColor { property, new, .. } => property.set(descriptor, *new),
+            Edit::StyleSize { property, new, .. } => property.set(descriptor, *new),
+            Edit::Element { property, new, .. } => property.set(descriptor, *new),
+            Edit::Unit { new, .. } => *unit_bits = *new,
+        }
+    }
+
+    /// Create the edit that reverts this one
+    fn inverse(&self) -> Edit {
+        match self.clone() {
+            Edit::AddField { index, field } => Edit::RemoveField { index, field },
+            Edit::RemoveField { index, field } => Edit::AddField { index, field },
+            Edit::SwapFields { parent, a, b } => Edit::SwapFields { parent, a, b },
+            Edit::ReplaceFields { old, new } => Edit::ReplaceFields { old: new, new: old },
+            Edit::Rename { index, old, new } => Edit::Rename {
+                index,
+                old: new,
+                new: old,
+            },
+            Edit::Length { index, old, new } => Edit::Length {
+                index,
+                old: new,
+                new: old,
+            },
+            Edit::Wrap { index, old, new } => Edit::Wrap {
+                index,
+                old: new,
+                new: old,
+            },
+            Edit::Color { index, old, new } => Edit::Color {
+                index,
+                old: new,
+                new: old,
+            },
+            Edit::Expand { index, old, new } => Edit::Expand {
+                index,
+                old: new,
+                new: old,
+            },
+            Edit::StyleColor { property, old, new } => Edit::StyleColor {
+                property,
+                old: new,
+                new: old,
+            },
+            Edit::StyleSize { property, old, new } => Edit::StyleSize {
+                property,
+                old: new,
+                new: old,
+            },
+            Edit::Element { property, old, new } => Edit::Element {
+                property,
+                old: new,
+                new: old,
+            },
+            Edit::Unit { old, new } => Edit::Unit { old: new, new: old },
+        }
+    }
+
+    /// Whether the edit doesn't change anything
+    fn is_noop(&self) -> bool {
+        match self {
+            Edit::AddField { .. } | Edit::RemoveField { .. } => false,
+            Edit::SwapFields { a, b, .. } => a == b,
+            Edit::ReplaceFields { old, new } => old == new,
+            Edit::Rename { old, new, .. } | Edit::Length { old, new, .. } => old == new,
+            Edit::Wrap { old, new, .. } | Edit::Expand { old, new, .. } => old == new,
+            Edit::Color { old, new, .. } => old == new,
+            Edit::StyleColor { old, new, .. } => old == new,
+            Edit::StyleSize { old, new, .. } => old == new,
+            Edit::Element { old, new, .. } => old == new,
+            Edit::Unit { old, new } => old == new,
+        }
+    }
+}
+
+/// Undo/redo stacks of edits applied to the document
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    // Whether the last undo entry can still absorb a coalesced edit
+    open: bool,
+}
+
+impl History {
+    /// Apply an edit and record it as a new history entry
+    pub fn record(
+        &mut self,
+        edit: Edit,
+        fields: &mut Vec<FieldInput>,
+        descriptor: &mut ProtoDescriptor,
+        unit_bits: &mut usize,
+    ) {
+        edit.apply(fields, descriptor, unit_bits);
+        self.open = false;
+
+        if edit.is_noop() {
+            return;
+        }
+
+        self.push(edit);
+    }
+
+    /// Apply an edit, merging it into the last entry if both change the same style size.
+    /// Used by sliders so a whole drag becomes a single entry, until `seal` is called.
+    pub fn record_coalesced(
+        &mut self,
+        edit: Edit,
+        fields: &mut Vec<FieldInput>,
+        descriptor: &mut ProtoDescriptor,
+        unit_bits: &mut usize,
+    ) {
+        edit.apply(fields, descriptor, unit_bits);
+
+        if let (
+            true,
+            Some(Edit::StyleSize { property, new, .. }),
+            Edit::StyleSize {
+                property: edit_property,
+                new: edit_new,
+                ..
+            },
+        ) = (self.open, self.undo.last_mut(), &edit)
+        {
+            if property == edit_property {
+                *new = *edit_new;
+                return;
+            }
+        }
+
+        self.push(edit);
+        self.open = true;
+    }
+
+    /// Close the current coalesced entry, so the next edit creates a new one
+    pub fn seal(&mut self) {
+        self.open = false;
+
+        // Drop the entry if the drag ended where it started
+        if let Some(edit) = self.undo.last() {
+            if edit.is_noop() {
+                self.undo.pop();
+            }
+        }
+    }
+
+    /// Revert the last edit. Returns false if there was nothing to undo
+    pub fn undo(
+        &mut self,
+        fields: &mut Vec<FieldInput>,
+        descriptor: &mut ProtoDescriptor,
+        unit_bits: &mut usize,
+    ) -> bool {
+        self.open = false;
+
+        match self.undo.pop() {
+            Some(edit) => {
+                edit.inverse().apply(fields, descriptor, unit_bits);
+                self.redo.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reapply the last undone edit. Returns false if there was nothing to redo
+    pub fn redo(
+        &mut self,
+        fields: &mut Vec<FieldInput>,
+        descriptor: &mut ProtoDescriptor,
+        unit_bits: &mut usize,
+    ) -> bool {
+        self.open = false;
+
+        match self.redo.pop() {
+            Some(edit) => {
+                edit.apply(fields, descriptor, unit_bits);
+                self.undo.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Forget all entries (e.g. when a new file is opened)
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.open = false;
+    }
+
+    fn push(&mut self, edit: Edit) {
+        self.redo.clear();
+        self.undo.push(edit);
+
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn field(name: &str) -> FieldInput {
+        FieldInput {
+            name: name.to_string(),
+            length: "1".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The fields, the descriptor and the unit an edit applies to
+    struct State {
+        fields: Vec<FieldInput>,
+        descriptor: ProtoDescriptor,
+        unit_bits: usize,
+    }
+
+    impl State {
+        /// A (C), B
+        fn new() -> Self {
+            let mut a = field("A");
+            a.children = vec![field("C")];
+            State {
+                fields: vec![a, field("B")],
+                descriptor: ProtoDescriptor::default(),
+                unit_bits: 1,
+            }
+        }
+
+        fn snapshot(&self) -> (Vec<FieldInput>, Value, usize) {
+            (
+                self.fields.clone(),
+                serde_json::to_value(&self.descriptor).unwrap(),
+                self.unit_bits,
+            )
+        }
+
+        fn apply(&mut self, edit: &Edit) {
+            edit.apply(&mut self.fields, &mut self.descriptor, &mut self.unit_bits);
+        }
+
+        fn record(&mut self, history: &mut History, edit: Edit) {
+            history.record(
+                edit,
+                &mut self.fields,
+                &mut self.descriptor,
+                &mut self.unit_bits,
+            );
+        }
+
+        fn record_coalesced(&mut self, history: &mut History, edit: Edit) {
+            history.record_coalesced(
+                edit,
+                &mut self.fields,
+                &mut self.descriptor,
+                &mut self.unit_bits,
+            );
+        }
+
+        fn undo(&mut self, history: &mut History) -> bool {
+            history.undo(&mut self.fields, &mut self.descriptor, &mut self.unit_bits)
+        }
+
+        fn redo(&mut self, history: &mut History) -> bool {
+            history.redo(&mut self.fields, &mut self.descriptor, &mut self.unit_bits)
+        }
+    }
+
+    fn rename(index: FieldPath, old: &str, new: &str) -> Edit {
+        Edit::Rename {
+            index,
+            old: old.to_string(),
+            new: new.to_string(),
+        }
+    }
+
+    #[test]
+    fn inverse_reverts_every_edit() {
+        let state = State::new();
+        let descriptor = &state.descriptor;
+        let edits = [
+            Edit::AddField {
+                index: vec![0, 1],
+                field: field("X"),
+            },
+            Edit::RemoveField {
+                index: vec![0, 0],
+                field: field("C"),
+            },
+            Edit::SwapFields {
+                parent: vec![],
+                a: 0,
+                b: 1,
+            },
+            Edit::ReplaceFields {
+                old: state.fields.clone(),
+                new: vec![field("X")],
+            },
+            rename(vec![0, 0], "C", "D"),
+            Edit::Length {
+                index: vec![1],
+                old: "1".to_string(),
+                new: "N".to_string(),
+            },
+            Edit::Wrap {
+                index: vec![1],
+                old: false,
+                new: true,
+            },
+            Edit::Color {
+                index: vec![0],
+                old: None,
+                new: Some(HexColor::rgb(0xFF, 0, 0)),
+            },
+            Edit::Expand {
+                index: vec![0],
+                old: false,
+                new: true,
+            },
+            Edit::style_color(descriptor, StyleColor::Text, HexColor::rgb(0, 0, 0xFF)),
+            Edit::style_size(descriptor, StyleSize::DynUnits, 5),
+            Edit::element(descriptor, ElementFlag::StartSymbol, false),
+            Edit::Unit { old: 1, new: 8 },
+        ];
+
+        for edit in &edits {
+            let mut state = State::new();
+            let before = state.snapshot();
+            state.apply(edit);
+            assert_ne!(state.snapshot(), before, "{:?}", edit);
+            state.apply(&edit.inverse());
+            assert_eq!(state.snapshot(), before, "{:?}", edit);
+        }
+    }
+
+    #[test]
+    fn ignores_stale_paths() {
+        let edits = [
+            Edit::SwapFields {
+                parent: vec![],
+                a: 0,
+                b: 5,
+            },
+            Edit::SwapFields {
+                parent: vec![0],
+                a: 1,
+                b: 0,
+            },
+            Edit::SwapFields {
+                parent: vec![7],
+                a: 0,
+                b: 1,
+            },
+            Edit::AddField {
+                index: vec![3, 0],
+                field: field("X"),
+            },
+            Edit::RemoveField {
+                index: vec![2],
+                field: field("X"),
+            },
+            rename(vec![0, 3], "X", "Y"),
+            rename(vec![], "X", "Y"),
+        ];
+
+        for edit in &edits {
+            let mut state = State::new();
+            let before = state.snapshot();
+            state.apply(edit);
+            assert_eq!(state.snapshot(), before, "{:?}", edit);
+        }
+    }
+
+    #[test]
+    fn undoes_and_redoes_edits() {
+        let mut state = State::new();
+        let mut history = History::default();
+        assert!(!state.undo(&mut history));
+
+        state.record(&mut history, rename(vec![1], "B", "B"));
+        assert!(!history.can_undo());
+
+        state.record(&mut history, rename(vec![1], "B", "X"));
+        state.record(&mut history, rename(vec![1], "X", "Y"));
+        assert!(state.undo(&mut history));
+        assert_eq!(state.fields[1].name, "X");
+        assert!(state.redo(&mut history));
+        assert_eq!(state.fields[1].name, "Y");
+        assert!(!state.redo(&mut history));
+
+        // A new edit drops the undone ones
+        state.undo(&mut history);
+        state.record(&mut history, rename(vec![0], "A", "Z"));
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn coalesces_slider_edits() {
+        let mut state = State::new();
+        let mut history = History::default();
+        let start = state.descriptor.style.unit_width;
+        let size =
+            |state: &State, new| Edit::style_size(&state.descriptor, StyleSize::UnitWidth, new);
+
+        for width in [60, 70, 80] {
+            let edit = size(&state, width);
+            state.record_coalesced(&mut history, edit);
+        }
+        // Another property makes an entry of its own
+        let edit = Edit::style_size(&state.descriptor, StyleSize::DynUnits, 5);
+        state.record_coalesced(&mut history, edit);
+        history.seal();
+        // So does the next drag of the same slider
+        let edit = size(&state, 90);
+        state.record_coalesced(&mut history, edit);
+        history.seal();
+
+        assert!(state.undo(&mut history));
+        assert_eq!(state.descriptor.style.unit_width, 80);
+        assert!(state.undo(&mut history));
+        assert_eq!(state.descriptor.style.dyn_units, 3);
+        assert!(state.undo(&mut history));
+        assert_eq!(state.descriptor.style.unit_width, start);
+        assert!(!history.can_undo());
+
+        // A drag ending where it started leaves no entry
+        let edit = size(&state, 60);
+        state.record_coalesced(&mut history, edit);
+        let edit = size(&state, start);
+        state.record_coalesced(&mut history, edit);
+        history.seal();
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn keeps_the_last_edits() {
+        let mut state = State::new();
+        let mut history = History::default();
+        let count = MAX_HISTORY + 10;
+        for i in 0..count {
+            let edit = rename(vec![1], &state.fields[1].name, &i.to_string());
+            state.record(&mut history, edit);
+        }
+
+        let mut undone = 0;
+        while state.undo(&mut history) {
+            undone += 1;
+        }
+        assert_eq!(undone, MAX_HISTORY);
+        // The oldest edits can't be undone
+        assert_eq!(state.fields[1].name, (count - MAX_HISTORY - 1).to_string());
+    }
+}
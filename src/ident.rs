@@ -0,0 +1,63 @@
+//! Identifiers derived from field names.
+//!
+//! Field names are free text (`Data Offset`, `TTL`, `ipVersion`). Generated code and Kaitai formats
+//! use them as snake case identifiers, and lengths name other fields loosely: ignoring case,
+//! spaces and punctuation.
+
+/// Convert a field name to a snake case identifier
+pub fn to_ident(name: &str) -> String {
+    let mut ident = String::new();
+    let mut previous_lower = false;
+
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            // Split camel case words
+            if c.is_ascii_uppercase() && previous_lower {
+                ident.push('_');
+            }
+            ident.push(c.to_ascii_lowercase());
+            previous_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        } else {
+            if !ident.ends_with('_') && !ident.is_empty() {
+                ident.push('_');
+            }
+            previous_lower = false;
+        }
+    }
+
+    ident.trim_end_matches('_').to_string()
+}
+
+/// Whether a name refers to the field with the given name, ignoring case and punctuation
+pub fn names_field(name: &str, field_name: &str) -> bool {
+    if field_name.trim().eq_ignore_ascii_case(name.trim()) {
+        return true;
+    }
+    let target = to_ident(name);
+    !target.is_empty() && to_ident(field_name) == target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_names_to_identifiers() {
+        assert_eq!(to_ident("Data Offset"), "data_offset");
+        assert_eq!(to_ident("TTL"), "ttl");
+        assert_eq!(to_ident("ipVersion"), "ip_version");
+        assert_eq!(to_ident("Length (bytes)"), "length_bytes");
+        assert_eq!(to_ident("  802.1Q tag "), "802_1_q_tag");
+        assert_eq!(to_ident("--"), "");
+    }
+
+    #[test]
+    fn matches_field_names_loosely() {
+        assert!(names_field("data offset", "Data Offset"));
+        assert!(names_field("Data-Offset", "Data Offset"));
+        assert!(names_field("dataOffset", "Data Offset"));
+        assert!(names_field(" -- ", "--"));
+        assert!(!names_field("Offset", "Data Offset"));
+        assert!(!names_field("?", "!"));
+    }
+}
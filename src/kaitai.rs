@@ -0,0 +1,565 @@
+//! Kaitai Struct (.ksy) conversion.
+//!
+//! The fields map to the `seq` of the format: byte aligned fields use `uN` types (or a `size` in
+//! bytes), other fields use `bN` bit types and variable fields use a `size` expression over
+//! previous integer fields (`ihl * 4 - 20`). Only the last field may name none of them (`N`) and
+//! use `size-eos`.
+//! Field names that aren't valid ids are kept in `-orig-id`, and the diagram line breaks and free
+//! lengths in `-wrap` and `-length` (keys starting with a dash are ignored by the Kaitai compiler).
+
+use std::collections::HashSet;
+
+use protoviz::descriptor::{FieldDescriptor, FieldLength, ProtoDescriptor};
+use serde::{Deserialize, Serialize};
+use serde_yaml_ng::Value;
+
+use crate::expr::{parse_length, Expr, Op};
+use crate::ident::{names_field, to_ident};
+use crate::length::{bits_length, fixed_bits};
+
+/// Bits in a row of an imported diagram
+const ROW_BITS: usize = 32;
+
+#[derive(Serialize, Deserialize)]
+struct Ksy {
+    #[serde(default)]
+    meta: Meta,
+    #[serde(default)]
+    seq: Vec<Attribute>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Meta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    endian: Option<String>,
+    #[serde(rename = "bit-endian", skip_serializing_if = "Option::is_none")]
+    bit_endian: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Attribute {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    ty: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<Value>,
+    #[serde(rename = "size-eos", skip_serializing_if = "Option::is_none")]
+    size_eos: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    terminator: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contents: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repeat: Option<String>,
+    #[serde(rename = "repeat-expr", skip_serializing_if = "Option::is_none")]
+    repeat_expr: Option<Value>,
+    #[serde(rename = "-orig-id", skip_serializing_if = "Option::is_none")]
+    orig_id: Option<String>,
+    #[serde(rename = "-length", skip_serializing_if = "Option::is_none")]
+    length: Option<String>,
+    #[serde(rename = "-wrap", skip_serializing_if = "Option::is_none")]
+    wrap: Option<bool>,
+}
+
+/// Fields and byte order read from a .ksy file
+pub struct KsyImport {
+    pub fields: Vec<FieldDescriptor>,
+    pub network_order: bool,
+}
+
+/// Create a unique Kaitai id (lower case, starting with a letter)
+fn unique_id(name: &str, fallback: &str, used: &mut HashSet<String>) -> String {
+    let mut id = to_ident(name);
+    if id.is_empty() {
+        id = fallback.to_string();
+    }
+    if id.starts_with(|c: char| c.is_ascii_digit()) {
+        id.insert_str(0, "f_");
+    }
+
+    let mut unique = id.clone();
+    let mut suffix = 2;
+    while used.contains(&unique) {
+        unique = format!("{}_{}", id, suffix);
+        suffix += 1;
+    }
+    used.insert(unique.clone());
+    unique
+}
+
+/// Id of the last integer field with the given name
+fn integer_id<'a>(name: &str, integers: &'a [(&str, String)]) -> Option<&'a str> {
+    integers
+        .iter()
+        .rev()
+        .find(|(field_name, _)| names_field(name, field_name))
+        .map(|(_, id)| id.as_str())
+}
+
+/// Kaitai expression of a parsed length, with the ids of the integer fields it names
+fn ksy_expr(expr: &Expr, integers: &[(&str, String)]) -> String {
+    fn precedence(op: Op) -> u8 {
+        match op {
+            Op::Add | Op::Sub => 0,
+            Op::Mul | Op::Div => 1,
+        }
+    }
+
+    match expr {
+        Expr::Number(number) => number.to_string(),
+        Expr::Field(name) => integer_id(name, integers).unwrap_or(name).to_string(),
+        Expr::Binary { op, lhs, rhs } => {
+            let operand = |operand: &Expr, right: bool| {
+                let text = ksy_expr(operand, integers);
+                match operand {
+                    Expr::Binary { op: inner, .. }
+                        if precedence(*inner) < precedence(*op)
+                            || (right && precedence(*inner) == precedence(*op)) =>
+                    {
+                        format!("({})", text)
+                    }
+                    _ => text,
+                }
+            };
+            let symbol = match op {
+                Op::Add => "+",
+                Op::Sub => "-",
+                Op::Mul => "*",
+                Op::Div => "/",
+            };
+            format!("{} {} {}", operand(lhs, false), symbol, operand(rhs, true))
+        }
+    }
+}
+
+/// Kaitai `size` of a variable length over previous integer fields, or None when it names none of
+/// them. Fails with the first name that isn't a previous integer field when it names some.
+fn size_expr(length: &str, integers: &[(&str, String)]) -> Result<Option<String>, String> {
+    let names: Vec<&str> = integers.iter().map(|(name, _)| *name).collect();
+    let Ok(expr) = parse_length(length, &names) else {
+        return Ok(None);
+    };
+    let (known, unknown): (Vec<&str>, Vec<&str>) = expr
+        .names()
+        .into_iter()
+        .partition(|name| integer_id(name, integers).is_some());
+    match (known.is_empty(), unknown.first()) {
+        (true, _) => Ok(None),
+        (false, Some(name)) => Err(name.to_string()),
+        (false, None) => Ok(Some(ksy_expr(&expr, integers))),
+    }
+}
+
+/// Replace the ids of previous fields in a Kaitai expression with their names
+fn replace_ids(expr: &str, names: &[(String, String)]) -> String {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut out = String::with_capacity(expr.len());
+    let mut rest = expr.trim();
+    while let Some(start) = rest.find(is_word) {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(|c: char| !is_word(c))
+            .map_or(rest.len(), |i| start + i);
+        let word = &rest[start..end];
+        match names.iter().rev().find(|(id, _)| id == word) {
+            Some((_, name)) => out.push_str(name),
+            None => out.push_str(word),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Convert the fields of a descriptor to a .ksy format with the given id
+pub fn render_ksy(
+    descriptor: &ProtoDescriptor,
+    name: &str,
+    unit_bits: usize,
+) -> Result<String, String> {
+    if unit_bits == 0 {
+        return Err("unit size must be positive".to_string());
+    }
+
+    let mut used = HashSet::new();
+    let mut seq = Vec::new();
+    // Ids of the previous integer fields, by field name
+    let mut integers: Vec<(&str, String)> = Vec::new();
+    let mut offset = 0;
+    let mut has_bits = false;
+
+    for (i, field) in descriptor.fields.iter().enumerate() {
+        let id = unique_id(&field.name, &format!("field_{}", i + 1), &mut used);
+        let mut attribute = Attribute {
+            orig_id: (field.name != id).then(|| field.name.clone()),
+            wrap: field.wrap.then_some(true),
+            ..Default::default()
+        };
+
+        match fixed_bits(&field.length, unit_bits) {
+            Some(0) => {
+                return Err(format!("field '{}' has no length", field.name));
+            }
+            Some(bits) => {
+                if offset == 0 && bits % 8 == 0 {
+                    match bits / 8 {
+                        bytes @ (1 | 2 | 4 | 8) => {
+                            attribute.ty = Some(Value::from(format!("u{}", bytes)));
+                            integers.push((&field.name, id.clone()));
+                        }
+                        bytes => attribute.size = Some(Value::from(bytes)),
+                    }
+                } else if bits <= 64 {
+                    attribute.ty = Some(Value::from(format!("b{}", bits)));
+                    integers.push((&field.name, id.clone()));
+                    has_bits = true;
+                } else {
+                    return Err(format!(
+                        "field '{}' is {} bits long and not byte aligned, which Kaitai can't describe",
+                        field.name, bits
+                    ));
+                }
+                offset = (offset + bits) % 8;
+            }
+            None => {
+                let length = field.length.to_string();
+                match size_expr(&length, &integers) {
+                    Ok(Some(size)) => attribute.size = Some(Value::from(size)),
+                    Ok(None) if i + 1 < descriptor.fields.len() => {
+                        return Err(format!(
+                            "the length of field '{}' names no previous integer field, which only the last field may do",
+                            field.name
+                        ));
+                    }
+                    Ok(None) => {
+                        attribute.size_eos = Some(true);
+                        attribute.length = Some(length);
+                    }
+                    Err(name) => {
+                        return Err(format!(
+                            "the length of field '{}' names '{}', which isn't a previous integer field",
+                            field.name, name
+                        ));
+                    }
+                }
+                // Byte sized fields start on a byte boundary
+                offset = 0;
+            }
+        }
+
+        attribute.id = Some(id);
+        seq.push(attribute);
+    }
+
+    let endian = if descriptor.elements.network_order {
+        "be"
+    } else {
+        "le"
+    };
+    let ksy = Ksy {
+        meta: Meta {
+            id: Some(unique_id(name, "frame", &mut HashSet::new())),
+            endian: Some(endian.to_string()),
+            bit_endian: has_bits.then(|| endian.to_string()),
+        },
+        seq,
+    };
+
+    serde_yaml_ng::to_string(&ksy).map_err(|e| format!("failed to serialize the format ({})", e))
+}
+
+/// Size in bits of a built-in Kaitai type, if it has a fixed size
+fn type_bits(ty: &str) -> Option<usize> {
+    if let Some(bits) = ty.strip_prefix('b') {
+        let bits = bits.trim_end_matches("be").trim_end_matches("le");
+        return bits.parse().ok().filter(|bits| (1..=64).contains(bits));
+    }
+
+    let ty = ty
+        .strip_suffix("be")
+        .or_else(|| ty.strip_suffix("le"))
+        .unwrap_or(ty);
+    match ty {
+        "u1" | "s1" => Some(8),
+        "u2" | "s2" => Some(16),
+        "u4" | "s4" | "f4" => Some(32),
+        "u8" | "s8" | "f8" => Some(64),
+        _ => None,
+    }
+}
+
+/// Size in bytes of fixed contents
+fn contents_bytes(contents: &Value) -> Option<usize> {
+    match contents {
+        Value::String(text) => Some(text.len()),
+        Value::Sequence(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::Number(_) => Some(1),
+                Value::String(text) => Some(text.len()),
+                _ => None,
+            })
+            .sum(),
+        _ => None,
+    }
+}
+
+/// Parse the `seq` of a .ksy format into field descriptors
+pub fn parse_ksy(text: &str, unit_bits: usize) -> Result<KsyImport, String> {
+    if unit_bits == 0 {
+        return Err("unit size must be positive".to_string());
+    }
+
+    let ksy: Ksy = serde_yaml_ng::from_str(text).map_err(|e| e.to_string())?;
+    if ksy.seq.is_empty() {
+        return Err("the format has no 'seq' fields".to_string());
+    }
+
+    let network_order = match ksy.meta.endian.as_deref() {
+        Some("le") => false,
+        Some("be") | None => ksy.meta.bit_endian.as_deref() != Some("le"),
+        Some(endian) => return Err(format!("unknown endianness '{}'", endian)),
+    };
+
+    // Field names by id, for size expressions naming a previous field
+    let mut names: Vec<(String, String)> = Vec::new();
+    let mut fields: Vec<FieldDescriptor> = Vec::new();
+    // Bits since the start of the row, to wrap the diagram like an RFC one when the format
+    // doesn't say where
+    let mut row_bits = 0;
+    let has_wraps = ksy.seq.iter().any(|attribute| attribute.wrap.is_some());
+
+    for (i, attribute) in ksy.seq.iter().enumerate() {
+        let name = attribute
+            .orig_id
+            .clone()
+            .or_else(|| attribute.id.clone())
+            .unwrap_or_else(|| format!("Field {}", i + 1));
+        let ty = attribute.ty.as_ref().and_then(|ty| ty.as_str());
+        let expression = |expr: &str| replace_ids(expr, &names);
+        let too_long = || format!("field '{}' is too long", name);
+
+        // Size of a single item, in bits
+        let item = match (&attribute.size, &attribute.contents) {
+            (Some(Value::Number(size)), _) => match size.as_u64() {
+                Some(size) => match usize::try_from(size)
+                    .ok()
+                    .and_then(|size| size.checked_mul(8))
+                {
+                    Some(bits) => Ok(bits),
+                    None => return Err(too_long()),
+                },
+                None => return Err(format!("field '{}' has an invalid size", name)),
+            },
+            (Some(Value::String(expr)), _) => Err(expression(expr)),
+            (Some(_), _) => return Err(format!("field '{}' has an invalid size", name)),
+            (None, Some(contents)) => match contents_bytes(contents) {
+                Some(bytes) => Ok(bytes.checked_mul(8).ok_or_else(too_long)?),
+                None => return Err(format!("field '{}' has invalid contents", name)),
+            },
+            (None, None) if attribute.size_eos == Some(true) => {
+                Err(attribute.length.clone().unwrap_or_else(|| "N".to_string()))
+            }
+            (None, None) if ty == Some("strz") || attribute.terminator.is_some() => {
+                Err("N".to_string())
+            }
+            (None, None) => match ty.and_then(type_bits) {
+                Some(bits) => Ok(bits),
+                None if attribute.ty.is_none() => {
+                    return Err(format!("field '{}' has no type or size", name));
+                }
+                None => {
+                    return Err(format!(
+                        "field '{}' has type '{}', which has no fixed size",
+                        name,
+                        ty.unwrap_or("?")
+                    ));
+                }
+            },
+        };
+
+        let bits = match (attribute.repeat.as_deref(), &attribute.repeat_expr) {
+            (None, _) => item,
+            (Some("expr"), Some(Value::Number(count))) => match count.as_u64() {
+                Some(count) => match item {
+                    Ok(bits) => Ok(usize::try_from(count)
+                        .ok()
+                        .and_then(|count| bits.checked_mul(count))
+                        .ok_or_else(too_long)?),
+                    Err(length) => Err(length),
+                },
+                None => return Err(format!("field '{}' has an invalid repeat count", name)),
+            },
+            (Some("expr"), Some(Value::String(expr))) => Err(expression(expr)),
+            _ => Err("N".to_string()),
+        };
+
+        let length = match &bits {
+            Ok(0) => return Err(format!("field '{}' has no length", name)),
+            Ok(bits) => {
+                row_bits = (row_bits + bits % ROW_BITS) % ROW_BITS;
+                bits_length(*bits, unit_bits)
+            }
+            Err(length) => {
+                row_bits = 0;
+                FieldLength::Variable(length.clone())
+            }
+        };
+
+        let wrap = if has_wraps {
+            attribute.wrap == Some(true)
+        } else {
+            bits.is_ok() && row_bits == 0 && i + 1 < ksy.seq.len()
+        };
+        if let Some(id) = &attribute.id {
+            names.push((id.clone(), name.clone()));
+        }
+        fields.push(FieldDescriptor {
+            name,
+            length,
+            wrap,
+            color: None,
+        });
+    }
+
+    Ok(KsyImport {
+        fields,
+        network_order,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::TEMPLATES;
+
+    fn field(name: &str, length: FieldLength) -> FieldDescriptor {
+        FieldDescriptor {
+            name: name.to_string(),
+            length,
+            wrap: false,
+            color: None,
+        }
+    }
+
+    fn descriptor(fields: Vec<FieldDescriptor>) -> ProtoDescriptor {
+        ProtoDescriptor {
+            fields,
+            ..Default::default()
+        }
+    }
+
+    fn variable(length: &str) -> FieldLength {
+        FieldLength::Variable(length.to_string())
+    }
+
+    #[test]
+    fn round_trips_templates() {
+        for template in TEMPLATES {
+            let descriptor = template.descriptor().unwrap();
+            // Templates with several free lengths (IPv4, TCP) can't be described
+            let Ok(ksy) = render_ksy(&descriptor, template.name, template.unit_bits) else {
+                continue;
+            };
+            let import = parse_ksy(&ksy, template.unit_bits).unwrap();
+
+            assert_eq!(import.network_order, descriptor.elements.network_order);
+            assert_eq!(
+                import.fields.len(),
+                descriptor.fields.len(),
+                "{}",
+                template.name
+            );
+            // Formats without line breaks are wrapped like RFC diagrams
+            let has_wraps = descriptor.fields.iter().any(|field| field.wrap);
+            for (imported, field) in import.fields.iter().zip(&descriptor.fields) {
+                assert_eq!(imported.name, field.name, "{}", template.name);
+                assert_eq!(imported.length, field.length, "{}", template.name);
+                if has_wraps {
+                    assert_eq!(imported.wrap, field.wrap, "{}", template.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exports_length_expressions() {
+        let fields = vec![
+            field("Type", FieldLength::Fixed(1)),
+            field("Header Length", FieldLength::Fixed(1)),
+            field("Options", variable("Header Length * 4 - 20")),
+            field("Data", variable("N")),
+        ];
+        let ksy = render_ksy(&descriptor(fields), "packet", 8).unwrap();
+        assert!(ksy.contains("size: header_length * 4 - 20"), "{}", ksy);
+        assert!(ksy.contains("size-eos: true"), "{}", ksy);
+
+        let import = parse_ksy(&ksy, 8).unwrap();
+        assert_eq!(import.fields[2].length, variable("Header Length * 4 - 20"));
+        assert_eq!(import.fields[3].length, variable("N"));
+    }
+
+    #[test]
+    fn refuses_free_lengths_before_the_last_field() {
+        let fields = vec![
+            field("Type", FieldLength::Fixed(1)),
+            field("Options", variable("N")),
+            field("Data", variable("K")),
+        ];
+        assert_eq!(
+            render_ksy(&descriptor(fields), "packet", 8).err().as_deref(),
+            Some("the length of field 'Options' names no previous integer field, which only the last field may do")
+        );
+
+        let ipv4 = TEMPLATES
+            .iter()
+            .find(|template| template.name == "IPv4")
+            .unwrap();
+        let error = render_ksy(&ipv4.descriptor().unwrap(), "ipv4", ipv4.unit_bits).unwrap_err();
+        assert!(error.contains("'Options'"), "{}", error);
+    }
+
+    #[test]
+    fn keeps_the_precedence_of_expressions() {
+        let integers = [
+            ("Length", "length".to_string()),
+            ("Count", "count".to_string()),
+        ];
+        let size = |length| size_expr(length, &integers).unwrap().unwrap();
+        assert_eq!(size("(Length - 2) * 4"), "(length - 2) * 4");
+        assert_eq!(size("Length - (Count - 1)"), "length - (count - 1)");
+        assert_eq!(size("Length - Count - 1"), "length - count - 1");
+        assert_eq!(size("Length / (Count * 2)"), "length / (count * 2)");
+        assert_eq!(size("0x10 + Count*2"), "16 + count * 2");
+    }
+
+    #[test]
+    fn refuses_lengths_naming_other_fields() {
+        let fields = vec![
+            field("Length", FieldLength::Fixed(2)),
+            field("Data", variable("Length + Padding")),
+            field("Padding", FieldLength::Fixed(1)),
+        ];
+        let error = render_ksy(&descriptor(fields), "packet", 8).unwrap_err();
+        assert!(error.contains("'Padding'"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        let size = "seq:\n  - id: data\n    size: 18446744073709551615\n";
+        assert_eq!(
+            parse_ksy(size, 8).err().as_deref(),
+            Some("field 'data' is too long")
+        );
+
+        let repeat = "seq:\n  - id: data\n    type: u4\n    repeat: expr\n    repeat-expr: 18446744073709551615\n";
+        assert_eq!(
+            parse_ksy(repeat, 8).err().as_deref(),
+            Some("field 'data' is too long")
+        );
+    }
+}
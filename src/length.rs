@@ -0,0 +1,315 @@
+//! Field length syntax.
+//!
+//! Lengths are whole units of the document (`FieldLength::Fixed`, as in the protoviz format) or
+//! bits, written with a `b` suffix (`4b`). A length in bits that is a whole number of units is
+//! saved as units, so the files stay readable by protoviz. Only the lengths shorter than a unit, or
+//! between two units, are kept as `FieldLength::Variable` strings, and drawn inside the units on a
+//! bit ruler.
+
+use protoviz::descriptor::{FieldDescriptor, FieldLength, ProtoDescriptor};
+use protoviz::errors::Error;
+
+use crate::fields::with_fields;
+
+/// Bits in a byte, the unit of sample data
+pub const BYTE_BITS: usize = 8;
+
+/// Smallest unit width accepted by `protoviz::render`
+const MIN_UNIT_WIDTH: usize = 10;
+
+/// Parse a length in bits ("4b")
+pub fn parse_bits(length: &str) -> Option<usize> {
+    let digits = length.trim().strip_suffix(['b', 'B'])?;
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Whether some fields have lengths in bits that aren't a whole number of units
+pub fn has_bit_lengths(fields: &[FieldDescriptor], unit_bits: usize) -> bool {
+    fields.iter().any(|field| match &field.length {
+        FieldLength::Fixed(_) => false,
+        FieldLength::Variable(length) => {
+            parse_bits(length).is_some_and(|bits| bits % unit_bits != 0)
+        }
+    })
+}
+
+/// Number of bits in a unit of fields saved before documents had a unit: they were drawn in bytes
+/// when some fields had lengths in bits, and in bits otherwise
+pub fn legacy_unit_bits(fields: &[FieldDescriptor]) -> usize {
+    if has_bit_lengths(fields, usize::MAX) {
+        BYTE_BITS
+    } else {
+        1
+    }
+}
+
+/// Length of a fixed field in bits, or None for variable fields
+pub fn fixed_bits(length: &FieldLength, unit_bits: usize) -> Option<usize> {
+    match length {
+        FieldLength::Fixed(length) => length.checked_mul(unit_bits),
+        FieldLength::Variable(length) => parse_bits(length),
+    }
+}
+
+/// Length of a field with the given number of bits, in units if it's a whole number of them
+pub fn bits_length(bits: usize, unit_bits: usize) -> FieldLength {
+    if bits % unit_bits == 0 {
+        FieldLength::Fixed(bits / unit_bits)
+    } else {
+        FieldLength::Variable(format!("{}b", bits))
+    }
+}
+
+/// The length, in units if it's a length in bits that is a whole number of them
+pub fn unit_length(length: &FieldLength, unit_bits: usize) -> FieldLength {
+    match length {
+        FieldLength::Variable(text) => match parse_bits(text) {
+            Some(bits) => bits_length(bits, unit_bits),
+            None => length.clone(),
+        },
+        FieldLength::Fixed(_) => length.clone(),
+    }
+}
+
+/// Fields with their lengths in bits in units when they are a whole number of them
+pub fn unit_fields(fields: &[FieldDescriptor], unit_bits: usize) -> Vec<FieldDescriptor> {
+    fields
+        .iter()
+        .map(|field| FieldDescriptor {
+            name: field.name.clone(),
+            length: unit_length(&field.length, unit_bits),
+            wrap: field.wrap,
+            color: field.color,
+        })
+        .collect()
+}
+
+/// Label of a number of bits, in units when it's a whole number of them
+pub fn bits_label(bits: usize, unit_bits: usize) -> String {
+    match (bits / unit_bits, bits % unit_bits) {
+        (units, 0) => units.to_string(),
+        (0, bits) => format!("{}b", bits),
+        (units, bits) => format!("{}+{}b", units, bits),
+    }
+}
+
+/// Replace the bit counts of the length and position subtitles with unit and bit labels
+fn relabel(svg: &str, network_order: bool, unit_bits: usize) -> String {
+    let position_baseline = if network_order { "auto" } else { "hanging" };
+    let mut out = String::with_capacity(svg.len());
+    let mut rest = svg;
+
+    while let Some(start) = rest.find("<text ") {
+        let Some(open_end) = rest[start..].find('>').map(|i| start + i + 1) else {
+            break;
+        };
+        let Some(close) = rest[open_end..].find("</text>").map(|i| open_end + i) else {
+            break;
+        };
+        let tag = &rest[start..open_end];
+        let text = &rest[open_end..close];
+        out.push_str(&rest[..open_end]);
+
+        let baseline = tag
+            .split("dominant-baseline=\"")
+            .nth(1)
+            .and_then(|value| value.split('"').next())
+            .unwrap_or("middle");
+        // Positions start with the fixed offset, followed by the variable lengths
+        let (fixed, variable) = match text.find('+') {
+            Some(i) if baseline == position_baseline => text.split_at(i),
+            _ => (text, ""),
+        };
+        match fixed.parse::<usize>() {
+            Ok(bits) if baseline != "middle" => {
+                out.push_str(&bits_label(bits, unit_bits));
+                out.push_str(variable);
+            }
+            _ => out.push_str(text),
+        }
+
+        rest = &rest[close..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Width of a bit when drawing fields with lengths in bits, given the width of a unit
+pub fn bit_width(unit_width: usize, unit_bits: usize) -> usize {
+    (unit_width / unit_bits).max(MIN_UNIT_WIDTH)
+}
+
+/// Render the descriptor with the given number of bits in a unit, drawing the fields with lengths
+/// in bits inside the units
+pub fn render_svg(descriptor: &ProtoDescriptor, unit_bits: usize) -> Result<String, Error> {
+    let fields = unit_fields(&descriptor.fields, unit_bits);
+    if !has_bit_lengths(&fields, unit_bits) {
+        return protoviz::render(&with_fields(descriptor, fields));
+    }
+
+    // Render with a unit per bit, so the ticks become a bit ruler
+    let fields = fields
+        .iter()
+        .map(|field| FieldDescriptor {
+            name: field.name.clone(),
+            length: match fixed_bits(&field.length, unit_bits) {
+                Some(bits) => FieldLength::Fixed(bits),
+                None => field.length.clone(),
+            },
+            wrap: field.wrap,
+            color: field.color,
+        })
+        .collect();
+    let mut bit_descriptor = with_fields(descriptor, fields);
+    bit_descriptor.style.unit_width = bit_width(descriptor.style.unit_width, unit_bits);
+    bit_descriptor.style.dyn_units = descriptor.style.dyn_units * unit_bits;
+
+    protoviz::render(&bit_descriptor)
+        .map(|svg| relabel(&svg, descriptor.elements.network_order, unit_bits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, length: FieldLength) -> FieldDescriptor {
+        FieldDescriptor {
+            name: name.to_string(),
+            length,
+            wrap: false,
+            color: None,
+        }
+    }
+
+    fn descriptor(fields: Vec<FieldDescriptor>) -> ProtoDescriptor {
+        ProtoDescriptor {
+            fields,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_bits() {
+        assert_eq!(parse_bits("4b"), Some(4));
+        assert_eq!(parse_bits(" 12B "), Some(12));
+        assert_eq!(parse_bits("0b"), Some(0));
+        for length in ["b", "4", "-4b", "4.5b", "N b", "4bb", ""] {
+            assert_eq!(parse_bits(length), None, "{:?}", length);
+        }
+    }
+
+    #[test]
+    fn counts_fixed_bits_in_the_unit() {
+        assert_eq!(fixed_bits(&FieldLength::Fixed(3), 8), Some(24));
+        assert_eq!(fixed_bits(&FieldLength::Fixed(3), 1), Some(3));
+        // Bit lengths don't depend on the unit
+        let nibble = FieldLength::Variable("4b".to_string());
+        assert_eq!(fixed_bits(&nibble, 8), Some(4));
+        assert_eq!(fixed_bits(&nibble, 1), Some(4));
+        assert_eq!(fixed_bits(&FieldLength::Variable("N".to_string()), 8), None);
+        assert_eq!(fixed_bits(&FieldLength::Fixed(usize::MAX), 8), None);
+    }
+
+    #[test]
+    fn stores_whole_units_as_units() {
+        assert_eq!(bits_length(16, 8), FieldLength::Fixed(2));
+        assert_eq!(bits_length(12, 8), FieldLength::Variable("12b".to_string()));
+        assert_eq!(bits_length(12, 1), FieldLength::Fixed(12));
+
+        let fields = unit_fields(
+            &[
+                field("Type", FieldLength::Variable("8b".to_string())),
+                field("Flags", FieldLength::Variable("4b".to_string())),
+                field("Length", FieldLength::Fixed(2)),
+                field("Data", FieldLength::Variable("Length".to_string())),
+            ],
+            8,
+        );
+        let lengths: Vec<_> = fields.into_iter().map(|field| field.length).collect();
+        assert_eq!(
+            lengths,
+            [
+                FieldLength::Fixed(1),
+                FieldLength::Variable("4b".to_string()),
+                FieldLength::Fixed(2),
+                FieldLength::Variable("Length".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn infers_the_unit_of_legacy_fields() {
+        let bits = [field("Version", FieldLength::Fixed(4))];
+        assert_eq!(legacy_unit_bits(&bits), 1);
+        let bytes = [
+            field("Flags", FieldLength::Variable("4b".to_string())),
+            field("Length", FieldLength::Fixed(2)),
+        ];
+        assert_eq!(legacy_unit_bits(&bytes), BYTE_BITS);
+        assert!(has_bit_lengths(&bytes, 8));
+        assert!(!has_bit_lengths(&bytes, 1));
+    }
+
+    #[test]
+    fn labels_bits_in_units() {
+        assert_eq!(bits_label(16, 8), "2");
+        assert_eq!(bits_label(4, 8), "4b");
+        assert_eq!(bits_label(12, 8), "1+4b");
+        assert_eq!(bits_label(5, 1), "5");
+    }
+
+    #[test]
+    fn relabels_subtitles() {
+        let svg = concat!(
+            r#"<text x="1" dominant-baseline="auto">12+N</text>"#,
+            r#"<text x="2" dominant-baseline="hanging">16</text>"#,
+            r#"<text x="3">24</text>"#,
+        );
+        assert_eq!(
+            relabel(svg, true, 8),
+            concat!(
+                r#"<text x="1" dominant-baseline="auto">1+4b+N</text>"#,
+                r#"<text x="2" dominant-baseline="hanging">2</text>"#,
+                r#"<text x="3">24</text>"#,
+            )
+        );
+        // Outside network order, the positions are below the fields
+        assert_eq!(
+            relabel(svg, false, 8),
+            concat!(
+                r#"<text x="1" dominant-baseline="auto">12+N</text>"#,
+                r#"<text x="2" dominant-baseline="hanging">2</text>"#,
+                r#"<text x="3">24</text>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn renders_whole_units_like_protoviz() {
+        let fields = vec![
+            field("Type", FieldLength::Variable("8b".to_string())),
+            field("Length", FieldLength::Fixed(2)),
+        ];
+        let expected = protoviz::render(&descriptor(vec![
+            field("Type", FieldLength::Fixed(1)),
+            field("Length", FieldLength::Fixed(2)),
+        ]))
+        .unwrap();
+        assert_eq!(render_svg(&descriptor(fields), 8).unwrap(), expected);
+    }
+
+    #[test]
+    fn renders_bits_on_a_ruler() {
+        let fields = vec![
+            field("Version", FieldLength::Variable("4b".to_string())),
+            field("Flags", FieldLength::Variable("4b".to_string())),
+            field("Length", FieldLength::Fixed(2)),
+        ];
+        let svg = render_svg(&descriptor(fields), 8).unwrap();
+        assert!(svg.contains(">4b<"), "{}", svg);
+        assert!(svg.contains(">2<"), "{}", svg);
+    }
+}
@@ -0,0 +1,28 @@
+//! Packet diagrams without the web interface.
+//!
+//! The fields and their groups, their rendering to SVG and PNG, and the formats they are read from
+//! and written to. The web app and the `protoviz-web-cli` tool are built on it.
+//!
+//! Nothing here depends on the browser: built without the default `web` feature
+//! (`default-features = false`), the crate compiles for native targets, without the app.
+
+pub mod ascii;
+pub mod clipboard;
+pub mod codegen;
+pub mod diagnostics;
+pub mod document;
+pub mod expr;
+pub mod fields;
+pub mod group;
+pub mod history;
+pub mod ident;
+pub mod kaitai;
+pub mod length;
+pub mod pcap;
+pub mod permalink;
+pub mod raster;
+pub mod sample;
+pub mod session;
+pub mod stack;
+pub mod templates;
+pub mod validate;
@@ -1,10 +1,19 @@
+mod storage;
 mod utils;
 
+use std::sync::Arc;
+
+use dioxus::html::{FileEngine, HasFileData};
 use dioxus::prelude::*;
 use dioxus_free_icons::{
     icons::{
         fa_brands_icons::FaGithub,
-        fa_solid_icons::{FaCaretDown, FaCaretUp, FaPlus, FaX},
+        fa_solid_icons::{
+            FaArrowDown, FaArrowRotateLeft, FaArrowRotateRight, FaArrowUp, FaCaretDown,
+            FaCaretRight, FaCaretUp, FaCircleExclamation, FaClone, FaCopy, FaDropletSlash,
+            FaGripVertical, FaIndent, FaLayerGroup, FaOutdent, FaPaste, FaPen, FaPlus, FaScissors,
+            FaTrash, FaTriangleExclamation, FaTurnDown, FaX,
+        },
     },
     Icon,
 };
@@ -12,44 +21,356 @@ use dioxus_logger::tracing::{error, Level};
 use hex_color::{Display, HexColor};
 use protoviz::descriptor::ProtoDescriptor;
 
-use utils::{create_field_descriptors, download_file, update_field_inputs, update_svg, FieldInput};
+use protoviz_web::{
+    ascii, clipboard, codegen, diagnostics, document, fields, group, history, length, pcap,
+    permalink, raster, sample, session, stack, templates, validate,
+};
+
+use ascii::{parse_ascii, render_ascii, AsciiOptions, ParseError, ROW_WIDTHS};
+use clipboard::JSON_TYPE;
+use codegen::{generate, CodegenOptions, Language};
+use diagnostics::{Diagnostic, DiagnosticKind};
+use document::{default_document, document_value, frame_descriptors, read_document};
+use fields::{default_field_inputs, FieldInput};
+use group::{
+    children_of, duplicate_fields, edit_fields, field_at, field_rows, indent, outdent, FieldPath,
+};
+use history::{Edit, ElementFlag, StyleColor, StyleSize};
+use permalink::{decode_permalink, encode_permalink};
+use length::render_svg;
+use pcap::{parse_capture, Packet};
+use raster::{render_png, RasterOptions, REFERENCE_DPI};
+use sample::{decode_sample, overlay_descriptor, parse_sample, to_hex};
+use session::Session;
+use stack::render_stack;
+use storage::{load_document, save_document};
+use templates::TEMPLATES;
+use utils::{
+    add_clipboard_listener, add_keydown_listener, clear_location_hash, copy_to_clipboard,
+    download_file, get_location_hash, page_url_with_hash, prevent_file_drop, read_from_clipboard,
+    start_row_drag,
+};
+use validate::{validate_fields, Severity};
+
+/// Indentation of the fields in the field list for each level of nesting, in pixels
+const FIELD_INDENT: usize = 24;
 
 fn main() {
     // Init logger
     dioxus_logger::init(Level::INFO).expect("failed to init logger");
     launch(app);
 }
-
 #[component]
 fn app() -> Element {
-    let mut input_fields = use_signal(|| {
-        vec![
-            FieldInput {
-                name: "Field 1".to_string(),
-                length: "2".to_string(),
-                wrap: false,
-                color: None,
-            },
-            FieldInput {
-                name: "Field 2".to_string(),
-                length: "N".to_string(),
-                wrap: false,
-                color: Some(HexColor::rgb(120, 180, 240)),
-            },
-            FieldInput {
-                name: "Field 3".to_string(),
-                length: "1".to_string(),
-                wrap: false,
-                color: Some(HexColor::rgb(240, 180, 120)),
-            },
-        ]
+    let mut notice = use_signal(|| None::<String>);
+    // The document being edited, with the fields and history of the frame shown and the selection
+    let mut session = use_signal(|| {
+        let mut linked = None;
+        if let Some(hash) = get_location_hash() {
+            clear_location_hash();
+            match decode_permalink(&hash) {
+                Ok(document) => linked = Some(document),
+                Err(e) => {
+                    error!("Failed to decode link: {}", e);
+                    notice.set(Some(format!("Couldn't open the shared link: {}", e)));
+                }
+            }
+        }
+
+        let saved = match load_document() {
+            Ok(saved) => saved,
+            Err(e) => {
+                error!("Failed to restore document: {}", e);
+                notice.set(Some(format!("Couldn't restore the previous document: {}", e)));
+                None
+            }
+        };
+
+        // A shared link takes precedence over the autosaved document, which is replaced by the
+        // next autosave, so the user is asked before losing their own work
+        let document = match (linked, saved) {
+            (Some(linked), Some(saved)) if !saved.is_default() => {
+                if gloo_dialogs::confirm("Open the shared link? It replaces the document you were editing.") {
+                    linked
+                } else {
+                    saved
+                }
+            }
+            (Some(linked), _) => linked,
+            (None, saved) => saved.unwrap_or_else(default_document),
+        };
+        Session::new(document)
+    });
+    let mut file_opened = use_signal(String::new);
+    let mut show_templates = use_signal(|| false);
+    let mut show_png_options = use_signal(|| false);
+    let mut png_scale = use_signal(|| "2".to_string());
+    let mut png_dpi = use_signal(|| 300.0);
+    let mut png_transparent = use_signal(|| false);
+    let mut show_ascii_options = use_signal(|| false);
+    let mut ascii_row_bits = use_signal(|| 32usize);
+    let mut show_codegen_options = use_signal(|| false);
+    let mut codegen_language = use_signal(|| Language::C);
+    let mut codegen_name = use_signal(|| CodegenOptions::default().name);
+    let mut show_code_preview = use_signal(|| false);
+    let mut show_sample_data = use_signal(|| false);
+    let mut stack_view = use_signal(|| false);
+    // Row of the field list being dragged, and the row it is over
+    let mut dragged_row = use_signal(|| None::<usize>);
+    let mut drop_row = use_signal(|| None::<usize>);
+    let mut sample_text = use_signal(String::new);
+    let mut capture = use_signal(Vec::<Packet>::new);
+    let mut capture_packet = use_signal(|| 0usize);
+    let mut capture_offset = use_signal(|| 0usize);
+    let mut show_ascii_import = use_signal(|| false);
+    let mut ascii_import_text = use_signal(String::new);
+    let mut ascii_import_error = use_signal(|| None::<ParseError>);
+
+    // Show a problem in the diagnostics panel
+    let mut report = move |kind: DiagnosticKind, message: String| {
+        session.write().report(kind, message);
+    };
+
+    // Warn that the names too long for their boxes won't read back from an ASCII diagram
+    let mut report_truncated = move |names: &[String]| {
+        if !names.is_empty() {
+            report(
+                DiagnosticKind::Export,
+                format!("Names shortened to fit the ASCII diagram: {}", names.join(", ")),
+            );
+        }
+    };
+
+    // Parse the pasted ASCII diagram, replacing or appending to the current fields
+    let mut import_ascii = move |append: bool| {
+        let unit_bits = session.read().unit_bits;
+        match parse_ascii(&ascii_import_text.read(), unit_bits) {
+            Ok(fields) => {
+                ascii_import_error.set(None);
+                show_ascii_import.set(false);
+                session.write().import_fields(&fields, append);
+            }
+            Err(e) => ascii_import_error.set(Some(e)),
+        }
+    };
+
+    // Show a packet of the opened capture as the sample data, from the given offset
+    let mut select_packet = move |index: usize, offset: usize| {
+        let capture = capture.read();
+        let Some(packet) = capture.get(index) else {
+            return;
+        };
+        let offset = offset.min(packet.data.len());
+        capture_packet.set(index);
+        capture_offset.set(offset);
+        sample_text.set(to_hex(&packet.data[offset..]));
+    };
+
+    // Open a descriptor (.json), a Kaitai format (.ksy) or a capture (.pcap, .pcapng)
+    let open_file = move |files: Arc<dyn FileEngine>| async move {
+        let Some(file_name) = files.files().first().cloned() else {
+            return;
+        };
+
+        if [".pcap", ".pcapng", ".cap"].iter().any(|extension| file_name.ends_with(extension)) {
+            let packets = match files.read_file(&file_name).await {
+                Some(file) => parse_capture(&file),
+                None => Err("failed to read the file".to_string()),
+            };
+            match packets {
+                Ok(packets) => {
+                    capture.set(packets);
+                    select_packet(0, 0);
+                    show_sample_data.set(true);
+                }
+                Err(e) => {
+                    report(DiagnosticKind::Parse, format!("Failed to open capture: {}", e));
+                }
+            }
+            return;
+        }
+
+        let Some(file) = files.read_file_to_string(&file_name).await else {
+            return;
+        };
+        let unit_bits = session.read().unit_bits;
+        match read_document(&file_name, &file, unit_bits) {
+            Ok(document) => session.write().open(document),
+            Err(e) => report(DiagnosticKind::Parse, format!("Failed to parse file: {}", e)),
+        }
+    };
+
+    // Autosave the document on every change
+    use_effect(move || {
+        let session = session.read();
+        save_document(
+            &session.descriptor,
+            &session.frames,
+            session.active,
+            &session.fields,
+            session.unit_bits,
+        );
     });
 
-    let mut file_opened = use_signal(|| String::new());
-    let mut descriptor = use_signal(|| ProtoDescriptor::default());
-    let mut svg_data = use_signal(|| {
-        descriptor.write().fields = create_field_descriptors(&input_fields.read());
-        update_svg(&descriptor.read())
+    // Files dropped on the page are opened instead of replacing it
+    use_hook(prevent_file_drop);
+
+    // Ctrl+Z / Ctrl+Shift+Z (and Ctrl+Y) shortcuts
+    use_hook(move || {
+        add_keydown_listener(move |evt| {
+            if !(evt.ctrl_key() || evt.meta_key()) {
+                return;
+            }
+
+            match evt.key().to_lowercase().as_str() {
+                "z" if evt.shift_key() => session.write().redo(),
+                "z" => session.write().undo(),
+                "y" => session.write().redo(),
+                _ => return,
+            };
+            evt.prevent_default();
+        })
+    });
+
+    // Generated code shown next to the diagram, kept up to date with the fields
+    let code_preview = if *show_code_preview.read() {
+        let options = CodegenOptions {
+            name: codegen_name.read().clone(),
+            unit_bits: session.read().unit_bits,
+        };
+        generate(&session.read().descriptor, *codegen_language.read(), &options)
+            .unwrap_or_else(|e| format!("Failed to generate code: {}", e))
+    } else {
+        String::new()
+    };
+
+    // Sample data decoded against the fields, with the values shown in the diagram
+    let sample = if *show_sample_data.read() && !sample_text.read().trim().is_empty() {
+        Some(parse_sample(&sample_text.read()).map(|data| {
+            let session = session.read();
+            decode_sample(&session.descriptor, &data, session.unit_bits)
+        }))
+    } else {
+        None
+    };
+    let header_offsets = capture
+        .read()
+        .get(*capture_packet.read())
+        .map(Packet::header_offsets)
+        .unwrap_or_default();
+    // Diagram shown and exported: the frames as a protocol stack in the stack view, or the frame
+    let diagram_svg = move || -> Result<String, String> {
+        let session = session.read();
+        if !*stack_view.read() {
+            return Ok(session.svg.clone());
+        }
+        let descriptors = frame_descriptors(&session.descriptor, &session.frames, session.active);
+        let layers: Vec<(&str, &ProtoDescriptor)> = session
+            .frames
+            .iter()
+            .map(|frame| frame.name.as_str())
+            .zip(&descriptors)
+            .collect();
+        render_stack(&layers, session.unit_bits)
+    };
+    let mut stack_problem = None;
+    let viewport_svg = match &sample {
+        _ if *stack_view.read() => diagram_svg().unwrap_or_else(|e| {
+            stack_problem = Some(e);
+            String::new()
+        }),
+        // The sample values are drawn in the frame view only
+        Some(Ok(decode)) if !session.read().descriptor.fields.is_empty() => {
+            let session = session.read();
+            render_svg(&overlay_descriptor(&session.descriptor, decode), session.unit_bits).unwrap_or_else(|e| {
+                error!("Failed to render sample values: {}", e);
+                session.svg.clone()
+            })
+        }
+        _ => session.read().svg.clone(),
+    };
+
+    // Problems of all the fields, counted in the header of the field list
+    let problems = {
+        let session = session.read();
+        validate_fields(&session.descriptor, &session.fields, session.unit_bits)
+    };
+    let error_count = problems.iter().filter(|(_, problem)| problem.severity == Severity::Error).count();
+    let warning_count = problems.len() - error_count;
+    // Rows of the field list: the path of the field, the field, the number of fields next to it,
+    // and its errors and warnings, shown next to it
+    let field_list = {
+        let session = session.read();
+        let input_fields = &session.fields;
+        field_rows(input_fields)
+            .into_iter()
+            .filter_map(|index| {
+                let (&i, parent) = index.split_last()?;
+                let siblings = children_of(input_fields, parent)?;
+                let messages = |severity| {
+                    problems
+                        .iter()
+                        .filter(|(path, problem)| *path == index && problem.severity == severity)
+                        .map(|(_, problem)| problem.message.clone())
+                        .collect()
+                };
+                let (errors, warnings) = (messages(Severity::Error), messages(Severity::Warning));
+                Some((index.clone(), siblings[i].clone(), siblings.len(), errors, warnings))
+            })
+            .collect::<Vec<(FieldPath, FieldInput, usize, Vec<String>, Vec<String>)>>()
+    };
+    // Problems to show, with the name of the field causing them
+    let diagnostic_list: Vec<(Diagnostic, Option<String>)> = {
+        let session = session.read();
+        session
+            .diagnostics
+            .list()
+            .iter()
+            .map(|diagnostic| {
+                let field = diagnostic.field.as_deref().and_then(|path| field_at(&session.fields, path));
+                (diagnostic.clone(), field.map(|field| field.name.clone()))
+            })
+            .collect()
+    };
+
+    // Ctrl+C / Ctrl+X / Ctrl+V shortcuts for the selected fields, with the JSON of the fields and
+    // their text for other applications
+    use_hook(move || {
+        let mut copy = move |evt: &web_sys::ClipboardEvent| {
+            let Some(data) = evt.clipboard_data() else {
+                return false;
+            };
+            let Some((json, text)) = session.write().copy_selection() else {
+                return false;
+            };
+            if let Err(e) = data.set_data(JSON_TYPE, &json).and(data.set_data("text/plain", &text)) {
+                report(DiagnosticKind::Export, format!("Failed to copy fields: {:?}", e));
+                return false;
+            }
+            evt.prevent_default();
+            true
+        };
+        add_clipboard_listener("copy", move |evt| {
+            copy(evt);
+        });
+        add_clipboard_listener("cut", move |evt| {
+            if copy(evt) {
+                session.write().delete_selection();
+            }
+        });
+        add_clipboard_listener("paste", move |evt| {
+            let Some(data) = evt.clipboard_data() else {
+                return;
+            };
+            let text = match data.get_data(JSON_TYPE) {
+                Ok(json) if !json.is_empty() => json,
+                _ => data.get_data("text/plain").unwrap_or_default(),
+            };
+            if !text.trim().is_empty() {
+                evt.prevent_default();
+                session.write().paste(&text);
+            }
+        })
     });
 
     rsx! {
@@ -59,38 +380,127 @@ fn app() -> Element {
                 "ProtoViz"
             },
             div { class: "header_left",
+                button { class: "button button_header",
+                    onclick: move |_| {
+                        if !gloo_dialogs::confirm("Discard the current document and start a new one?") {
+                            return;
+                        }
+
+                        session.write().open(default_document());
+                    },
+                    "New"
+                },
+                div { class: "menu_anchor",
+                    button { class: "button button_header",
+                        onclick: move |_| {
+                            let shown = *show_templates.read();
+                            show_templates.set(!shown);
+                        },
+                        "Templates"
+                    },
+                    if *show_templates.read() {
+                        div { class: "menu",
+                            for template in TEMPLATES.iter() {
+                                div { class: "row list_row",
+                                    span { class: "menu_label", "{template.name}" },
+                                    button { class: "button",
+                                        onclick: move |_| {
+                                            show_templates.set(false);
+                                            let unit_bits = session.read().unit_bits;
+                                            match template.fields(unit_bits) {
+                                                Ok(template_fields) => session.write().import_fields(&template_fields, false),
+                                                Err(e) => {
+                                                    report(DiagnosticKind::Parse, format!("Failed to load template: {}", e));
+                                                }
+                                            }
+                                        },
+                                        "Open"
+                                    },
+                                    button { class: "button",
+                                        onclick: move |_| {
+                                            show_templates.set(false);
+                                            let unit_bits = session.read().unit_bits;
+                                            match template.fields(unit_bits) {
+                                                Ok(template_fields) => session.write().import_fields(&template_fields, true),
+                                                Err(e) => {
+                                                    report(DiagnosticKind::Parse, format!("Failed to load template: {}", e));
+                                                }
+                                            }
+                                        },
+                                        "Append"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                div { class: "menu_anchor",
+                    button { class: "button button_header",
+                        onclick: move |_| {
+                            let shown = *show_ascii_import.read();
+                            show_ascii_import.set(!shown);
+                        },
+                        "Import ASCII"
+                    },
+                    if *show_ascii_import.read() {
+                        div { class: "menu menu_wide",
+                            textarea { class: "ascii_input",
+                                name: "ascii_import",
+                                placeholder: "Paste an RFC-style diagram (+-+-+ borders, two columns per bit)",
+                                spellcheck: false,
+                                value: "{ascii_import_text}",
+                                oninput: move |evt| {
+                                    ascii_import_text.set(evt.value());
+                                    ascii_import_error.set(None);
+                                },
+                            },
+                            if let Some(e) = ascii_import_error.read().as_ref() {
+                                div { class: "parse_error",
+                                    span { "Line {e.line}, column {e.column}: {e.message}" },
+                                    pre { "{e.snippet(&ascii_import_text.read())}" }
+                                }
+                            },
+                            div { class: "row list_row",
+                                button { class: "button",
+                                    onclick: move |_| import_ascii(false),
+                                    "Replace Fields"
+                                },
+                                button { class: "button",
+                                    onclick: move |_| import_ascii(true),
+                                    "Append Fields"
+                                }
+                            }
+                        }
+                    }
+                },
+                button { class: "button button_header",
+                    onclick: move |_| {
+                        let shown = *stack_view.read();
+                        stack_view.set(!shown);
+                    },
+                    if *stack_view.read() { "Frame View" } else { "Stack View" }
+                },
+                button { class: "button button_header",
+                    onclick: move |_| {
+                        let shown = *show_sample_data.read();
+                        show_sample_data.set(!shown);
+                    },
+                    "Sample Data"
+                },
                 label { r#for: "file-open", class: "button button_header",
                     "Open"
                 },
                 input {
                     id: "file-open",
                     r#type: "file",
-                    accept: ".json",
+                    accept: ".json,.ksy,.pcap,.pcapng,.cap",
                     multiple: false,
                     value: "{*file_opened.read()}",
                     onchange: move |evt| {
                         *file_opened.write() = evt.value();
                         async move {
                             if let Some(files) = evt.files() {
-                                match files.files().iter().next() {
-                                    Some(file_name) => {
-                                        if let Some(file) = files.read_file_to_string(file_name).await {
-                                            let new_descriptor: ProtoDescriptor = match serde_json::from_str(&file) {
-                                                Ok(descriptor) => descriptor,
-                                                Err(e) => {
-                                                    error!("Failed to parse file: {}", e);
-                                                    gloo_dialogs::alert("Failed to parse file");
-                                                    return;
-                                                }
-                                            };
-
-                                            *descriptor.write() = new_descriptor;
-                                            *input_fields.write() = update_field_inputs(&descriptor.read().fields);
-                                            *svg_data.write() = update_svg(&descriptor.read());
-                                        }
-                                    },
-                                    None => {}
-                                }
+                                open_file(files).await;
                             }
                             *file_opened.write() = String::new();
                         }
@@ -98,34 +508,359 @@ fn app() -> Element {
                 }
                 button { class: "button button_header",
                     onclick: move |_| {
-                        match serde_json::to_string_pretty(&*descriptor.read()) {
+                        let document = {
+                            let session = session.read();
+                            document_value(&session.descriptor, &session.frames, session.active, &session.fields, session.unit_bits)
+                        };
+                        match document.and_then(|document| serde_json::to_string_pretty(&document)) {
                             Ok(json) => {
                                 let cur_date = chrono::Local::now();
                                 let file_name = cur_date.format("protoviz_%Y-%m-%d_%H-%M-%S.json").to_string();
                                 if !download_file(json.as_bytes(), &file_name, "application/json") {
-                                    gloo_dialogs::alert("Failed to download file");
+                                    report(DiagnosticKind::Export, "Failed to download file".to_string());
                                 }
                             },
                             Err(e) => {
-                                error!("Failed to serialize descriptor: {}", e);
-                                gloo_dialogs::alert("Failed to create json");
+                                report(DiagnosticKind::Export, format!("Failed to serialize document: {}", e));
                             }
                         }
                     },
                     "Save"
                 }
+                button { class: "button circle_button",
+                    title: "Undo (Ctrl+Z)",
+                    disabled: !session.read().history.can_undo(),
+                    onclick: move |_| {
+                        session.write().undo();
+                    },
+                    Icon {
+                        width: 15,
+                        height: 15,
+                        icon: FaArrowRotateLeft,
+                    }
+                }
+                button { class: "button circle_button",
+                    title: "Redo (Ctrl+Shift+Z)",
+                    disabled: !session.read().history.can_redo(),
+                    onclick: move |_| {
+                        session.write().redo();
+                    },
+                    Icon {
+                        width: 15,
+                        height: 15,
+                        icon: FaArrowRotateRight,
+                    }
+                }
             },
             div { class: "header_right",
+                button { class: "button button_header",
+                    onclick: move |_| {
+                        let link = {
+                            let session = session.read();
+                            encode_permalink(&session.descriptor, &session.frames, session.active, &session.fields, session.unit_bits)
+                        }.map(|hash| page_url_with_hash(&hash));
+                        async move {
+                            match link {
+                                Ok(Some(link)) => {
+                                    if copy_to_clipboard(&link).await {
+                                        notice.set(Some("Link copied to the clipboard".to_string()));
+                                    } else {
+                                        report(DiagnosticKind::Export, "Failed to copy link".to_string());
+                                    }
+                                },
+                                Ok(None) => report(DiagnosticKind::Export, "Failed to create link".to_string()),
+                                Err(e) => {
+                                    report(DiagnosticKind::Export, format!("Failed to create link: {}", e));
+                                }
+                            }
+                        }
+                    },
+                    "Copy Link"
+                },
                 button { class: "button button_header",
                     onclick: move |_| {
                         let cur_date = chrono::Local::now();
                         let file_name = cur_date.format("protoviz_%Y-%m-%d_%H-%M-%S.svg").to_string();
-                        if !download_file(svg_data.read().as_bytes(), &file_name, "image/svg+xml") {
-                            gloo_dialogs::alert("Failed to download file");
+                        match diagram_svg() {
+                            Ok(svg) => {
+                                if !download_file(svg.as_bytes(), &file_name, "image/svg+xml") {
+                                    report(DiagnosticKind::Export, "Failed to download file".to_string());
+                                }
+                            },
+                            Err(e) => {
+                                report(DiagnosticKind::Export, format!("Failed to render stack: {}", e));
+                            }
                         }
                     },
                     "Export SVG"
                 },
+                div { class: "menu_anchor",
+                    button { class: "button button_header",
+                        onclick: move |_| {
+                            let shown = *show_png_options.read();
+                            show_png_options.set(!shown);
+                        },
+                        "Export PNG"
+                    },
+                    if *show_png_options.read() {
+                        div { class: "menu menu_right",
+                            div { class: "row list_row",
+                                label { r#for: "png_scale", "Scale" },
+                                select {
+                                    name: "png_scale",
+                                    value: "{png_scale}",
+                                    onchange: move |evt| png_scale.set(evt.value()),
+                                    option { value: "1", "1x" },
+                                    option { value: "2", "2x" },
+                                    option { value: "4", "4x" },
+                                    option { value: "dpi", "Custom DPI" },
+                                }
+                            },
+                            if *png_scale.read() == "dpi" {
+                                div { class: "row list_row",
+                                    label { r#for: "png_dpi", "DPI" },
+                                    input { class: "text_entry",
+                                        r#type: "number",
+                                        name: "png_dpi",
+                                        min: "24",
+                                        max: "1200",
+                                        value: "{png_dpi}",
+                                        onchange: move |evt| {
+                                            if let Ok(dpi) = evt.value().parse::<f64>() {
+                                                png_dpi.set(dpi);
+                                            }
+                                        }
+                                    }
+                                }
+                            },
+                            div { class: "row list_row",
+                                label { r#for: "png_transparent", "Transparent Background" },
+                                input { r#type: "checkbox",
+                                    name: "png_transparent",
+                                    checked: *png_transparent.read(),
+                                    oninput: move |evt| png_transparent.set(evt.checked()),
+                                }
+                            },
+                            button { class: "button",
+                                onclick: move |_| {
+                                    show_png_options.set(false);
+
+                                    let scale = match png_scale.read().as_str() {
+                                        "dpi" => *png_dpi.read() / REFERENCE_DPI,
+                                        scale => scale.parse().unwrap_or(1.0),
+                                    };
+                                    let options = RasterOptions {
+                                        scale,
+                                        background: if *png_transparent.read() {
+                                            None
+                                        } else {
+                                            Some(session.read().descriptor.style.background_color)
+                                        },
+                                    };
+
+                                    let svg = match diagram_svg() {
+                                        Ok(svg) => svg,
+                                        Err(e) => {
+                                            report(DiagnosticKind::Export, format!("Failed to render stack: {}", e));
+                                            return;
+                                        }
+                                    };
+                                    match render_png(&svg, &options) {
+                                        Ok(png) => {
+                                            let cur_date = chrono::Local::now();
+                                            let file_name = cur_date.format("protoviz_%Y-%m-%d_%H-%M-%S.png").to_string();
+                                            if !download_file(&png, &file_name, "image/png") {
+                                                report(DiagnosticKind::Export, "Failed to download file".to_string());
+                                            }
+                                        },
+                                        Err(e) => {
+                                            report(DiagnosticKind::Export, format!("Failed to render PNG: {}", e));
+                                        }
+                                    }
+                                },
+                                "Download"
+                            }
+                        }
+                    }
+                },
+                div { class: "menu_anchor",
+                    button { class: "button button_header",
+                        onclick: move |_| {
+                            let shown = *show_ascii_options.read();
+                            show_ascii_options.set(!shown);
+                        },
+                        "Export ASCII"
+                    },
+                    if *show_ascii_options.read() {
+                        div { class: "menu menu_right",
+                            div { class: "row list_row",
+                                label { r#for: "ascii_row_bits", "Row Width" },
+                                select {
+                                    name: "ascii_row_bits",
+                                    value: "{ascii_row_bits}",
+                                    onchange: move |evt| {
+                                        if let Ok(row_bits) = evt.value().parse() {
+                                            ascii_row_bits.set(row_bits);
+                                        }
+                                    },
+                                    for row_bits in ROW_WIDTHS {
+                                        option { value: "{row_bits}", "{row_bits} bits" }
+                                    }
+                                }
+                            },
+                            button { class: "button",
+                                onclick: move |_| {
+                                    show_ascii_options.set(false);
+
+                                    let options = AsciiOptions {
+                                        row_bits: *ascii_row_bits.read(),
+                                        unit_bits: session.read().unit_bits,
+                                    };
+                                    let ascii = render_ascii(&session.read().descriptor, &options);
+                                    match ascii {
+                                        Ok(ascii) => {
+                                            report_truncated(&ascii.truncated);
+                                            let cur_date = chrono::Local::now();
+                                            let file_name = cur_date.format("protoviz_%Y-%m-%d_%H-%M-%S.txt").to_string();
+                                            if !download_file(ascii.text.as_bytes(), &file_name, "text/plain") {
+                                                report(DiagnosticKind::Export, "Failed to download file".to_string());
+                                            }
+                                        },
+                                        Err(e) => {
+                                            report(DiagnosticKind::Export, format!("Failed to render ASCII: {}", e));
+                                        }
+                                    }
+                                },
+                                "Download"
+                            },
+                            button { class: "button",
+                                onclick: move |_| {
+                                    show_ascii_options.set(false);
+
+                                    let options = AsciiOptions {
+                                        row_bits: *ascii_row_bits.read(),
+                                        unit_bits: session.read().unit_bits,
+                                    };
+                                    let ascii = render_ascii(&session.read().descriptor, &options);
+                                    async move {
+                                        match ascii {
+                                            Ok(ascii) => {
+                                                report_truncated(&ascii.truncated);
+                                                if copy_to_clipboard(&ascii.text).await {
+                                                    notice.set(Some("Diagram copied to the clipboard".to_string()));
+                                                } else {
+                                                    report(DiagnosticKind::Export, "Failed to copy diagram".to_string());
+                                                }
+                                            },
+                                            Err(e) => {
+                                                report(DiagnosticKind::Export, format!("Failed to render ASCII: {}", e));
+                                            }
+                                        }
+                                    }
+                                },
+                                "Copy"
+                            }
+                        }
+                    }
+                },
+                div { class: "menu_anchor",
+                    button { class: "button button_header",
+                        onclick: move |_| {
+                            let shown = *show_codegen_options.read();
+                            show_codegen_options.set(!shown);
+                        },
+                        "Generate Code"
+                    },
+                    if *show_codegen_options.read() {
+                        div { class: "menu menu_right",
+                            div { class: "row list_row",
+                                label { r#for: "codegen_language", "Language" },
+                                select {
+                                    name: "codegen_language",
+                                    value: "{codegen_language.read().name()}",
+                                    onchange: move |evt| {
+                                        if let Some(language) = Language::from_name(&evt.value()) {
+                                            codegen_language.set(language);
+                                        }
+                                    },
+                                    for language in Language::ALL {
+                                        option { value: "{language.name()}", "{language.name()}" }
+                                    }
+                                }
+                            },
+                            div { class: "row list_row",
+                                label { r#for: "codegen_name", "Type Name" },
+                                input { class: "text_entry",
+                                    name: "codegen_name",
+                                    value: "{codegen_name}",
+                                    oninput: move |evt| codegen_name.set(evt.value()),
+                                }
+                            },
+                            button { class: "button",
+                                onclick: move |_| {
+                                    show_codegen_options.set(false);
+
+                                    let language = *codegen_language.read();
+                                    let options = CodegenOptions {
+                                        name: codegen_name.read().clone(),
+                                        unit_bits: session.read().unit_bits,
+                                    };
+                                    let code = generate(&session.read().descriptor, language, &options);
+                                    match code {
+                                        Ok(code) => {
+                                            let cur_date = chrono::Local::now();
+                                            let file_name = format!(
+                                                "{}.{}",
+                                                cur_date.format("protoviz_%Y-%m-%d_%H-%M-%S"),
+                                                language.extension()
+                                            );
+                                            if !download_file(code.as_bytes(), &file_name, "text/plain") {
+                                                report(DiagnosticKind::Export, "Failed to download file".to_string());
+                                            }
+                                        },
+                                        Err(e) => {
+                                            report(DiagnosticKind::Export, format!("Failed to generate code: {}", e));
+                                        }
+                                    }
+                                },
+                                "Download"
+                            },
+                            button { class: "button",
+                                onclick: move |_| {
+                                    show_codegen_options.set(false);
+
+                                    let options = CodegenOptions {
+                                        name: codegen_name.read().clone(),
+                                        unit_bits: session.read().unit_bits,
+                                    };
+                                    let code = generate(&session.read().descriptor, *codegen_language.read(), &options);
+                                    async move {
+                                        match code {
+                                            Ok(code) => {
+                                                if copy_to_clipboard(&code).await {
+                                                    notice.set(Some("Code copied to the clipboard".to_string()));
+                                                } else {
+                                                    report(DiagnosticKind::Export, "Failed to copy code".to_string());
+                                                }
+                                            },
+                                            Err(e) => {
+                                                report(DiagnosticKind::Export, format!("Failed to generate code: {}", e));
+                                            }
+                                        }
+                                    }
+                                },
+                                "Copy"
+                            },
+                            button { class: "button",
+                                onclick: move |_| {
+                                    show_codegen_options.set(false);
+                                    show_code_preview.set(true);
+                                },
+                                "Preview"
+                            }
+                        }
+                    }
+                },
                 a { class: "icon_link",
                     href: "https://github.com/danielstuart14/protoviz_web",
                     target: "_blank",
@@ -137,33 +872,392 @@ fn app() -> Element {
                 }
             }
         }
+        if let Some(message) = notice.read().as_ref() {
+            div { class: "notice",
+                span { "{message}" },
+                button { class: "button circle_button",
+                    onclick: move |_| notice.set(None),
+                    Icon {
+                        width: 10,
+                        height: 10,
+                        icon: FaX,
+                    }
+                }
+            }
+        }
+        if !diagnostic_list.is_empty() {
+            div { class: "diagnostics",
+                for (i, (diagnostic, field)) in diagnostic_list.into_iter().enumerate() {
+                    div { class: "diagnostic",
+                        span { class: "diagnostic_kind", "{diagnostic.kind.name()}" },
+                        span { class: "diagnostic_message", "{diagnostic.message}" },
+                        if let (Some(path), Some(name)) = (diagnostic.field, field) {
+                            button { class: "button diagnostic_field",
+                                title: "Select the field",
+                                onclick: move |_| session.write().selection = vec![path.clone()],
+                                "{name}"
+                            }
+                        },
+                        button { class: "button circle_button",
+                            title: "Dismiss",
+                            onclick: move |_| session.write().diagnostics.dismiss(i),
+                            Icon {
+                                width: 10,
+                                height: 10,
+                                icon: FaX,
+                            }
+                        }
+                    }
+                }
+            }
+        }
         div { class: "row",
+            ondrop: move |evt| async move {
+                if let Some(files) = evt.files() {
+                    open_file(files).await;
+                }
+            },
             div { class: "column left_column",
+                div { class: "header tab frame_tabs",
+                    div { class: "frame_tab_list",
+                        for (i, frame) in session.read().frames.iter().enumerate() {
+                            button {
+                                class: if i == session.read().active { "button frame_tab frame_tab_active" } else { "button frame_tab" },
+                                onclick: move |_| session.write().show_frame(i),
+                                "{frame.name}"
+                            }
+                        }
+                    },
+                    div { class: "frame_actions",
+                        button { class: "button circle_button",
+                            title: "Add Frame",
+                            onclick: move |_| {
+                                let name = format!("Frame {}", session.read().frames.len() + 1);
+                                session.write().add_frame(&name, default_field_inputs());
+                            },
+                            Icon {
+                                width: 12,
+                                height: 12,
+                                icon: FaPlus,
+                            }
+                        },
+                        button { class: "button circle_button",
+                            title: "Duplicate Frame",
+                            onclick: move |_| {
+                                let mut session = session.write();
+                                let name = format!("{} copy", session.frame_name());
+                                let fields = session.fields.clone();
+                                session.add_frame(&name, fields);
+                            },
+                            Icon {
+                                width: 12,
+                                height: 12,
+                                icon: FaCopy,
+                            }
+                        },
+                        button { class: "button circle_button",
+                            title: "Rename Frame",
+                            onclick: move |_| {
+                                let current = session.read().frame_name().to_string();
+                                if let Some(name) = gloo_dialogs::prompt("Frame name", Some(&current)) {
+                                    session.write().rename_frame(&name);
+                                }
+                            },
+                            Icon {
+                                width: 12,
+                                height: 12,
+                                icon: FaPen,
+                            }
+                        },
+                        button { class: "button circle_button",
+                            title: "Delete Frame",
+                            disabled: session.read().frames.len() <= 1,
+                            onclick: move |_| {
+                                let name = session.read().frame_name().to_string();
+                                if gloo_dialogs::confirm(&format!("Delete frame '{}'?", name)) {
+                                    session.write().delete_frame();
+                                }
+                            },
+                            Icon {
+                                width: 12,
+                                height: 12,
+                                icon: FaTrash,
+                            }
+                        }
+                    }
+                },
                 div { class: "header tab",
-                    h3 { "Fields" },
+                    div { class: "fields_title",
+                        h3 { "Fields" },
+                        select { class: "unit_select",
+                            name: "unit_bits",
+                            title: "Unit of the field lengths, for the whole document",
+                            value: "{session.read().unit_bits}",
+                            onchange: move |evt| {
+                                if let Ok(bits) = evt.value().parse() {
+                                    session.write().set_unit_bits(bits);
+                                }
+                            },
+                            option { value: "1", "Bits" },
+                            option { value: "8", "Bytes" },
+                        },
+                        if error_count > 0 {
+                            span { class: "badge badge_error",
+                                title: "Fields with errors are not drawn as intended",
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaCircleExclamation,
+                                },
+                                if error_count == 1 { "1 error" } else { "{error_count} errors" }
+                            }
+                        },
+                        if warning_count > 0 {
+                            span { class: "badge badge_warning",
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaTriangleExclamation,
+                                },
+                                if warning_count == 1 { "1 warning" } else { "{warning_count} warnings" }
+                            }
+                        }
+                    },
                     button { class: "button circle_button",
                         onclick: move |_| {
-                            input_fields.write().push(FieldInput::default());
-                            descriptor.write().fields = create_field_descriptors(&input_fields.read());
-                            *svg_data.write() = update_svg(&descriptor.read());
+                            let index = vec![session.read().fields.len()];
+                            session.write().commit(Edit::AddField { index, field: FieldInput::default() });
                         },
                         Icon {
                             width: 15,
                             height: 15,
                             icon: FaPlus,
                         }
+                    },
+                    button { class: "button circle_button",
+                        title: "Paste Fields",
+                        onclick: move |_| async move {
+                            if let Some(text) = read_from_clipboard().await {
+                                session.write().paste(&text);
+                            } else {
+                                report(DiagnosticKind::Parse, "Failed to read the clipboard".to_string());
+                            }
+                        },
+                        Icon {
+                            width: 15,
+                            height: 15,
+                            icon: FaPaste,
+                        }
+                    }
+                },
+                if !session.read().selection.is_empty() {
+                    div { class: "header tab selection_bar",
+                        span { "{session.read().selection.len()} selected" },
+                        div { class: "selection_actions",
+                            input {
+                                r#type: "color",
+                                title: "Color",
+                                value: format!("{}", Display::new(session.read().descriptor.style.field_color)),
+                                onchange: move |evt| {
+                                    let color = HexColor::parse_rgb(&evt.value()).ok();
+                                    session.write().edit_selection(&|fields, paths| {
+                                        let new = edit_fields(fields, paths, |field| field.color = color)?;
+                                        Some((new, paths.to_vec()))
+                                    });
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Remove Color",
+                                onclick: move |_| {
+                                    session.write().edit_selection(&|fields, paths| {
+                                        let new = edit_fields(fields, paths, |field| field.color = None)?;
+                                        Some((new, paths.to_vec()))
+                                    });
+                                },
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaDropletSlash,
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Toggle Wrap",
+                                onclick: move |_| {
+                                    session.write().edit_selection(&|fields, paths| {
+                                        let wrap = !paths.iter().all(|path| field_at(fields, path).is_some_and(|field| field.wrap));
+                                        let new = edit_fields(fields, paths, |field| field.wrap = wrap)?;
+                                        Some((new, paths.to_vec()))
+                                    });
+                                },
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaTurnDown,
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Move Up",
+                                onclick: move |_| session.write().move_selection(false),
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaArrowUp,
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Move Down",
+                                onclick: move |_| session.write().move_selection(true),
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaArrowDown,
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Duplicate",
+                                onclick: move |_| {
+                                    session.write().edit_selection(&|fields, paths| Some((duplicate_fields(fields, paths)?, Vec::new())));
+                                },
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaClone,
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Group",
+                                onclick: move |_| {
+                                    let Some(name) = gloo_dialogs::prompt("Group name", Some("Group")) else {
+                                        return;
+                                    };
+                                    let grouped = session.write().group_selection(&name);
+                                    if !grouped {
+                                        report(DiagnosticKind::Edit, "The selected fields must be in the same group to be grouped.".to_string());
+                                    }
+                                },
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaLayerGroup,
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Copy",
+                                onclick: move |_| {
+                                    let copied = session.write().copy_selection();
+                                    async move {
+                                        if let Some((json, _)) = copied {
+                                            if copy_to_clipboard(&json).await {
+                                                notice.set(Some("Fields copied to the clipboard".to_string()));
+                                            } else {
+                                                report(DiagnosticKind::Export, "Failed to copy fields".to_string());
+                                            }
+                                        }
+                                    }
+                                },
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaCopy,
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Cut",
+                                onclick: move |_| {
+                                    let copied = session.write().copy_selection();
+                                    async move {
+                                        if let Some((json, _)) = copied {
+                                            if copy_to_clipboard(&json).await {
+                                                session.write().delete_selection();
+                                            } else {
+                                                report(DiagnosticKind::Export, "Failed to cut fields".to_string());
+                                            }
+                                        }
+                                    }
+                                },
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaScissors,
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Delete",
+                                onclick: move |_| {
+                                    session.write().delete_selection();
+                                },
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaTrash,
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Clear Selection",
+                                onclick: move |_| {
+                                    session.write().clear_selection();
+                                },
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaX,
+                                }
+                            }
+                        }
                     }
                 },
                 div { class: "list",
-                    for (i, field) in input_fields.read().iter().enumerate() {
-                        div { class: "row list_row",
+                    for (row, (index, field, siblings, errors, warnings)) in field_list.into_iter().enumerate() {
+                        div {
+                            class: format!(
+                                "row list_row{}{}{}",
+                                if session.read().selection.contains(&index) { " selected_row" } else { "" },
+                                if session.read().diagnostics.at_field(&index).is_some() { " diagnostic_row" } else { "" },
+                                if *drop_row.read() == Some(row) && *dragged_row.read() != Some(row) { " drop_target" } else { "" }
+                            ),
+                            style: format!("padding-left: {}px", (index.len() - 1) * FIELD_INDENT),
+                            onclick: move |evt| {
+                                let modifiers = evt.modifiers();
+                                if modifiers.shift() || modifiers.ctrl() || modifiers.meta() {
+                                    session.write().select_row(row, modifiers.shift());
+                                }
+                            },
+                            ondragover: move |_| {
+                                if dragged_row.read().is_some() && *drop_row.read() != Some(row) {
+                                    drop_row.set(Some(row));
+                                }
+                            },
+                            ondrop: move |_| {
+                                drop_row.set(None);
+                                if let Some(from) = dragged_row.take() {
+                                    session.write().drop_row(from, row);
+                                }
+                            },
+                            div { class: "drag_handle",
+                                title: "Drag to move the field",
+                                draggable: "true",
+                                ondragstart: move |evt| {
+                                    start_row_drag(&evt.data());
+                                    dragged_row.set(Some(row));
+                                },
+                                ondragend: move |_| {
+                                    dragged_row.set(None);
+                                    drop_row.set(None);
+                                },
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaGripVertical,
+                                }
+                            },
                             div { class: "column arrow_column",
                                 button { class: "button arrow_button",
-                                    disabled: i == 0,
+                                    disabled: index.last() == Some(&0),
                                     onclick: move |_| {
-                                        input_fields.write().swap(i, i - 1);
-                                        descriptor.write().fields = create_field_descriptors(&input_fields.read());
-                                        *svg_data.write() = update_svg(&descriptor.read());
+                                        let mut session = session.write();
+                                        if let Some((i, parent)) = session.row_path(row).and_then(|mut path| Some((path.pop()?, path))) {
+                                            session.commit(Edit::SwapFields { parent, a: i, b: i - 1 });
+                                        }
                                     },
                                     Icon {
                                         width: 10,
@@ -172,11 +1266,12 @@ fn app() -> Element {
                                     },
                                 },
                                 button { class: "button arrow_button",
-                                    disabled: i == input_fields.read().len() - 1,
+                                    disabled: index.last() == Some(&(siblings - 1)),
                                     onclick: move |_| {
-                                        input_fields.write().swap(i, i + 1);
-                                        descriptor.write().fields = create_field_descriptors(&input_fields.read());
-                                        *svg_data.write() = update_svg(&descriptor.read());
+                                        let mut session = session.write();
+                                        if let Some((i, parent)) = session.row_path(row).and_then(|mut path| Some((path.pop()?, path))) {
+                                            session.commit(Edit::SwapFields { parent, a: i, b: i + 1 });
+                                        }
                                     },
                                     Icon {
                                         width: 10,
@@ -185,22 +1280,56 @@ fn app() -> Element {
                                     },
                                 },
                             },
+                            button { class: "button circle_button",
+                                title: if field.expanded { "Collapse" } else { "Expand" },
+                                disabled: field.children.is_empty(),
+                                onclick: move |_| {
+                                    let mut session = session.write();
+                                    if let Some(index) = session.row_path(row) {
+                                        let old = field_at(&session.fields, &index).is_some_and(|field| field.expanded);
+                                        session.commit(Edit::Expand { index, old, new: !old });
+                                    }
+                                },
+                                if field.expanded && !field.children.is_empty() {
+                                    Icon {
+                                        width: 10,
+                                        height: 10,
+                                        icon: FaCaretDown,
+                                    }
+                                } else {
+                                    Icon {
+                                        width: 10,
+                                        height: 10,
+                                        icon: FaCaretRight,
+                                    }
+                                }
+                            },
                             input { class: "text_entry", style: "flex: 2;",
                                 placeholder: "Field Name",
                                 value: field.name.clone(),
                                 onchange: move |evt| {
-                                    input_fields.write()[i].name = evt.value();
-                                    descriptor.write().fields = create_field_descriptors(&input_fields.read());
-                                    *svg_data.write() = update_svg(&descriptor.read());
+                                    let mut session = session.write();
+                                    if let Some(index) = session.row_path(row) {
+                                        let old = field_at(&session.fields, &index).map(|field| field.name.clone()).unwrap_or_default();
+                                        session.commit(Edit::Rename { index, old, new: evt.value() });
+                                    }
                                 }
                             },
-                            input { class: "text_entry",
+                            input {
+                                class: if errors.is_empty() { "text_entry" } else { "text_entry invalid_entry" },
                                 placeholder: "Field Length",
+                                title: if errors.is_empty() {
+                                    "Length in units, in bits with a b suffix (4b), or an expression of other fields (IHL*4 - 20)".to_string()
+                                } else {
+                                    errors.join("; ")
+                                },
                                 value: field.length.clone(),
                                 onchange: move |evt| {
-                                    input_fields.write()[i].length = evt.value();
-                                    descriptor.write().fields = create_field_descriptors(&input_fields.read());
-                                    *svg_data.write() = update_svg(&descriptor.read());
+                                    let mut session = session.write();
+                                    if let Some(index) = session.row_path(row) {
+                                        let old = field_at(&session.fields, &index).map(|field| field.length.clone()).unwrap_or_default();
+                                        session.commit(Edit::Length { index, old, new: evt.value() });
+                                    }
                                 }
                             },
                             label { r#for: "wrap", "Wrap" },
@@ -208,13 +1337,11 @@ fn app() -> Element {
                                 checked: field.wrap,
                                 name: "wrap",
                                 oninput: move |evt| {
-                                    if evt.checked() {
-                                        input_fields.write()[i].wrap = true;
-                                    } else {
-                                        input_fields.write()[i].wrap = false;
+                                    let mut session = session.write();
+                                    if let Some(index) = session.row_path(row) {
+                                        let old = field_at(&session.fields, &index).is_some_and(|field| field.wrap);
+                                        session.commit(Edit::Wrap { index, old, new: evt.checked() });
                                     }
-                                    descriptor.write().fields = create_field_descriptors(&input_fields.read());
-                                    *svg_data.write() = update_svg(&descriptor.read());
                                 }
                             },
                             label { r#for: "color", "Color" },
@@ -222,36 +1349,100 @@ fn app() -> Element {
                                 checked: field.color.is_some(),
                                 name: "color",
                                 oninput: move |evt| {
-                                    if evt.checked() {
-                                        input_fields.write()[i].color = Some(descriptor.read().style.field_color.clone());
-                                    } else {
-                                        input_fields.write()[i].color = None;
+                                    let mut session = session.write();
+                                    if let Some(index) = session.row_path(row) {
+                                        let old = field_at(&session.fields, &index).and_then(|field| field.color);
+                                        let new = if evt.checked() {
+                                            Some(session.descriptor.style.field_color)
+                                        } else {
+                                            None
+                                        };
+                                        session.commit(Edit::Color { index, old, new });
                                     }
-                                    descriptor.write().fields = create_field_descriptors(&input_fields.read());
-                                    *svg_data.write() = update_svg(&descriptor.read());
                                 }
                             },
                             input {
                                 r#type: "color",
                                 disabled: field.color.is_none(),
-                                value: format!("{}", Display::new(field.color.unwrap_or(descriptor.read().style.field_color.clone()))),
+                                value: format!("{}", Display::new(field.color.unwrap_or(session.read().descriptor.style.field_color))),
                                 onchange: move |evt| {
-                                    input_fields.write()[i].color = Some(HexColor::parse_rgb(&evt.value()).unwrap());
-                                    descriptor.write().fields = create_field_descriptors(&input_fields.read());
-                                    *svg_data.write() = update_svg(&descriptor.read());
+                                    let mut session = session.write();
+                                    if let Some(index) = session.row_path(row) {
+                                        let old = field_at(&session.fields, &index).and_then(|field| field.color);
+                                        session.commit(Edit::Color { index, old, new: Some(HexColor::parse_rgb(&evt.value()).unwrap()) });
+                                    }
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Move into the field above",
+                                disabled: index.last() == Some(&0),
+                                onclick: move |_| {
+                                    let mut session = session.write();
+                                    if let Some(index) = session.row_path(row) {
+                                        if let Some(new) = indent(&session.fields, &index) {
+                                            session.replace_fields(new);
+                                        }
+                                    }
+                                },
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaIndent,
+                                }
+                            },
+                            button { class: "button circle_button",
+                                title: "Move out of the parent field",
+                                disabled: index.len() < 2,
+                                onclick: move |_| {
+                                    let mut session = session.write();
+                                    if let Some(index) = session.row_path(row) {
+                                        if let Some(new) = outdent(&session.fields, &index) {
+                                            session.replace_fields(new);
+                                        }
+                                    }
+                                },
+                                Icon {
+                                    width: 12,
+                                    height: 12,
+                                    icon: FaOutdent,
                                 }
                             },
                             button { class: "button circle_button",
                                 onclick: move |_| {
-                                    input_fields.write().remove(i);
-                                    descriptor.write().fields = create_field_descriptors(&input_fields.read());
-                                    *svg_data.write() = update_svg(&descriptor.read());
+                                    let mut session = session.write();
+                                    if let Some(index) = session.row_path(row) {
+                                        if let Some(field) = field_at(&session.fields, &index).cloned() {
+                                            session.commit(Edit::RemoveField { index, field });
+                                        }
+                                    }
                                 },
                                 Icon {
                                     width: 12,
                                     height: 12,
                                     icon: FaX,
                                 },
+                            },
+                            if !errors.is_empty() {
+                                span { class: "badge badge_error",
+                                    title: errors.join("\n"),
+                                    Icon {
+                                        width: 12,
+                                        height: 12,
+                                        icon: FaCircleExclamation,
+                                    },
+                                    "{errors.len()}"
+                                }
+                            },
+                            if !warnings.is_empty() {
+                                span { class: "badge badge_warning",
+                                    title: warnings.join("\n"),
+                                    Icon {
+                                        width: 12,
+                                        height: 12,
+                                        icon: FaTriangleExclamation,
+                                    },
+                                    "{warnings.len()}"
+                                }
                             }
                         }
                     }
@@ -259,8 +1450,126 @@ fn app() -> Element {
             }
             div { class: "column right_column",
                 div { class: "viewport",
-                    style: format!("background-color: {}", Display::new(descriptor.read().style.background_color)),
-                    dangerous_inner_html: svg_data.read().as_str(),
+                    style: format!("background-color: {}", Display::new(session.read().descriptor.style.background_color)),
+                    dangerous_inner_html: viewport_svg.as_str(),
+                }
+                if let Some(problem) = stack_problem {
+                    p { class: "stack_problem", "Can't draw the stack: {problem}" }
+                }
+                if *show_sample_data.read() {
+                    div { class: "column sample_data",
+                        div { class: "header tab",
+                            h3 { "Sample Data" },
+                            button { class: "button circle_button",
+                                onclick: move |_| show_sample_data.set(false),
+                                Icon {
+                                    width: 10,
+                                    height: 10,
+                                    icon: FaX,
+                                }
+                            }
+                        },
+                        div { class: "options",
+                            textarea { class: "sample_input",
+                                name: "sample_data",
+                                placeholder: "Paste the bytes of a frame, in hex (45 00 00 3c ...) or base64, or open a capture",
+                                spellcheck: false,
+                                value: "{sample_text}",
+                                oninput: move |evt| sample_text.set(evt.value()),
+                            },
+                            if !capture.read().is_empty() {
+                                div { class: "row list_row list_row_slim",
+                                    label { r#for: "capture_packet", "Packet" },
+                                    select {
+                                        name: "capture_packet",
+                                        value: "{capture_packet}",
+                                        onchange: move |evt| {
+                                            if let Ok(index) = evt.value().parse() {
+                                                select_packet(index, *capture_offset.read());
+                                            }
+                                        },
+                                        for (i, packet) in capture.read().iter().enumerate() {
+                                            option { value: "{i}",
+                                                "#{i + 1} ({packet.data.len()} of {packet.original_length} bytes)"
+                                            }
+                                        }
+                                    }
+                                }
+                                div { class: "row list_row list_row_slim",
+                                    label { r#for: "capture_offset", "Start Offset" },
+                                    input { class: "text_entry",
+                                        r#type: "number",
+                                        name: "capture_offset",
+                                        min: "0",
+                                        value: "{capture_offset}",
+                                        onchange: move |evt| {
+                                            if let Ok(offset) = evt.value().parse() {
+                                                select_packet(*capture_packet.read(), offset);
+                                            }
+                                        }
+                                    },
+                                    for (name, offset) in header_offsets {
+                                        button { class: "button",
+                                            title: "Start after the {name} header ({offset} bytes)",
+                                            onclick: move |_| select_packet(*capture_packet.read(), offset),
+                                            "Skip {name}"
+                                        }
+                                    }
+                                }
+                            },
+                            match &sample {
+                                Some(Err(e)) => rsx! {
+                                    div { class: "parse_error", "Invalid sample data: {e}" }
+                                },
+                                Some(Ok(decode)) => rsx! {
+                                    for problem in decode.problems() {
+                                        div { class: "parse_error", "{problem}" }
+                                    },
+                                    table { class: "sample_table",
+                                        tr {
+                                            th { "Field" },
+                                            th { "Offset" },
+                                            th { "Length" },
+                                            th { "Hex" },
+                                            th { "Unsigned" },
+                                            th { "Signed" },
+                                            th { "ASCII" },
+                                        },
+                                        for field in decode.fields.iter() {
+                                            tr { class: if field.missing > 0 { "sample_missing" } else { "" },
+                                                td { "{field.name}" },
+                                                td { "{field.position()}" },
+                                                td { title: field.length_note.clone().unwrap_or_default(),
+                                                    "{field.length()}"
+                                                },
+                                                td { "{field.hex()}" },
+                                                td { "{field.unsigned()}" },
+                                                td { "{field.signed()}" },
+                                                td { "{field.ascii()}" },
+                                            }
+                                        }
+                                    }
+                                },
+                                None => rsx! {},
+                            }
+                        }
+                    }
+                }
+                if *show_code_preview.read() {
+                    div { class: "column code_preview",
+                        div { class: "header tab",
+                            h3 { "{codegen_language.read().name()} Code" },
+                            button { class: "button circle_button",
+                                onclick: move |_| show_code_preview.set(false),
+                                Icon {
+                                    width: 10,
+                                    height: 10,
+                                    icon: FaX,
+                                }
+                            }
+                        },
+                        pre { "{code_preview}" }
+                    }
                 }
                 div {
                     div { class: "row flex_separator",
@@ -274,10 +1583,10 @@ fn app() -> Element {
                                     input {
                                         r#type: "color",
                                         name: "back_color",
-                                        value: format!("{}", Display::new(descriptor.read().style.background_color)),
+                                        value: format!("{}", Display::new(session.read().descriptor.style.background_color)),
                                         onchange: move |evt| {
-                                            descriptor.write().style.background_color = HexColor::parse_rgb(&evt.value()).unwrap();
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            let edit = Edit::style_color(&session.read().descriptor, StyleColor::Background, HexColor::parse_rgb(&evt.value()).unwrap());
+                                            session.write().commit(edit);
                                         }
                                     }
                                 },
@@ -286,10 +1595,10 @@ fn app() -> Element {
                                     input {
                                         r#type: "color",
                                         name: "field_color",
-                                        value: format!("{}", Display::new(descriptor.read().style.field_color)),
+                                        value: format!("{}", Display::new(session.read().descriptor.style.field_color)),
                                         onchange: move |evt| {
-                                            descriptor.write().style.field_color = HexColor::parse_rgb(&evt.value()).unwrap();
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            let edit = Edit::style_color(&session.read().descriptor, StyleColor::Field, HexColor::parse_rgb(&evt.value()).unwrap());
+                                            session.write().commit(edit);
                                         }
                                     }
                                 },
@@ -298,10 +1607,10 @@ fn app() -> Element {
                                     input {
                                         r#type: "color",
                                         name: "text_color",
-                                        value: format!("{}", Display::new(descriptor.read().style.text_color)),
+                                        value: format!("{}", Display::new(session.read().descriptor.style.text_color)),
                                         onchange: move |evt| {
-                                            descriptor.write().style.text_color = HexColor::parse_rgb(&evt.value()).unwrap();
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            let edit = Edit::style_color(&session.read().descriptor, StyleColor::Text, HexColor::parse_rgb(&evt.value()).unwrap());
+                                            session.write().commit(edit);
                                         }
                                     }
                                 },
@@ -310,45 +1619,46 @@ fn app() -> Element {
                                     input {
                                         r#type: "color",
                                         name: "sub_color",
-                                        value: format!("{}", Display::new(descriptor.read().style.subtitle_color)),
+                                        value: format!("{}", Display::new(session.read().descriptor.style.subtitle_color)),
                                         onchange: move |evt| {
-                                            descriptor.write().style.subtitle_color = HexColor::parse_rgb(&evt.value()).unwrap();
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            let edit = Edit::style_color(&session.read().descriptor, StyleColor::Subtitle, HexColor::parse_rgb(&evt.value()).unwrap());
+                                            session.write().commit(edit);
                                         }
                                     }
                                 },
                                 div { class: "row list_row list_row_slim",
-                                    label { r#for: "unit_width", "Unit Width ({descriptor.read().style.unit_width})" },
+                                    label { r#for: "unit_width", "Unit Width ({session.read().descriptor.style.unit_width})" },
                                     input {
                                         r#type: "range",
                                         name: "unit_width",
                                         min: "20",
                                         max: "80",
                                         step: "5",
-                                        value: "{descriptor.read().style.unit_width}",
+                                        value: "{session.read().descriptor.style.unit_width}",
                                         oninput: move |evt| {
-                                            descriptor.write().style.unit_width = evt.value().parse().unwrap();
+                                            let edit = Edit::style_size(&session.read().descriptor, StyleSize::UnitWidth, evt.value().parse().unwrap());
+                                            session.write().commit_coalesced(edit);
                                         },
                                         onchange: move |_| {
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            session.write().seal();
                                         }
                                     }
                                 },
                                 div { class: "row list_row list_row_slim",
-                                    label { r#for: "dyn_units", "Dynamic Units ({descriptor.read().style.dyn_units})" },
+                                    label { r#for: "dyn_units", "Dynamic Units ({session.read().descriptor.style.dyn_units})" },
                                     input {
                                         r#type: "range",
                                         name: "dyn_units",
                                         min: "3",
                                         max: "32",
                                         step: "1",
-                                        value: "{descriptor.read().style.dyn_units}",
+                                        value: "{session.read().descriptor.style.dyn_units}",
                                         oninput: move |evt| {
-                                            descriptor.write().style.dyn_units = evt.value().parse().unwrap();
-                                            
+                                            let edit = Edit::style_size(&session.read().descriptor, StyleSize::DynUnits, evt.value().parse().unwrap());
+                                            session.write().commit_coalesced(edit);
                                         },
                                         onchange: move |_| {
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            session.write().seal();
                                         }
                                     }
                                 }
@@ -364,10 +1674,10 @@ fn app() -> Element {
                                     input {
                                         r#type: "checkbox",
                                         name: "is_network",
-                                        checked: descriptor.read().elements.network_order,
+                                        checked: session.read().descriptor.elements.network_order,
                                         onchange: move |evt| {
-                                            descriptor.write().elements.network_order = evt.checked();
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            let edit = Edit::element(&session.read().descriptor, ElementFlag::NetworkOrder, evt.checked());
+                                            session.write().commit(edit);
                                         }
                                     }
                                 },
@@ -376,10 +1686,10 @@ fn app() -> Element {
                                     input {
                                         r#type: "checkbox",
                                         name: "inner_subs",
-                                        checked: descriptor.read().elements.inner_subtitles,
+                                        checked: session.read().descriptor.elements.inner_subtitles,
                                         onchange: move |evt| {
-                                            descriptor.write().elements.inner_subtitles = evt.checked();
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            let edit = Edit::element(&session.read().descriptor, ElementFlag::InnerSubtitles, evt.checked());
+                                            session.write().commit(edit);
                                         }
                                     }
                                 },
@@ -388,10 +1698,10 @@ fn app() -> Element {
                                     input {
                                         r#type: "checkbox",
                                         name: "field_pos",
-                                        checked: descriptor.read().elements.field_position,
+                                        checked: session.read().descriptor.elements.field_position,
                                         onchange: move |evt| {
-                                            descriptor.write().elements.field_position = evt.checked();
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            let edit = Edit::element(&session.read().descriptor, ElementFlag::FieldPosition, evt.checked());
+                                            session.write().commit(edit);
                                         }
                                     }
                                 },
@@ -400,10 +1710,10 @@ fn app() -> Element {
                                     input {
                                         r#type: "checkbox",
                                         name: "field_len",
-                                        checked: descriptor.read().elements.field_length,
+                                        checked: session.read().descriptor.elements.field_length,
                                         onchange: move |evt| {
-                                            descriptor.write().elements.field_length = evt.checked();
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            let edit = Edit::element(&session.read().descriptor, ElementFlag::FieldLength, evt.checked());
+                                            session.write().commit(edit);
                                         }
                                     }
                                 },
@@ -412,10 +1722,10 @@ fn app() -> Element {
                                     input {
                                         r#type: "checkbox",
                                         name: "wrap_line",
-                                        checked: descriptor.read().elements.wrap_line,
+                                        checked: session.read().descriptor.elements.wrap_line,
                                         onchange: move |evt| {
-                                            descriptor.write().elements.wrap_line = evt.checked();
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            let edit = Edit::element(&session.read().descriptor, ElementFlag::WrapLine, evt.checked());
+                                            session.write().commit(edit);
                                         }
                                     }
                                 },
@@ -424,10 +1734,10 @@ fn app() -> Element {
                                     input {
                                         r#type: "checkbox",
                                         name: "start_symbol",
-                                        checked: descriptor.read().elements.start_symbol,
+                                        checked: session.read().descriptor.elements.start_symbol,
                                         onchange: move |evt| {
-                                            descriptor.write().elements.start_symbol = evt.checked();
-                                            *svg_data.write() = update_svg(&descriptor.read());
+                                            let edit = Edit::element(&session.read().descriptor, ElementFlag::StartSymbol, evt.checked());
+                                            session.write().commit(edit);
                                         }
                                     }
                                 },
@@ -0,0 +1,351 @@
+//! Packet capture (.pcap and .pcapng) reading.
+//!
+//! Only the packets are read: pcapng blocks other than the section header, interface description
+//! and packet blocks are skipped.
+
+/// Link layer types of the capture headers
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_IPV6: u16 = 229;
+
+/// pcapng block types
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const BLOCK_INTERFACE: u32 = 1;
+const BLOCK_PACKET: u32 = 2;
+const BLOCK_SIMPLE_PACKET: u32 = 3;
+const BLOCK_ENHANCED_PACKET: u32 = 6;
+
+/// A packet of a capture
+pub struct Packet {
+    /// Captured bytes, which may be fewer than the ones on the wire
+    pub data: Vec<u8>,
+    /// Length of the packet on the wire
+    pub original_length: usize,
+    /// Link layer type, if known
+    pub link_type: Option<u16>,
+}
+
+/// Reader of the integers of a capture, in its byte order
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// Read `length` bytes, or the ones left
+    fn bytes(&self, offset: usize, length: usize) -> &[u8] {
+        let start = offset.min(self.data.len());
+        let end = offset.saturating_add(length).min(self.data.len());
+        &self.data[start..end]
+    }
+}
+
+/// Read the packets of a .pcap or .pcapng file
+pub fn parse_capture(file: &[u8]) -> Result<Vec<Packet>, String> {
+    let magic = file
+        .get(0..4)
+        .ok_or_else(|| "the file is too short".to_string())?;
+
+    let packets = match magic {
+        [0xA1, 0xB2, 0xC3, 0xD4] | [0xA1, 0xB2, 0x3C, 0x4D] => parse_pcap(file, true)?,
+        [0xD4, 0xC3, 0xB2, 0xA1] | [0x4D, 0x3C, 0xB2, 0xA1] => parse_pcap(file, false)?,
+        [0x0A, 0x0D, 0x0D, 0x0A] => parse_pcapng(file)?,
+        _ => return Err("the file isn't a pcap or pcapng capture".to_string()),
+    };
+    if packets.is_empty() {
+        return Err("the capture has no packets".to_string());
+    }
+    Ok(packets)
+}
+
+fn parse_pcap(file: &[u8], big_endian: bool) -> Result<Vec<Packet>, String> {
+    const HEADER: usize = 24;
+    const RECORD_HEADER: usize = 16;
+
+    let reader = Reader {
+        data: file,
+        big_endian,
+    };
+    let link_type = reader
+        .u32(20)
+        .ok_or_else(|| "the capture header is truncated".to_string())?;
+
+    let mut packets = Vec::new();
+    let mut offset = HEADER;
+    while let Some(start) = offset
+        .checked_add(RECORD_HEADER)
+        .filter(|&start| start <= file.len())
+    {
+        let (Some(captured), Some(original)) = (reader.u32(offset + 8), reader.u32(offset + 12))
+        else {
+            break;
+        };
+        let Some(end) = start
+            .checked_add(captured as usize)
+            .filter(|&end| end <= file.len())
+        else {
+            return Err(format!("truncated record at byte {}", offset));
+        };
+        packets.push(Packet {
+            data: file[start..end].to_vec(),
+            original_length: original as usize,
+            link_type: Some(link_type as u16),
+        });
+        offset = end;
+    }
+    Ok(packets)
+}
+
+fn parse_pcapng(file: &[u8]) -> Result<Vec<Packet>, String> {
+    let mut reader = Reader {
+        data: file,
+        big_endian: false,
+    };
+    // Link types of the interfaces of the current section
+    let mut interfaces: Vec<u16> = Vec::new();
+    let mut packets = Vec::new();
+    let mut offset = 0;
+
+    while offset + 12 <= file.len() {
+        if reader.u32(offset) == Some(BLOCK_SECTION_HEADER) {
+            // The byte order magic sets the byte order of the section
+            reader.big_endian = match file.get(offset + 8..offset + 12) {
+                Some([0x1A, 0x2B, 0x3C, 0x4D]) => true,
+                Some([0x4D, 0x3C, 0x2B, 0x1A]) => false,
+                _ => return Err(format!("invalid section header at byte {}", offset)),
+            };
+            interfaces.clear();
+        }
+
+        let block_type = reader.u32(offset).unwrap_or_default();
+        let length = reader.u32(offset + 4).unwrap_or_default() as usize;
+        if length < 12
+            || length % 4 != 0
+            || offset
+                .checked_add(length)
+                .is_none_or(|end| end > file.len())
+        {
+            return Err(format!("invalid block at byte {}", offset));
+        }
+        let body = offset + 8;
+        let body_length = length - 12;
+
+        match block_type {
+            BLOCK_INTERFACE => {
+                interfaces.push(reader.u16(body).unwrap_or_default());
+            }
+            BLOCK_ENHANCED_PACKET | BLOCK_PACKET if body_length >= 20 => {
+                let interface = if block_type == BLOCK_PACKET {
+                    reader.u16(body).map(usize::from)
+                } else {
+                    reader.u32(body).map(|id| id as usize)
+                };
+                let captured = reader.u32(body + 12).unwrap_or_default() as usize;
+                let original = reader.u32(body + 16).unwrap_or_default() as usize;
+                packets.push(Packet {
+                    data: reader
+                        .bytes(body + 20, captured.min(body_length - 20))
+                        .to_vec(),
+                    original_length: original,
+                    link_type: interface.and_then(|id| interfaces.get(id).copied()),
+                });
+            }
+            BLOCK_SIMPLE_PACKET if body_length >= 4 => {
+                let original = reader.u32(body).unwrap_or_default() as usize;
+                packets.push(Packet {
+                    data: reader
+                        .bytes(body + 4, original.min(body_length - 4))
+                        .to_vec(),
+                    original_length: original,
+                    link_type: interfaces.first().copied(),
+                });
+            }
+            _ => {}
+        }
+        offset += length;
+    }
+    Ok(packets)
+}
+
+impl Packet {
+    /// Offsets of the payloads of the headers found at the start of the packet, with the name of
+    /// the header, to skip them when decoding the packet
+    pub fn header_offsets(&self) -> Vec<(&'static str, usize)> {
+        let data = &self.data;
+        let mut offsets = Vec::new();
+
+        // Link layer, giving the EtherType of the network layer
+        let (mut offset, ethertype) = match self.link_type {
+            Some(LINKTYPE_ETHERNET) => {
+                let mut offset = 12;
+                let mut ethertype = read_u16(data, offset);
+                // VLAN tags
+                while matches!(ethertype, Some(0x8100 | 0x88A8)) {
+                    offset += 4;
+                    ethertype = read_u16(data, offset);
+                }
+                offsets.push(("Ethernet", offset + 2));
+                (offset + 2, ethertype)
+            }
+            Some(LINKTYPE_LINUX_SLL) => {
+                offsets.push(("Linux cooked", 16));
+                (16, read_u16(data, 14))
+            }
+            Some(LINKTYPE_RAW) => match data.first().map(|byte| byte >> 4) {
+                Some(4) => (0, Some(0x0800)),
+                Some(6) => (0, Some(0x86DD)),
+                _ => (0, None),
+            },
+            Some(LINKTYPE_IPV4) => (0, Some(0x0800)),
+            Some(LINKTYPE_IPV6) => (0, Some(0x86DD)),
+            _ => (0, None),
+        };
+
+        // Network layer, giving the protocol of the transport layer
+        let protocol = match ethertype {
+            Some(0x0800) => {
+                let header = usize::from(data.get(offset).copied().unwrap_or_default() & 0x0F) * 4;
+                let protocol = data.get(offset + 9).copied();
+                offset += header;
+                offsets.push(("IPv4", offset));
+                protocol
+            }
+            Some(0x86DD) => {
+                let protocol = data.get(offset + 6).copied();
+                offset += 40;
+                offsets.push(("IPv6", offset));
+                protocol
+            }
+            _ => None,
+        };
+
+        match protocol {
+            Some(6) => {
+                let header = usize::from(data.get(offset + 12).copied().unwrap_or_default() >> 4);
+                offsets.push(("TCP", offset + header * 4));
+            }
+            Some(17) => offsets.push(("UDP", offset + 8)),
+            _ => {}
+        }
+
+        offsets.retain(|(_, offset)| *offset <= data.len());
+        offsets
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// pcapng block of the given type and body, in little endian
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = (12 + body.len()) as u32;
+        let mut block = Vec::new();
+        block.extend(block_type.to_le_bytes());
+        block.extend(length.to_le_bytes());
+        block.extend(body);
+        block.extend(length.to_le_bytes());
+        block
+    }
+
+    fn section_header() -> Vec<u8> {
+        let mut body = vec![0x4D, 0x3C, 0x2B, 0x1A, 1, 0, 0, 0];
+        body.extend(u64::MAX.to_le_bytes());
+        block(BLOCK_SECTION_HEADER, &body)
+    }
+
+    fn enhanced_packet(data: &[u8]) -> Vec<u8> {
+        let mut body = vec![0; 12];
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend(data);
+        body.resize(body.len().next_multiple_of(4), 0);
+        block(BLOCK_ENHANCED_PACKET, &body)
+    }
+
+    /// pcap file in little endian, with a record of the given captured length and data
+    fn pcap_record(captured: u32, data: &[u8]) -> Vec<u8> {
+        let mut file = vec![0xD4, 0xC3, 0xB2, 0xA1, 2, 0, 4, 0];
+        file.extend([0; 12]);
+        file.extend(u32::from(LINKTYPE_ETHERNET).to_le_bytes());
+        file.extend([0; 8]);
+        file.extend(captured.to_le_bytes());
+        file.extend((data.len() as u32).to_le_bytes());
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn reads_pcap_packets() {
+        let packets = parse_capture(&pcap_record(3, &[0xAA, 0xBB, 0xCC])).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, [0xAA, 0xBB, 0xCC]);
+        assert_eq!(packets[0].link_type, Some(LINKTYPE_ETHERNET));
+    }
+
+    #[test]
+    fn rejects_truncated_pcap_records() {
+        let truncated = parse_capture(&pcap_record(4, &[0xAA, 0xBB, 0xCC]));
+        assert_eq!(
+            truncated.err(),
+            Some("truncated record at byte 24".to_string())
+        );
+
+        // A captured length that would wrap the offset around on 32-bit targets
+        let huge = parse_capture(&pcap_record(u32::MAX - 8, &[0xAA; 16]));
+        assert_eq!(huge.err(), Some("truncated record at byte 24".to_string()));
+    }
+
+    #[test]
+    fn reads_pcapng_packets() {
+        let mut file = section_header();
+        file.extend(block(BLOCK_INTERFACE, &[1, 0, 0, 0, 0, 0, 0, 0]));
+        file.extend(enhanced_packet(&[0xAA, 0xBB, 0xCC]));
+
+        let packets = parse_capture(&file).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, [0xAA, 0xBB, 0xCC]);
+        assert_eq!(packets[0].original_length, 3);
+        assert_eq!(packets[0].link_type, Some(LINKTYPE_ETHERNET));
+    }
+
+    #[test]
+    fn rejects_blocks_past_the_end() {
+        let mut file = section_header();
+        let offset = file.len();
+        // A length that wraps the offset around on 32-bit targets
+        file.extend(BLOCK_ENHANCED_PACKET.to_le_bytes());
+        file.extend(0xFFFF_FFFC_u32.to_le_bytes());
+        file.extend([0; 24]);
+
+        assert_eq!(
+            parse_capture(&file).err(),
+            Some(format!("invalid block at byte {}", offset))
+        );
+    }
+}
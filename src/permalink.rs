@@ -0,0 +1,154 @@
+//! Permalinks holding the whole document in the URL fragment.
+//!
+//! The fragment has the form `<version>.<data>`, where data is the document JSON compressed with
+//! DEFLATE and encoded as base64url. The version allows old links to be decoded after the format changes:
+//! v1 links hold a single descriptor.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use protoviz::descriptor::ProtoDescriptor;
+
+use crate::document::{document_value, parse_document, Document, Frame};
+use crate::fields::FieldInput;
+
+/// Current version of the permalink encoding
+const PERMALINK_VERSION: &str = "v2";
+
+/// Maximum size of a decoded document, to avoid decompression bombs
+const MAX_DOCUMENT_SIZE: usize = 1 << 20;
+
+/// DEFLATE compression level, the best one since links are short
+const COMPRESSION_LEVEL: u8 = 9;
+
+/// Encode the document into a URL fragment (without the leading '#').
+/// The fields of the frame at `active` are `active_fields`, with `unit_bits` bits in a unit.
+pub fn encode_permalink(
+    descriptor: &ProtoDescriptor,
+    frames: &[Frame],
+    active: usize,
+    active_fields: &[FieldInput],
+    unit_bits: usize,
+) -> Result<String, String> {
+    let json = document_value(descriptor, frames, active, active_fields, unit_bits)
+        .map(|document| document.to_string())
+        .map_err(|e| format!("failed to serialize document ({})", e))?;
+    let compressed = compress_to_vec(json.as_bytes(), COMPRESSION_LEVEL);
+
+    Ok(format!(
+        "{}.{}",
+        PERMALINK_VERSION,
+        URL_SAFE_NO_PAD.encode(compressed)
+    ))
+}
+
+/// Decode a document from a URL fragment (with or without the leading '#')
+pub fn decode_permalink(fragment: &str) -> Result<Document, String> {
+    let fragment = fragment.trim_start_matches('#');
+    let (version, data) = fragment
+        .split_once('.')
+        .ok_or_else(|| "link has no version".to_string())?;
+
+    match version {
+        "v1" | "v2" => {
+            let compressed = URL_SAFE_NO_PAD
+                .decode(data)
+                .map_err(|e| format!("link is not valid base64 ({})", e))?;
+            let json = decompress_to_vec_with_limit(&compressed, MAX_DOCUMENT_SIZE)
+                .map_err(|e| format!("link data is corrupt ({})", e))?;
+
+            serde_json::from_slice(&json)
+                .map_err(|e| e.to_string())
+                .and_then(parse_document)
+                .map_err(|e| format!("link document is invalid ({})", e))
+        }
+        _ => Err(format!("link has unsupported version '{}'", version)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::document::{default_document, DEFAULT_FRAME_NAME};
+    use crate::fields::update_field_inputs;
+
+    /// Permalink of the JSON with the given version
+    fn link(version: &str, json: &[u8]) -> String {
+        format!(
+            "{}.{}",
+            version,
+            URL_SAFE_NO_PAD.encode(compress_to_vec(json, COMPRESSION_LEVEL))
+        )
+    }
+
+    #[test]
+    fn round_trips_documents() {
+        let mut document = default_document();
+        document.frames.push(Frame::new(
+            "Reply",
+            vec![FieldInput {
+                name: "Status".to_string(),
+                length: "8".to_string(),
+                ..Default::default()
+            }],
+        ));
+        let fields = document.frames[0].fields.clone();
+
+        let fragment =
+            encode_permalink(&document.descriptor, &document.frames, 0, &fields, 8).unwrap();
+        assert!(fragment.starts_with("v2."));
+
+        let decoded = decode_permalink(&format!("#{}", fragment)).unwrap();
+        assert_eq!(decoded.frames.len(), 2);
+        for (decoded, frame) in decoded.frames.iter().zip(&document.frames) {
+            assert_eq!(decoded.name, frame.name);
+            assert_eq!(decoded.fields, frame.fields);
+        }
+        assert_eq!(decoded.unit_bits, 8);
+        assert_eq!(
+            decoded.descriptor.style.unit_width,
+            document.descriptor.style.unit_width
+        );
+    }
+
+    #[test]
+    fn decodes_v1_descriptors() {
+        let descriptor = json!({
+            "fields": [
+                {"name": "Type", "length": 8},
+                {"name": "Length", "length": 8, "wrap": true},
+            ],
+        });
+        let decoded = decode_permalink(&link("v1", descriptor.to_string().as_bytes())).unwrap();
+
+        assert_eq!(decoded.frames.len(), 1);
+        assert_eq!(decoded.frames[0].name, DEFAULT_FRAME_NAME);
+        assert_eq!(decoded.unit_bits, 1);
+        let expected: Vec<protoviz::descriptor::FieldDescriptor> =
+            serde_json::from_value(descriptor["fields"].clone()).unwrap();
+        assert_eq!(decoded.frames[0].fields, update_field_inputs(&expected));
+    }
+
+    #[test]
+    fn rejects_invalid_links() {
+        let error = |fragment: &str| decode_permalink(fragment).err().unwrap();
+
+        assert_eq!(error("abc"), "link has no version");
+        assert_eq!(error("v9.abc"), "link has unsupported version 'v9'");
+        assert!(error("v2.!!!").starts_with("link is not valid base64"));
+        assert!(error(&format!("v2.{}", URL_SAFE_NO_PAD.encode([0x07])))
+            .starts_with("link data is corrupt"));
+        assert!(error(&link("v2", b"{\"frames\": 1}")).starts_with("link document is invalid"));
+    }
+
+    #[test]
+    fn rejects_oversized_documents() {
+        let json = vec![b' '; MAX_DOCUMENT_SIZE + 1];
+        assert_eq!(
+            decode_permalink(&link("v2", &json)).err().unwrap(),
+            "link data is corrupt (Output size exceeded the specified limit)"
+        );
+    }
+}
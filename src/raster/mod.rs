@@ -0,0 +1,160 @@
+//! Rasterization of the SVG images produced by `protoviz::render`, with resvg.
+//!
+//! Texts are drawn with an embedded font (DejaVu Sans), so the output doesn't depend on the fonts
+//! available in the browser or on the system.
+
+use hex_color::HexColor;
+use resvg::tiny_skia::{Color, Pixmap, Transform};
+use resvg::usvg::{Options, Tree};
+
+/// Font used for the texts, which don't set a font family
+const FONT: &[u8] = include_bytes!("fonts/DejaVuSans.ttf");
+const FONT_FAMILY: &str = "DejaVu Sans";
+
+/// The background comes from the options, not from the `background-color` of the SVG
+const STYLE_SHEET: &str = "svg { background-color: transparent !important; }";
+
+/// Largest image (in pixels) that can be rasterized
+const MAX_PIXELS: usize = 32 * 1024 * 1024;
+
+/// CSS reference resolution, used to convert a DPI into a scale
+pub const REFERENCE_DPI: f64 = 96.0;
+
+pub struct RasterOptions {
+    /// Scale applied to the SVG units (1.0 = one pixel per unit)
+    pub scale: f64,
+    /// Background color, or None for a transparent background
+    pub background: Option<HexColor>,
+}
+
+/// Rasterize a protoviz SVG into an image
+pub fn rasterize(svg: &str, options: &RasterOptions) -> Result<Pixmap, String> {
+    if !(options.scale.is_finite() && options.scale > 0.0) {
+        return Err(format!("invalid scale {}", options.scale));
+    }
+
+    let mut parse_options = Options {
+        font_family: FONT_FAMILY.to_string(),
+        style_sheet: Some(STYLE_SHEET.to_string()),
+        ..Options::default()
+    };
+    parse_options.fontdb_mut().load_font_data(FONT.to_vec());
+    let tree = Tree::from_str(svg, &parse_options).map_err(|e| e.to_string())?;
+
+    let size = tree.size();
+    let width = (size.width() as f64 * options.scale).ceil().max(1.0);
+    let height = (size.height() as f64 * options.scale).ceil().max(1.0);
+    if width * height > MAX_PIXELS as f64 {
+        return Err(format!("image is too large ({}x{} pixels)", width, height));
+    }
+
+    let mut image = Pixmap::new(width as u32, height as u32)
+        .ok_or_else(|| format!("invalid image size ({}x{} pixels)", width, height))?;
+    if let Some(color) = options.background {
+        image.fill(Color::from_rgba8(color.r, color.g, color.b, color.a));
+    }
+
+    let scale = options.scale as f32;
+    resvg::render(
+        &tree,
+        Transform::from_scale(scale, scale),
+        &mut image.as_mut(),
+    );
+
+    Ok(image)
+}
+
+/// Rasterize a protoviz SVG and encode it as PNG
+pub fn render_png(svg: &str, options: &RasterOptions) -> Result<Vec<u8>, String> {
+    rasterize(svg, options)?
+        .encode_png()
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::TEMPLATES;
+
+    fn options(background: Option<HexColor>) -> RasterOptions {
+        RasterOptions {
+            scale: 1.0,
+            background,
+        }
+    }
+
+    /// Get a pixel as straight (non-premultiplied) RGBA
+    fn pixel(image: &Pixmap, x: u32, y: u32) -> [u8; 4] {
+        let color = image.pixel(x, y).unwrap().demultiply();
+        [color.red(), color.green(), color.blue(), color.alpha()]
+    }
+
+    #[test]
+    fn draws_the_fields_of_templates() {
+        let field_color = HexColor::rgb(0xFF, 0x00, 0x00);
+        let background = HexColor::rgb(0x00, 0x00, 0xFF);
+        let mut descriptor = TEMPLATES[0].descriptor().unwrap();
+        descriptor.style.field_color = field_color;
+        descriptor.style.background_color = background;
+        let svg = protoviz::render(&descriptor).unwrap();
+
+        // The first corner of the first field, from `<polygon points="x,y ...`
+        let points = svg.split("<polygon points=\"").nth(1).unwrap();
+        let (left, top) = points.split(' ').next().unwrap().split_once(',').unwrap();
+        let (left, top) = (left.parse::<f64>().unwrap(), top.parse::<f64>().unwrap());
+
+        // A point inside the first field, away from its border and name
+        let image = rasterize(&svg, &options(Some(background))).unwrap();
+        assert_eq!(
+            pixel(&image, left as u32 + 3, top as u32 + 3),
+            [0xFF, 0x00, 0x00, 0xFF]
+        );
+        assert_eq!(pixel(&image, 0, 0), [0x00, 0x00, 0xFF, 0xFF]);
+
+        let transparent = rasterize(&svg, &options(None)).unwrap();
+        assert_eq!(pixel(&transparent, 0, 0), [0; 4]);
+    }
+
+    #[test]
+    fn draws_texts_with_the_embedded_font() {
+        let svg = r#"<svg viewBox="0 0 40 20" xmlns="http://www.w3.org/2000/svg"><text x="2" y="15" font-size="14" fill="black">Type</text></svg>"#;
+        let image = rasterize(svg, &options(None)).unwrap();
+        assert!(image.pixels().iter().any(|pixel| pixel.alpha() > 0));
+    }
+
+    #[test]
+    fn scales_the_image() {
+        let svg = r#"<svg viewBox="0 0 10 5" xmlns="http://www.w3.org/2000/svg"></svg>"#;
+        let image = rasterize(
+            svg,
+            &RasterOptions {
+                scale: 2.5,
+                background: None,
+            },
+        )
+        .unwrap();
+        assert_eq!((image.width(), image.height()), (25, 13));
+
+        let png = render_png(svg, &options(None)).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    }
+
+    #[test]
+    fn rejects_invalid_images() {
+        let unterminated = r#"<svg viewBox="0 0 10 10"><text x="1" y="1">Type</svg>"#;
+        assert!(rasterize(unterminated, &options(None)).is_err());
+
+        let huge = r#"<svg viewBox="0 0 1e9 1e9" xmlns="http://www.w3.org/2000/svg"></svg>"#;
+        let error = rasterize(huge, &options(None)).err().unwrap();
+        assert!(error.starts_with("image is too large"), "{}", error);
+
+        let scale = RasterOptions {
+            scale: f64::NAN,
+            background: None,
+        };
+        assert_eq!(
+            rasterize(huge, &scale).err().as_deref(),
+            Some("invalid scale NaN")
+        );
+    }
+}
@@ -0,0 +1,527 @@
+//! Sample data decoding.
+//!
+//! Bytes pasted as hex or base64 are split into the fields of the descriptor. In network order the
+//! bits of a byte are taken from the most significant one and values are big endian, otherwise
+//! from the least significant one and values are little endian, as in the generated code.
+
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD},
+    Engine,
+};
+use protoviz::descriptor::{FieldDescriptor, ProtoDescriptor};
+
+use crate::expr::parse_length;
+use crate::fields::with_fields;
+use crate::length::{bits_label, fixed_bits, BYTE_BITS};
+
+/// Bytes shown for the fields longer than a value
+const MAX_SHOWN_BYTES: usize = 16;
+
+/// Bytes shown inside the boxes of the diagram
+const MAX_LABEL_BYTES: usize = 4;
+
+/// A field of the descriptor, with its part of the sample data
+pub struct DecodedField {
+    pub name: String,
+    /// Offset from the start of the data, in bits
+    pub offset: usize,
+    /// Length in bits
+    pub bits: usize,
+    /// Bits of the field past the end of the data
+    pub missing: usize,
+    /// How a variable length was found
+    pub length_note: Option<String>,
+    /// Value of the fields up to 64 bits long
+    pub value: Option<u64>,
+    /// Bytes of the byte aligned fields (the ones in the data)
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// Fields of the descriptor decoded from sample data
+pub struct SampleDecode {
+    pub fields: Vec<DecodedField>,
+    /// Bits of the data after the last field
+    pub leftover: usize,
+    /// Bits needed by the fields past the end of the data
+    pub missing: usize,
+}
+
+/// Parse sample bytes written in hex (`45 00 0x3c`, `45:00:3c`) or base64
+pub fn parse_sample(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text
+        .split(|c: char| c.is_whitespace() || matches!(c, ',' | ':' | '-'))
+        .map(|token| {
+            token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"))
+                .unwrap_or(token)
+        })
+        .collect();
+    if digits.is_empty() {
+        return Err("no data".to_string());
+    }
+
+    if digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        if digits.len() % 2 != 0 {
+            return Err("odd number of hex digits".to_string());
+        }
+        return (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string());
+    }
+
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD]
+        .iter()
+        .find_map(|engine| engine.decode(&text).ok())
+        .ok_or_else(|| "the data is neither hex nor base64".to_string())
+}
+
+/// Write bytes in hex, 16 per line
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .map(|line| {
+            line.iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Read the bits of a field as a value, in the frame byte order
+fn extract(data: &[u8], offset: usize, bits: usize, network_order: bool) -> u64 {
+    let mut value = 0;
+    for i in 0..bits {
+        let position = offset + i;
+        let byte = data[position / 8];
+        if network_order {
+            let bit = (byte >> (7 - position % 8)) & 1;
+            value = (value << 1) | u64::from(bit);
+        } else {
+            let bit = (byte >> (position % 8)) & 1;
+            value |= u64::from(bit) << i;
+        }
+    }
+    value
+}
+
+/// Split the data into the fields of the descriptor.
+/// Variable lengths are computed from the fields they name when possible, and otherwise take the
+/// rest of the data, leaving room for the fixed fields after them.
+pub fn decode_sample(descriptor: &ProtoDescriptor, data: &[u8], unit_bits: usize) -> SampleDecode {
+    let fields = &descriptor.fields;
+    let network_order = descriptor.elements.network_order;
+    let field_names: Vec<&str> = fields.iter().map(|field| field.name.as_str()).collect();
+    let fixed: Vec<Option<usize>> = fields
+        .iter()
+        .map(|field| fixed_bits(&field.length, unit_bits))
+        .collect();
+    let total = data.len() * 8;
+
+    let mut values = vec![None; fields.len()];
+    let mut decoded = Vec::with_capacity(fields.len());
+    let mut offset: usize = 0;
+
+    for (i, field) in fields.iter().enumerate() {
+        let (bits, length_note) = match fixed[i] {
+            Some(bits) => (bits, None),
+            None => {
+                let length = field.length.to_string();
+                let units = parse_length(&length, &field_names)
+                    .ok()
+                    .and_then(|expr| expr.eval(&field_names, &values));
+                match units {
+                    Some(units) => {
+                        let bits = usize::try_from(units)
+                            .unwrap_or(usize::MAX)
+                            .saturating_mul(unit_bits);
+                        (bits, Some(format!("{} = {}", length.trim(), units)))
+                    }
+                    None => {
+                        let trailing: usize = fixed[i + 1..].iter().flatten().sum();
+                        let bits = total.saturating_sub(offset.saturating_add(trailing));
+                        (bits, Some("rest of the data".to_string()))
+                    }
+                }
+            }
+        };
+
+        let available = total.saturating_sub(offset).min(bits);
+        let missing = bits - available;
+        let value = (missing == 0 && (1..=64).contains(&bits))
+            .then(|| extract(data, offset, bits, network_order));
+        let bytes = (offset % 8 == 0 && bits % 8 == 0).then(|| {
+            let start = (offset / 8).min(data.len());
+            data[start..start + available / 8].to_vec()
+        });
+
+        values[i] = value;
+        decoded.push(DecodedField {
+            name: field.name.clone(),
+            offset,
+            bits,
+            missing,
+            length_note,
+            value,
+            bytes,
+        });
+        offset = offset.saturating_add(bits);
+    }
+
+    SampleDecode {
+        fields: decoded,
+        leftover: total.saturating_sub(offset),
+        missing: offset.saturating_sub(total),
+    }
+}
+
+/// Amount of data in bytes and bits, in words
+fn amount(bits: usize) -> String {
+    let plural = |count: usize, unit: &str| match count {
+        1 => format!("1 {}", unit),
+        count => format!("{} {}s", count, unit),
+    };
+    match (bits / 8, bits % 8) {
+        (bytes, 0) => plural(bytes, "byte"),
+        (0, bits) => plural(bits, "bit"),
+        (bytes, bits) => format!("{} and {}", plural(bytes, "byte"), plural(bits, "bit")),
+    }
+}
+
+impl SampleDecode {
+    /// Differences between the size of the data and of the fields
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.missing > 0 {
+            let incomplete = self.fields.iter().find(|field| field.missing > 0);
+            problems.push(match incomplete {
+                Some(field) => format!(
+                    "The data is {} short, from field '{}' on",
+                    amount(self.missing),
+                    field.name
+                ),
+                None => format!("The data is {} short", amount(self.missing)),
+            });
+        }
+        if self.leftover > 0 {
+            problems.push(format!(
+                "{} left over after the last field",
+                amount(self.leftover)
+            ));
+        }
+        problems
+    }
+}
+
+/// Bytes in hex, with an ellipsis after the given number of them
+fn hex_bytes(bytes: &[u8], max: usize) -> String {
+    let mut hex: Vec<String> = bytes
+        .iter()
+        .take(max)
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    if bytes.len() > max {
+        hex.push("…".to_string());
+    }
+    hex.join(" ")
+}
+
+impl DecodedField {
+    pub fn hex(&self) -> String {
+        match (&self.value, &self.bytes) {
+            (Some(value), _) => format!("0x{:01$X}", value, self.bits.div_ceil(4)),
+            (None, Some(bytes)) => hex_bytes(bytes, MAX_SHOWN_BYTES),
+            (None, None) => String::new(),
+        }
+    }
+
+    pub fn unsigned(&self) -> String {
+        self.value
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Value as a two's complement number
+    pub fn signed(&self) -> String {
+        let Some(value) = self.value else {
+            return String::new();
+        };
+        let shift = 64 - self.bits;
+        (((value << shift) as i64) >> shift).to_string()
+    }
+
+    /// Printable characters of the bytes, with dots for the others
+    pub fn ascii(&self) -> String {
+        let Some(bytes) = &self.bytes else {
+            return String::new();
+        };
+        let mut ascii: String = bytes
+            .iter()
+            .take(MAX_SHOWN_BYTES)
+            .map(|&byte| match byte {
+                0x20..=0x7E => byte as char,
+                _ => '.',
+            })
+            .collect();
+        if bytes.len() > MAX_SHOWN_BYTES {
+            ascii.push('…');
+        }
+        ascii
+    }
+
+    /// Offset in bytes (and bits)
+    pub fn position(&self) -> String {
+        bits_label(self.offset, BYTE_BITS)
+    }
+
+    /// Length in bytes (and bits)
+    pub fn length(&self) -> String {
+        bits_label(self.bits, BYTE_BITS)
+    }
+
+    /// Short value shown inside the box of the field
+    fn label(&self) -> String {
+        if self.missing > 0 {
+            return "missing".to_string();
+        }
+        match (&self.value, &self.bytes) {
+            (Some(value), _) if self.bits <= 16 => value.to_string(),
+            (Some(_), _) => self.hex(),
+            (None, Some(bytes)) => hex_bytes(bytes, MAX_LABEL_BYTES),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+/// Descriptor with the decoded value of each field next to its name
+pub fn overlay_descriptor(descriptor: &ProtoDescriptor, decode: &SampleDecode) -> ProtoDescriptor {
+    let fields = descriptor
+        .fields
+        .iter()
+        .zip(&decode.fields)
+        .map(|(field, decoded)| FieldDescriptor {
+            name: match decoded.label() {
+                label if label.is_empty() => field.name.clone(),
+                label => format!("{} = {}", field.name, label),
+            },
+            length: field.length.clone(),
+            wrap: field.wrap,
+            color: field.color,
+        })
+        .collect();
+    with_fields(descriptor, fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use protoviz::descriptor::FieldLength;
+
+    use super::*;
+
+    fn field(name: &str, length: FieldLength) -> FieldDescriptor {
+        FieldDescriptor {
+            name: name.to_string(),
+            length,
+            wrap: false,
+            color: None,
+        }
+    }
+
+    fn descriptor(fields: Vec<FieldDescriptor>, network_order: bool) -> ProtoDescriptor {
+        let mut descriptor = ProtoDescriptor {
+            fields,
+            ..Default::default()
+        };
+        descriptor.elements.network_order = network_order;
+        descriptor
+    }
+
+    fn variable(length: &str) -> FieldLength {
+        FieldLength::Variable(length.to_string())
+    }
+
+    fn values(decode: &SampleDecode) -> Vec<Option<u64>> {
+        decode.fields.iter().map(|field| field.value).collect()
+    }
+
+    fn value_field(bits: usize, value: u64) -> DecodedField {
+        DecodedField {
+            name: "Value".to_string(),
+            offset: 0,
+            bits,
+            missing: 0,
+            length_note: None,
+            value: Some(value),
+            bytes: None,
+        }
+    }
+
+    #[test]
+    fn parses_hex_and_base64() {
+        assert_eq!(parse_sample("45 00 0x3c").unwrap(), [0x45, 0x00, 0x3C]);
+        assert_eq!(parse_sample("45:00:3C\n").unwrap(), [0x45, 0x00, 0x3C]);
+        assert_eq!(parse_sample("0X45,0x00-3c").unwrap(), [0x45, 0x00, 0x3C]);
+        assert_eq!(parse_sample("RQA8").unwrap(), [0x45, 0x00, 0x3C]);
+        assert_eq!(parse_sample("RQA8PA==").unwrap(), [0x45, 0x00, 0x3C, 0x3C]);
+
+        assert_eq!(
+            parse_sample("45 0").err().as_deref(),
+            Some("odd number of hex digits")
+        );
+        assert_eq!(parse_sample(" \n").err().as_deref(), Some("no data"));
+        assert_eq!(
+            parse_sample("!!").err().as_deref(),
+            Some("the data is neither hex nor base64")
+        );
+    }
+
+    #[test]
+    fn reads_lengths_from_referenced_fields() {
+        let fields = vec![
+            field("Type", FieldLength::Fixed(1)),
+            field("Length", FieldLength::Fixed(1)),
+            field("Payload", variable("Length")),
+            field("Checksum", FieldLength::Fixed(1)),
+        ];
+        let data = [0x07, 0x03, 0xAA, 0xBB, 0xCC, 0xDD];
+        let decode = decode_sample(&descriptor(fields, true), &data, 8);
+
+        assert_eq!(
+            values(&decode),
+            [Some(7), Some(3), Some(0xAABBCC), Some(0xDD)]
+        );
+        let payload = &decode.fields[2];
+        assert_eq!((payload.offset, payload.bits), (16, 24));
+        assert_eq!(payload.length_note.as_deref(), Some("Length = 3"));
+        assert_eq!(payload.bytes.as_deref(), Some(&data[2..5]));
+        assert!(decode.problems().is_empty());
+    }
+
+    #[test]
+    fn takes_the_rest_of_the_data() {
+        let fields = vec![
+            field("Type", FieldLength::Fixed(1)),
+            field("Data", variable("N")),
+            field("CRC", FieldLength::Fixed(2)),
+        ];
+        let data = [1, 2, 3, 4, 5, 6];
+        let decode = decode_sample(&descriptor(fields, true), &data, 8);
+
+        let payload = &decode.fields[1];
+        assert_eq!(payload.bits, 24);
+        assert_eq!(payload.length_note.as_deref(), Some("rest of the data"));
+        assert_eq!(payload.bytes.as_deref(), Some(&data[1..4]));
+        assert_eq!(decode.fields[2].value, Some(0x0506));
+        assert_eq!((decode.missing, decode.leftover), (0, 0));
+    }
+
+    #[test]
+    fn reports_missing_and_leftover_bits() {
+        let fields = vec![
+            field("A", FieldLength::Fixed(2)),
+            field("B", FieldLength::Fixed(2)),
+        ];
+        let decode = decode_sample(&descriptor(fields, true), &[1, 2, 3], 8);
+        let b = &decode.fields[1];
+        assert_eq!((b.missing, b.value), (8, None));
+        assert_eq!(b.bytes.as_deref(), Some(&[3][..]));
+        assert_eq!(decode.missing, 8);
+        assert_eq!(
+            decode.problems(),
+            ["The data is 1 byte short, from field 'B' on"]
+        );
+
+        let fields = vec![field("Flags", FieldLength::Fixed(4))];
+        let decode = decode_sample(&descriptor(fields, true), &[0xAB, 0xCD], 1);
+        assert_eq!(decode.leftover, 12);
+        assert_eq!(
+            decode.problems(),
+            ["1 byte and 4 bits left over after the last field"]
+        );
+    }
+
+    #[test]
+    fn follows_the_bit_order_of_the_frame() {
+        let fields = || {
+            vec![
+                field("Low", FieldLength::Fixed(4)),
+                field("High", FieldLength::Fixed(4)),
+                field("Word", FieldLength::Fixed(16)),
+            ]
+        };
+        let data = [0xAB, 0x34, 0x12];
+
+        let little = decode_sample(&descriptor(fields(), false), &data, 1);
+        assert_eq!(values(&little), [Some(0xB), Some(0xA), Some(0x1234)]);
+
+        let network = decode_sample(&descriptor(fields(), true), &data, 1);
+        assert_eq!(values(&network), [Some(0xA), Some(0xB), Some(0x3412)]);
+    }
+
+    #[test]
+    fn formats_values() {
+        let byte = value_field(8, 0xFE);
+        assert_eq!(byte.hex(), "0xFE");
+        assert_eq!(byte.unsigned(), "254");
+        assert_eq!(byte.signed(), "-2");
+
+        assert_eq!(value_field(4, 0x9).hex(), "0x9");
+        assert_eq!(value_field(4, 0x9).signed(), "-7");
+        assert_eq!(value_field(12, 0x5).hex(), "0x005");
+        assert_eq!(value_field(12, 0x5).signed(), "5");
+        assert_eq!(value_field(64, u64::MAX).signed(), "-1");
+
+        let text = DecodedField {
+            value: None,
+            bytes: Some(b"Hi\0there, this is long".to_vec()),
+            ..value_field(176, 0)
+        };
+        assert_eq!(text.ascii(), "Hi.there, this i…");
+        assert_eq!(
+            text.hex(),
+            "48 69 00 74 68 65 72 65 2C 20 74 68 69 73 20 69 …"
+        );
+        assert_eq!(text.signed(), "");
+    }
+
+    #[test]
+    fn overlays_the_values_on_the_names() {
+        let fields = vec![
+            field("Type", FieldLength::Fixed(1)),
+            field("Address", FieldLength::Fixed(3)),
+            field("Data", variable("N")),
+            field("CRC", FieldLength::Fixed(2)),
+        ];
+        let descriptor = descriptor(fields, true);
+        let data = [7, 0xAA, 0xBB, 0xCC, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0x09, 0xFF];
+        let decode = decode_sample(&descriptor, &data, 8);
+        let overlay = overlay_descriptor(&descriptor, &decode);
+
+        let names: Vec<&str> = overlay
+            .fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "Type = 7",
+                "Address = 0xAABBCC",
+                "Data = 01 02 03 04 …",
+                "CRC = 2559"
+            ]
+        );
+        for (overlaid, field) in overlay.fields.iter().zip(&descriptor.fields) {
+            assert_eq!(overlaid.length, field.length);
+        }
+
+        let short = decode_sample(&descriptor, &data[..2], 8);
+        let overlay = overlay_descriptor(&descriptor, &short);
+        assert_eq!(overlay.fields[1].name, "Address = missing");
+    }
+}
@@ -0,0 +1,548 @@
+//! Editing sessions.
+//!
+//! The document being edited with the state of the editor around it: the fields and undo history
+//! of the frame shown, the fields selected for bulk edits, and the last diagram drawn with the
+//! problems found. The web app keeps a session in a signal and calls it from its event handlers.
+
+use protoviz::descriptor::FieldDescriptor;
+use protoviz::descriptor::ProtoDescriptor;
+
+use crate::clipboard::{fields_json, fields_text, parse_fields};
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics};
+use crate::document::{unique_frame_name, Document, Frame};
+use crate::fields::{create_field_descriptors, update_field_inputs, update_svg, FieldInput};
+use crate::group::{
+    field_rows, fields_at, group_fields, insert_fields, move_fields, remove_fields, FieldPath,
+};
+use crate::history::{Edit, History};
+
+/// Edit of the selected fields, giving the new fields and the paths of the fields to select then
+pub type BulkEdit<'a> =
+    dyn Fn(&[FieldInput], &[FieldPath]) -> Option<(Vec<FieldInput>, Vec<FieldPath>)> + 'a;
+
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Elements and style of the document, with the fields of the frame shown
+    pub descriptor: ProtoDescriptor,
+    /// Frames of the document. The fields and history of the one shown are in `fields` and
+    /// `history` while it is shown
+    pub frames: Vec<Frame>,
+    /// Index of the frame shown
+    pub active: usize,
+    pub fields: Vec<FieldInput>,
+    pub history: History,
+    /// Bits in a unit of the field lengths
+    pub unit_bits: usize,
+    /// Fields selected for bulk edits
+    pub selection: Vec<FieldPath>,
+    /// Row a range selection starts from, and fields are pasted after
+    pub anchor: Option<usize>,
+    /// Last diagram drawn, kept while the fields can't be drawn
+    pub svg: String,
+    pub diagnostics: Diagnostics,
+}
+
+impl Session {
+    pub fn new(document: Document) -> Self {
+        let mut session = Session::default();
+        session.open(document);
+        session
+    }
+
+    /// Replace the document, showing its first frame
+    pub fn open(&mut self, document: Document) {
+        self.fields = match document.frames.first() {
+            Some(frame) => frame.fields.clone(),
+            None => update_field_inputs(&document.descriptor.fields),
+        };
+        self.descriptor = document.descriptor;
+        self.frames = document.frames;
+        self.unit_bits = document.unit_bits;
+        self.active = 0;
+        self.diagnostics.clear(DiagnosticKind::Parse);
+        self.render();
+        self.history.clear();
+        self.clear_selection();
+    }
+
+    /// Draw the fields again, keeping the last diagram while they can't be drawn
+    pub fn render(&mut self) {
+        self.diagnostics.clear(DiagnosticKind::Render);
+        match update_svg(&self.descriptor, &self.fields, self.unit_bits) {
+            Ok(svg) => self.svg = svg,
+            Err(diagnostic) => self.diagnostics.report(diagnostic),
+        }
+    }
+
+    /// Show a problem in the diagnostics panel
+    pub fn report(&mut self, kind: DiagnosticKind, message: String) {
+        self.diagnostics.report(Diagnostic::new(kind, message));
+    }
+
+    /// Change the unit of the lengths of the document, as an edit
+    pub fn set_unit_bits(&mut self, unit_bits: usize) {
+        let old = self.unit_bits;
+        self.commit(Edit::Unit {
+            old,
+            new: unit_bits,
+        });
+    }
+
+    /// Apply an edit through the history and draw the fields
+    pub fn commit(&mut self, edit: Edit) {
+        // The paths of the selected fields don't follow the fields around
+        if matches!(
+            edit,
+            Edit::AddField { .. }
+                | Edit::RemoveField { .. }
+                | Edit::SwapFields { .. }
+                | Edit::ReplaceFields { .. }
+                | Edit::Expand { .. }
+        ) {
+            self.clear_selection();
+        }
+        self.history.record(
+            edit,
+            &mut self.fields,
+            &mut self.descriptor,
+            &mut self.unit_bits,
+        );
+        self.update();
+    }
+
+    /// Apply an edit merged with the previous one until `seal`, without drawing (e.g. while a
+    /// slider is dragged)
+    pub fn commit_coalesced(&mut self, edit: Edit) {
+        self.history.record_coalesced(
+            edit,
+            &mut self.fields,
+            &mut self.descriptor,
+            &mut self.unit_bits,
+        );
+    }
+
+    /// End the edit being merged and draw the fields
+    pub fn seal(&mut self) {
+        self.history.seal();
+        self.render();
+    }
+
+    pub fn undo(&mut self) -> bool {
+        let undone = self
+            .history
+            .undo(&mut self.fields, &mut self.descriptor, &mut self.unit_bits);
+        if undone {
+            self.clear_selection();
+            self.update();
+        }
+        undone
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let redone = self
+            .history
+            .redo(&mut self.fields, &mut self.descriptor, &mut self.unit_bits);
+        if redone {
+            self.clear_selection();
+            self.update();
+        }
+        redone
+    }
+
+    /// Replace the fields as a single edit
+    pub fn replace_fields(&mut self, new: Vec<FieldInput>) {
+        let old = self.fields.clone();
+        self.commit(Edit::ReplaceFields { old, new });
+    }
+
+    /// Add fields read from a file, a template or a diagram after the fields, or in their place
+    pub fn import_fields(&mut self, fields: &[FieldDescriptor], append: bool) {
+        let mut new = if append {
+            self.fields.clone()
+        } else {
+            Vec::new()
+        };
+        new.extend(update_field_inputs(fields));
+        self.replace_fields(new);
+    }
+
+    /// Name of the frame shown
+    pub fn frame_name(&self) -> &str {
+        self.frames
+            .get(self.active)
+            .map_or("", |frame| frame.name.as_str())
+    }
+
+    /// Show the fields of another frame, keeping the fields and history of the current one
+    pub fn show_frame(&mut self, index: usize) {
+        if index >= self.frames.len() {
+            return;
+        }
+        if let Some(frame) = self.frames.get_mut(self.active) {
+            frame.fields = std::mem::take(&mut self.fields);
+            frame.history = std::mem::take(&mut self.history);
+        }
+        self.fields = self.frames[index].fields.clone();
+        self.history = std::mem::take(&mut self.frames[index].history);
+        self.active = index;
+        self.clear_selection();
+        self.update();
+    }
+
+    /// Add a frame after the current one and show it
+    pub fn add_frame(&mut self, name: &str, fields: Vec<FieldInput>) {
+        let index = self.active + 1;
+        let name = unique_frame_name(&self.frames, name);
+        self.frames.insert(index, Frame::new(&name, fields));
+        self.show_frame(index);
+    }
+
+    /// Rename the current frame, keeping the names of the frames unique
+    pub fn rename_frame(&mut self, name: &str) {
+        let name = name.trim();
+        if name.is_empty() || name == self.frame_name() {
+            return;
+        }
+        let name = unique_frame_name(&self.frames, name);
+        if let Some(frame) = self.frames.get_mut(self.active) {
+            frame.name = name;
+        }
+    }
+
+    /// Delete the current frame, showing the next one
+    pub fn delete_frame(&mut self) {
+        if self.frames.len() <= 1 {
+            return;
+        }
+        self.frames.remove(self.active);
+        let next = self.active.min(self.frames.len() - 1);
+        self.fields = self.frames[next].fields.clone();
+        self.history = std::mem::take(&mut self.frames[next].history);
+        self.active = next;
+        self.clear_selection();
+        self.update();
+    }
+
+    /// Path of the field shown in a row of the field list
+    pub fn row_path(&self, row: usize) -> Option<FieldPath> {
+        field_rows(&self.fields).get(row).cloned()
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection.clear();
+        self.anchor = None;
+    }
+
+    /// Select a row, or the rows from the last one selected with `range`
+    pub fn select_row(&mut self, row: usize, range: bool) {
+        let Some(path) = self.row_path(row) else {
+            return;
+        };
+        match self.anchor.filter(|_| range) {
+            Some(anchor) => {
+                let rows = field_rows(&self.fields);
+                let selected = rows
+                    .get(anchor.min(row)..=anchor.max(row))
+                    .unwrap_or_default();
+                self.selection = selected.to_vec();
+            }
+            None => {
+                match self.selection.iter().position(|selected| *selected == path) {
+                    Some(i) => {
+                        self.selection.remove(i);
+                    }
+                    None => self.selection.push(path),
+                }
+                self.anchor = Some(row);
+            }
+        }
+    }
+
+    /// Replace the fields with the result of a bulk edit of the selected fields, as a single
+    /// edit, and select the fields it gives. Returns false if the edit doesn't apply.
+    pub fn edit_selection(&mut self, edit: &BulkEdit<'_>) -> bool {
+        let Some((new, selected)) = edit(&self.fields, &self.selection) else {
+            return false;
+        };
+        self.replace_fields(new);
+        self.selection = selected;
+        true
+    }
+
+    /// Move the selected fields one row up or down
+    pub fn move_selection(&mut self, down: bool) {
+        let rows = field_rows(&self.fields);
+        let selected = &self.selection;
+        let positions = rows
+            .iter()
+            .enumerate()
+            .filter(|(_, path)| selected.contains(path))
+            .map(|(row, _)| row);
+        let target = if down {
+            // The first row below the selection, not inside a selected field
+            let last = positions.max();
+            rows.iter()
+                .enumerate()
+                .skip_while(|&(row, _)| Some(row) <= last)
+                .map(|(_, path)| path)
+                .find(|path| !selected.iter().any(|selected| path.starts_with(selected)))
+        } else {
+            positions
+                .min()
+                .and_then(|first| rows.get(first.checked_sub(1)?))
+        };
+        if let Some(target) = target.cloned() {
+            self.edit_selection(&|fields, paths| move_fields(fields, paths, &target, down));
+        }
+    }
+
+    /// Move the field of a row, or the selection it is part of, before or after the row it is
+    /// dropped on, as a single edit
+    pub fn drop_row(&mut self, from: usize, to: usize) {
+        let (Some(source), Some(target)) = (self.row_path(from), self.row_path(to)) else {
+            return;
+        };
+        let after = to > from;
+        if self.selection.contains(&source) {
+            self.edit_selection(&|fields, paths| move_fields(fields, paths, &target, after));
+        } else if let Some((new, _)) = move_fields(&self.fields, &[source], &target, after) {
+            self.replace_fields(new);
+        }
+    }
+
+    /// Move the selected fields into a new field with the given name. Returns false if they
+    /// don't have the same parent.
+    pub fn group_selection(&mut self, name: &str) -> bool {
+        let unit_bits = self.unit_bits;
+        self.edit_selection(&|fields, paths| {
+            Some((group_fields(fields, paths, name, unit_bits)?, Vec::new()))
+        })
+    }
+
+    pub fn delete_selection(&mut self) {
+        self.edit_selection(&|fields, paths| Some((remove_fields(fields, paths)?, Vec::new())));
+    }
+
+    /// JSON of the selected fields for the clipboard, and their text for other applications
+    pub fn copy_selection(&mut self) -> Option<(String, String)> {
+        let fields =
+            fields_at(&self.fields, &self.selection).filter(|fields| !fields.is_empty())?;
+        match fields_json(&self.descriptor, &fields, self.unit_bits) {
+            Ok(json) => Some((json, fields_text(&fields))),
+            Err(e) => {
+                self.report(
+                    DiagnosticKind::Export,
+                    format!("Failed to copy fields: {}", e),
+                );
+                None
+            }
+        }
+    }
+
+    /// Paste fields after the row last clicked, or at the end, and select them
+    pub fn paste(&mut self, text: &str) {
+        let pasted = match parse_fields(text) {
+            Ok(pasted) => pasted,
+            Err(e) => {
+                self.report(
+                    DiagnosticKind::Parse,
+                    format!("Failed to paste fields: {}", e),
+                );
+                return;
+            }
+        };
+        let after = self.anchor.and_then(|row| self.row_path(row));
+        let Some((new, pasted)) = insert_fields(&self.fields, after.as_deref(), pasted) else {
+            return;
+        };
+        self.replace_fields(new);
+        self.anchor = field_rows(&self.fields)
+            .iter()
+            .position(|path| Some(path) == pasted.last());
+        self.selection = pasted;
+    }
+
+    /// Draw the fields after they changed
+    fn update(&mut self) {
+        self.descriptor.fields = create_field_descriptors(&self.fields);
+        self.render();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::default_document;
+    use crate::history::{ElementFlag, StyleSize};
+
+    fn names(fields: &[FieldInput]) -> Vec<&str> {
+        fields.iter().map(|field| field.name.as_str()).collect()
+    }
+
+    fn rename(session: &mut Session, row: usize, name: &str) {
+        let index = session.row_path(row).unwrap();
+        let old = session.fields[index[0]].name.clone();
+        session.commit(Edit::Rename {
+            index,
+            old,
+            new: name.to_string(),
+        });
+    }
+
+    #[test]
+    fn opens_documents() {
+        let mut session = Session::new(default_document());
+        assert_eq!(names(&session.fields), ["Field 1", "Field 2", "Field 3"]);
+        assert!(session.svg.contains("Field 1"));
+
+        session.report(DiagnosticKind::Parse, "bad file".to_string());
+        rename(&mut session, 0, "Type");
+        session.add_frame("Reply", Vec::new());
+        session.open(default_document());
+        assert_eq!(session.frames.len(), 1);
+        assert_eq!(session.active, 0);
+        assert!(!session.history.can_undo());
+        assert!(session.diagnostics.list().is_empty());
+    }
+
+    #[test]
+    fn undoes_edits_and_draws_them() {
+        let mut session = Session::new(default_document());
+        rename(&mut session, 0, "Type");
+        assert_eq!(session.descriptor.fields[0].name, "Type");
+        assert!(session.svg.contains("Type"));
+
+        assert!(session.undo());
+        assert_eq!(session.descriptor.fields[0].name, "Field 1");
+        assert!(!session.svg.contains("Type"));
+        assert!(!session.undo());
+        assert!(session.redo());
+        assert_eq!(session.fields[0].name, "Type");
+
+        // Slider moves are a single edit, drawn once they end
+        let edit = Edit::style_size(&session.descriptor, StyleSize::UnitWidth, 60);
+        session.commit_coalesced(edit);
+        let edit = Edit::style_size(&session.descriptor, StyleSize::UnitWidth, 65);
+        session.commit_coalesced(edit);
+        session.seal();
+        assert_eq!(session.descriptor.style.unit_width, 65);
+        let wrap_line = !session.descriptor.elements.wrap_line;
+        let edit = Edit::element(&session.descriptor, ElementFlag::WrapLine, wrap_line);
+        session.commit(edit);
+        session.undo();
+        session.undo();
+        assert_ne!(session.descriptor.style.unit_width, 65);
+        assert_eq!(session.fields[0].name, "Type");
+    }
+
+    #[test]
+    fn undoes_unit_changes() {
+        let mut session = Session::new(default_document());
+        session.set_unit_bits(8);
+        assert_eq!(session.unit_bits, 8);
+        assert!(session.history.can_undo());
+
+        assert!(session.undo());
+        assert_eq!(session.unit_bits, 1);
+        assert!(session.redo());
+        assert_eq!(session.unit_bits, 8);
+    }
+
+    #[test]
+    fn reports_render_problems_until_fixed() {
+        let mut session = Session::new(default_document());
+        let svg = session.svg.clone();
+        let index = vec![0];
+        session.commit(Edit::Length {
+            index: index.clone(),
+            old: "2".to_string(),
+            new: "0".to_string(),
+        });
+        let problem = session.diagnostics.at_field(&index).unwrap();
+        assert_eq!(problem.kind, DiagnosticKind::Render);
+        assert_eq!(session.svg, svg);
+
+        session.undo();
+        assert!(session.diagnostics.list().is_empty());
+    }
+
+    #[test]
+    fn keeps_the_fields_and_history_of_frames() {
+        let mut session = Session::new(default_document());
+        rename(&mut session, 0, "Type");
+        session.add_frame("Frame", Vec::new());
+        assert_eq!(session.active, 1);
+        assert!(session.fields.is_empty());
+        assert!(!session.history.can_undo());
+
+        session.add_frame("Frame", session.fields.clone());
+        assert_eq!(session.frame_name(), "Frame 2");
+        session.rename_frame(" Reply ");
+        assert_eq!(session.frame_name(), "Reply");
+
+        session.show_frame(0);
+        assert_eq!(session.fields[0].name, "Type");
+        assert!(session.undo());
+        assert_eq!(session.descriptor.fields[0].name, "Field 1");
+
+        session.show_frame(1);
+        session.delete_frame();
+        assert_eq!(session.active, 1);
+        assert_eq!(session.frame_name(), "Reply");
+        session.delete_frame();
+        session.delete_frame();
+        assert_eq!(session.frames.len(), 1);
+        assert_eq!(names(&session.fields), ["Field 1", "Field 2", "Field 3"]);
+    }
+
+    #[test]
+    fn edits_the_selection() {
+        let mut session = Session::new(default_document());
+        session.select_row(0, false);
+        session.select_row(2, true);
+        assert_eq!(session.selection, [vec![0], vec![1], vec![2]]);
+        session.select_row(1, false);
+        assert_eq!(session.selection, [vec![0], vec![2]]);
+
+        session.move_selection(false);
+        assert_eq!(names(&session.fields), ["Field 1", "Field 2", "Field 3"]);
+        session.clear_selection();
+        session.select_row(2, false);
+        session.move_selection(false);
+        assert_eq!(names(&session.fields), ["Field 1", "Field 3", "Field 2"]);
+        assert_eq!(session.selection, [vec![1]]);
+        session.select_row(0, false);
+
+        assert!(session.group_selection("Group"));
+        assert_eq!(names(&session.fields), ["Group", "Field 2"]);
+        assert!(session.selection.is_empty());
+        assert!(session.undo());
+
+        session.drop_row(0, 2);
+        assert_eq!(names(&session.fields), ["Field 3", "Field 2", "Field 1"]);
+    }
+
+    #[test]
+    fn copies_and_pastes_fields() {
+        let mut session = Session::new(default_document());
+        assert_eq!(session.copy_selection(), None);
+
+        session.select_row(0, false);
+        let (json, text) = session.copy_selection().unwrap();
+        assert_eq!(text, "Field 1:2");
+        session.delete_selection();
+        assert_eq!(names(&session.fields), ["Field 2", "Field 3"]);
+
+        // Pasted after the row last clicked, and selected
+        session.select_row(0, false);
+        session.paste(&json);
+        assert_eq!(names(&session.fields), ["Field 2", "Field 1", "Field 3"]);
+        assert_eq!(session.selection, [vec![1]]);
+        assert_eq!(session.anchor, Some(1));
+
+        session.paste(":4");
+        let problem = &session.diagnostics.list()[0];
+        assert_eq!(problem.kind, DiagnosticKind::Parse);
+        assert_eq!(session.fields.len(), 3);
+    }
+}
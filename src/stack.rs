@@ -0,0 +1,375 @@
+//! Encapsulation diagrams.
+//!
+//! The frames of a document, in order, are drawn as the layers of a protocol stack (Ethernet, IP,
+//! TCP, ...). Each layer is drawn next to the previous one, and connector lines expand its payload,
+//! the last field with a variable length, into the fields of the next layer.
+
+use hex_color::{Display, HexColor};
+use protoviz::descriptor::{FieldDescriptor, FieldLength, ProtoDescriptor};
+
+use crate::diagnostics::error_message;
+use crate::length::{parse_bits, render_svg};
+
+/// Vertical space between layers, for the connector lines
+const LAYER_SPACING: f64 = 40.0;
+
+/// Margin and size of the layer names
+const NAME_MARGIN: f64 = 10.0;
+const NAME_SIZE: f64 = 16.0;
+
+/// Width of the dashed lines linking a field to the fields it's made of
+const LINE_STROKE_WIDTH: f64 = 1.5;
+
+/// Box in the coordinates of an SVG
+#[derive(Clone, Copy)]
+pub struct Rect {
+    pub left: f64,
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+}
+
+impl Rect {
+    fn union(self, other: Rect) -> Rect {
+        Rect {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+}
+
+/// A descriptor rendered by protoviz, with the place of its fields
+pub struct RenderedLayer {
+    /// Elements of the SVG, without the root element
+    pub body: String,
+    pub width: f64,
+    pub height: f64,
+    /// Box around all the fields
+    pub fields: Rect,
+    /// Box of each field, in the order of the descriptor
+    pub boxes: Vec<Rect>,
+}
+
+/// Whether the field is drawn with a variable length (lengths in bits are drawn as fixed ones)
+fn is_variable(field: &FieldDescriptor) -> bool {
+    match &field.length {
+        FieldLength::Fixed(_) => false,
+        FieldLength::Variable(length) => parse_bits(length).is_none(),
+    }
+}
+
+/// Read the points of the polygons of a protoviz SVG
+fn polygons(svg: &str) -> Vec<Vec<(f64, f64)>> {
+    svg.split("<polygon points=\"")
+        .skip(1)
+        .filter_map(|rest| {
+            let points = rest.split('"').next()?;
+            points
+                .split_whitespace()
+                .map(|point| {
+                    let (x, y) = point.split_once(',')?;
+                    Some((x.parse().ok()?, y.parse().ok()?))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn bounds(points: &[(f64, f64)]) -> Rect {
+    points.iter().fold(
+        Rect {
+            left: f64::INFINITY,
+            top: f64::INFINITY,
+            right: f64::NEG_INFINITY,
+            bottom: f64::NEG_INFINITY,
+        },
+        |rect, &(x, y)| Rect {
+            left: rect.left.min(x),
+            top: rect.top.min(y),
+            right: rect.right.max(x),
+            bottom: rect.bottom.max(y),
+        },
+    )
+}
+
+/// Render the descriptor with the given number of bits in a unit, finding the boxes of its fields
+pub fn render_layer(
+    descriptor: &ProtoDescriptor,
+    unit_bits: usize,
+) -> Result<RenderedLayer, String> {
+    if descriptor.fields.is_empty() {
+        return Err("there are no fields".to_string());
+    }
+    let svg = render_svg(descriptor, unit_bits).map_err(|e| error_message(&e))?;
+    let invalid = || "unexpected SVG".to_string();
+
+    let size = svg
+        .split("viewBox=\"0 0 ")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .ok_or_else(invalid)?;
+    let mut size = size.split_whitespace().map(str::parse::<f64>);
+    let (Some(Ok(width)), Some(Ok(height))) = (size.next(), size.next()) else {
+        return Err(invalid());
+    };
+    let body_start = svg.find('>').ok_or_else(invalid)? + 1;
+    let body_end = svg.rfind("</svg>").ok_or_else(invalid)?;
+
+    // Fields are drawn in order (from the last one when not in network order), with a rectangle,
+    // or two slanted halves for the variable ones after all the fixed ones. The start symbol is a
+    // triangle. This is how protoviz 0.5.3 draws them, the version pinned in Cargo.toml.
+    let shapes: Vec<Vec<(f64, f64)>> = polygons(&svg)
+        .into_iter()
+        .filter(|points| points.len() == 4)
+        .collect();
+    let (halves, rectangles): (Vec<_>, Vec<_>) = shapes
+        .iter()
+        .partition(|points| points[0].0 != points[1].0 || points[2].0 != points[3].0);
+    let mut rectangles = rectangles.into_iter().map(|points| bounds(points));
+    let mut halves = halves.into_iter().map(|points| bounds(points));
+
+    let fields: Box<dyn Iterator<Item = _>> = if descriptor.elements.network_order {
+        Box::new(descriptor.fields.iter())
+    } else {
+        Box::new(descriptor.fields.iter().rev())
+    };
+    let mut boxes = fields
+        .map(|field| match is_variable(field) {
+            true => Some(halves.next()?.union(halves.next()?)),
+            false => rectangles.next(),
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(invalid)?;
+    if !descriptor.elements.network_order {
+        boxes.reverse();
+    }
+
+    Ok(RenderedLayer {
+        body: svg[body_start..body_end].to_string(),
+        width,
+        height,
+        fields: boxes
+            .iter()
+            .copied()
+            .reduce(Rect::union)
+            .ok_or_else(invalid)?,
+        boxes,
+    })
+}
+
+/// Render the layers, from the outermost one, as a single SVG, with the given number of bits in a
+/// unit
+pub fn render_stack(
+    layers: &[(&str, &ProtoDescriptor)],
+    unit_bits: usize,
+) -> Result<String, String> {
+    let Some((_, first)) = layers.first() else {
+        return Err("the stack has no layers".to_string());
+    };
+
+    let rendered = layers
+        .iter()
+        .map(|(name, descriptor)| {
+            render_layer(descriptor, unit_bits).map_err(|e| format!("layer '{}': {}", name, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The payload of a layer is its last variable field
+    let mut payloads = Vec::with_capacity(layers.len());
+    for (i, ((name, descriptor), layer)) in layers.iter().zip(&rendered).enumerate() {
+        let payload = descriptor
+            .fields
+            .iter()
+            .rposition(is_variable)
+            .map(|index| layer.boxes[index]);
+        if payload.is_none() && i + 1 < layers.len() {
+            return Err(format!(
+                "layer '{}' has no variable length field to hold the next layer",
+                name
+            ));
+        }
+        payloads.push(payload);
+    }
+
+    // Each layer is drawn below its name. The stack goes down in network order, where the payload
+    // is at the bottom of a layer, and up otherwise.
+    let name_band = NAME_MARGIN + NAME_SIZE;
+    let downward = first.elements.network_order;
+    let width = rendered.iter().map(|layer| layer.width).fold(0.0, f64::max);
+    let height = rendered
+        .iter()
+        .map(|layer| name_band + layer.height)
+        .sum::<f64>()
+        + LAYER_SPACING * (rendered.len() - 1) as f64;
+
+    // Origins of the layers, centered
+    let mut origins = vec![(0.0, 0.0); rendered.len()];
+    let mut y = 0.0;
+    let order: Box<dyn Iterator<Item = usize>> = if downward {
+        Box::new(0..rendered.len())
+    } else {
+        Box::new((0..rendered.len()).rev())
+    };
+    for i in order {
+        origins[i] = ((width - rendered[i].width) / 2.0, y + name_band);
+        y += name_band + rendered[i].height + LAYER_SPACING;
+    }
+
+    let mut svg = format!(
+        "<svg viewBox=\"0 0 {} {}\" xmlns=\"http://www.w3.org/2000/svg\" style=\"background-color:{}\">",
+        width,
+        height,
+        Display::new(first.style.background_color)
+    );
+    for (i, ((name, descriptor), layer)) in layers.iter().zip(&rendered).enumerate() {
+        let (x, y) = origins[i];
+        svg.push_str(&format!(
+            "\n<text x=\"{}\" y=\"{}\" fill=\"{}\" dominant-baseline=\"hanging\" font-size=\"{}\" font-weight=\"bold\">{}</text>",
+            NAME_MARGIN,
+            y - NAME_SIZE,
+            Display::new(descriptor.style.text_color),
+            NAME_SIZE,
+            escape(name)
+        ));
+        svg.push_str(&format!("\n<g transform=\"translate({},{})\">", x, y));
+        svg.push_str(&layer.body);
+        svg.push_str("\n</g>");
+
+        // Connector lines from the corners of the payload to the fields of the next layer
+        let (Some(payload), Some(next)) = (payloads[i], rendered.get(i + 1)) else {
+            continue;
+        };
+        let (next_x, next_y) = origins[i + 1];
+        let (from_y, to_y) = if downward {
+            (y + payload.bottom, next_y + next.fields.top)
+        } else {
+            (y + payload.top, next_y + next.fields.bottom)
+        };
+        for (from_x, to_x) in [
+            (payload.left, next.fields.left),
+            (payload.right, next.fields.right),
+        ] {
+            svg.push_str(&dashed_line(
+                (x + from_x, from_y),
+                (next_x + to_x, to_y),
+                descriptor.style.text_color,
+            ));
+        }
+    }
+    svg.push_str("\n</svg>");
+    Ok(svg)
+}
+
+/// SVG line between two points, dashed, as drawn from a payload or a group to its fields
+pub fn dashed_line(from: (f64, f64), to: (f64, f64), color: HexColor) -> String {
+    format!(
+        "\n<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" style=\"stroke:{};stroke-width:{};stroke-dasharray:6 4\" />",
+        from.0,
+        from.1,
+        to.0,
+        to.1,
+        Display::new(color),
+        LINE_STROKE_WIDTH
+    )
+}
+
+/// Escape the text of an SVG element
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::TEMPLATES;
+
+    fn ipv4() -> ProtoDescriptor {
+        TEMPLATES
+            .iter()
+            .find(|template| template.name == "IPv4")
+            .unwrap()
+            .descriptor()
+            .unwrap()
+    }
+
+    fn width(rect: &Rect) -> f64 {
+        rect.right - rect.left
+    }
+
+    /// The boxes are read from the polygons drawn by protoviz, which is pinned to the version
+    /// these tests were written for. They fail when its drawing changes.
+    #[test]
+    fn finds_the_boxes_of_the_fields() {
+        let descriptor = ipv4();
+        let layer = render_layer(&descriptor, 1).unwrap();
+        assert_eq!(layer.boxes.len(), descriptor.fields.len());
+
+        let name = |name| {
+            descriptor
+                .fields
+                .iter()
+                .position(|field| field.name == name)
+                .map(|index| layer.boxes[index])
+                .unwrap()
+        };
+        let (version, ihl, tos, total_length) = (
+            name("Version"),
+            name("IHL"),
+            name("TOS"),
+            name("Total Length"),
+        );
+        let (identification, options, data) =
+            (name("Identification"), name("Options"), name("Data"));
+
+        // The first row holds 4, 4, 8 and 16 bits side by side
+        assert_eq!(version.top, total_length.top);
+        assert_eq!(version.right, ihl.left);
+        assert_eq!(ihl.right, tos.left);
+        assert_eq!(tos.right, total_length.left);
+        assert_eq!(width(&tos), 2.0 * width(&ihl));
+        assert_eq!(width(&total_length), 2.0 * width(&tos));
+        assert_eq!(layer.fields.left, version.left);
+        assert_eq!(layer.fields.right, total_length.right);
+
+        // The next rows are below, and the variable fields come last, side by side
+        assert!(identification.top >= version.bottom);
+        assert!(options.top >= identification.bottom);
+        assert_eq!(data.top, options.top);
+        assert_eq!(data.left, options.right);
+        assert_eq!(layer.fields.bottom, data.bottom);
+        assert!(layer.fields.right <= layer.width && layer.fields.bottom <= layer.height);
+    }
+
+    #[test]
+    fn finds_the_boxes_of_little_endian_fields() {
+        let mut descriptor = ipv4();
+        descriptor.elements.network_order = false;
+        let layer = render_layer(&descriptor, 1).unwrap();
+
+        // Fields are drawn from the right
+        let (version, ihl) = (layer.boxes[0], layer.boxes[1]);
+        assert_eq!(version.top, ihl.top);
+        assert_eq!(ihl.right, version.left);
+    }
+
+    #[test]
+    fn stacks_layers_on_their_payload() {
+        let ethernet = TEMPLATES[0].descriptor().unwrap();
+        let ipv4 = ipv4();
+        let svg = render_stack(&[("Ethernet", &ethernet), ("IPv4", &ipv4)], 8).unwrap();
+        assert_eq!(svg.matches("stroke-dasharray").count(), 2);
+        assert!(svg.contains(">Ethernet</text>") && svg.contains(">IPv4</text>"));
+
+        let mut fixed = ethernet;
+        fixed.fields.retain(|field| !is_variable(field));
+        let error = render_stack(&[("Fixed", &fixed), ("IPv4", &ipv4)], 8).unwrap_err();
+        assert_eq!(
+            error,
+            "layer 'Fixed' has no variable length field to hold the next layer"
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use dioxus_logger::tracing::error;
+use protoviz::descriptor::ProtoDescriptor;
+use serde::{Deserialize, Serialize};
+
+use crate::document::{document_value, parse_document, Document, Frame};
+use crate::fields::FieldInput;
+
+/// Key of the autosaved document in the browser local storage
+const STORAGE_KEY: &str = "protoviz_web.document";
+
+/// Version of the stored document format, bumped whenever it changes
+const STORAGE_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct StoredDocument {
+    version: u32,
+    /// Document, or a single descriptor in version 1
+    #[serde(alias = "descriptor")]
+    document: serde_json::Value,
+}
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok().flatten()
+}
+
+/// Save the document to the local storage, returning false on failure.
+/// The fields of the frame at `active` are `active_fields`, with lengths in units of `unit_bits`.
+pub fn save_document(
+    descriptor: &ProtoDescriptor,
+    frames: &[Frame],
+    active: usize,
+    active_fields: &[FieldInput],
+    unit_bits: usize,
+) -> bool {
+    let storage = match local_storage() {
+        Some(storage) => storage,
+        None => {
+            error!("Local storage is not available");
+            return false;
+        }
+    };
+
+    let json =
+        match document_value(descriptor, frames, active, active_fields, unit_bits).and_then(|document| {
+            serde_json::to_string(&StoredDocument {
+                version: STORAGE_VERSION,
+                document,
+            })
+        }) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed to serialize document: {}", e);
+                return false;
+            }
+        };
+
+    if let Err(e) = storage.set_item(STORAGE_KEY, &json) {
+        error!("Failed to write local storage: {:?}", e);
+        return false;
+    }
+
+    true
+}
+
+/// Load the document saved in the local storage.
+/// Returns `Ok(None)` if there is nothing stored and an error if the stored state can't be used.
+pub fn load_document() -> Result<Option<Document>, String> {
+    let json = match local_storage().map(|storage| storage.get_item(STORAGE_KEY)) {
+        Some(Ok(Some(json))) => json,
+        Some(Ok(None)) | None => return Ok(None),
+        Some(Err(e)) => return Err(format!("failed to read local storage ({:?})", e)),
+    };
+
+    let stored: StoredDocument =
+        serde_json::from_str(&json).map_err(|e| format!("stored document is corrupt ({})", e))?;
+
+    // Version 1 stored a single descriptor, which opens as a document with one frame
+    if !(1..=STORAGE_VERSION).contains(&stored.version) {
+        return Err(format!(
+            "stored document has unsupported version {}",
+            stored.version
+        ));
+    }
+
+    parse_document(stored.document)
+        .map(Some)
+        .map_err(|e| format!("stored document is invalid ({})", e))
+}
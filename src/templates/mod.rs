@@ -0,0 +1,154 @@
+use protoviz::descriptor::{FieldDescriptor, ProtoDescriptor};
+
+use crate::length::{bits_length, fixed_bits};
+
+/// A ready-made descriptor bundled into the binary
+pub struct Template {
+    pub name: &'static str,
+    /// Number of bits in a unit of the lengths
+    pub unit_bits: usize,
+    json: &'static str,
+}
+
+impl Template {
+    /// Parse the bundled descriptor
+    pub fn descriptor(&self) -> Result<ProtoDescriptor, String> {
+        serde_json::from_str(self.json)
+            .map_err(|e| format!("template '{}' is invalid ({})", self.name, e))
+    }
+
+    /// Fields of the template, with their lengths in units of the given number of bits
+    pub fn fields(&self, unit_bits: usize) -> Result<Vec<FieldDescriptor>, String> {
+        let fields = self.descriptor()?.fields;
+        Ok(fields
+            .into_iter()
+            .map(|field| FieldDescriptor {
+                length: match fixed_bits(&field.length, self.unit_bits) {
+                    Some(bits) => bits_length(bits, unit_bits),
+                    None => field.length,
+                },
+                ..field
+            })
+            .collect())
+    }
+}
+
+/// Templates offered in the "Templates" menu
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "Ethernet II",
+        unit_bits: 8,
+        json: include_str!("ethernet.json"),
+    },
+    Template {
+        name: "802.1Q",
+        unit_bits: 8,
+        json: include_str!("dot1q.json"),
+    },
+    Template {
+        name: "IPv4",
+        unit_bits: 1,
+        json: include_str!("ipv4.json"),
+    },
+    Template {
+        name: "IPv6",
+        unit_bits: 1,
+        json: include_str!("ipv6.json"),
+    },
+    Template {
+        name: "TCP",
+        unit_bits: 1,
+        json: include_str!("tcp.json"),
+    },
+    Template {
+        name: "UDP",
+        unit_bits: 1,
+        json: include_str!("udp.json"),
+    },
+    Template {
+        name: "ICMP",
+        unit_bits: 1,
+        json: include_str!("icmp.json"),
+    },
+    Template {
+        name: "ARP",
+        unit_bits: 8,
+        json: include_str!("arp.json"),
+    },
+    Template {
+        name: "DNS Header",
+        unit_bits: 1,
+        json: include_str!("dns.json"),
+    },
+    Template {
+        name: "CAN 2.0A",
+        unit_bits: 1,
+        json: include_str!("can_a.json"),
+    },
+    Template {
+        name: "CAN 2.0B",
+        unit_bits: 1,
+        json: include_str!("can_b.json"),
+    },
+    Template {
+        name: "Modbus RTU",
+        unit_bits: 8,
+        json: include_str!("modbus_rtu.json"),
+    },
+    Template {
+        name: "MQTT Fixed Header",
+        unit_bits: 1,
+        json: include_str!("mqtt.json"),
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use protoviz::descriptor::FieldLength;
+
+    use super::*;
+
+    fn template(name: &str) -> &'static Template {
+        TEMPLATES
+            .iter()
+            .find(|template| template.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn templates_keep_their_lengths_in_their_unit() {
+        for template in TEMPLATES {
+            assert!(
+                [1, 8].contains(&template.unit_bits),
+                "{}: {}",
+                template.name,
+                template.unit_bits
+            );
+            let lengths = |fields: Vec<FieldDescriptor>| -> Vec<FieldLength> {
+                fields.into_iter().map(|field| field.length).collect()
+            };
+            assert_eq!(
+                lengths(template.fields(template.unit_bits).unwrap()),
+                lengths(template.descriptor().unwrap().fields),
+                "{}",
+                template.name
+            );
+        }
+    }
+
+    #[test]
+    fn converts_appended_fields_to_the_unit() {
+        let ipv4 = template("IPv4").fields(8).unwrap();
+        assert_eq!(ipv4[0].length, FieldLength::Variable("4b".to_string()));
+        assert!(ipv4
+            .iter()
+            .any(|field| field.name == "Total Length" && field.length == FieldLength::Fixed(2)));
+
+        let ethernet = template("Ethernet II").fields(1).unwrap();
+        assert_eq!(ethernet[0].length, FieldLength::Fixed(48));
+        assert_eq!(
+            template("Ethernet II").fields(8).unwrap()[0].length,
+            FieldLength::Fixed(6)
+        );
+    }
+}
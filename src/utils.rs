@@ -1,15 +1,6 @@
+use dioxus::html::DragData;
 use dioxus_logger::tracing::error;
-use hex_color::HexColor;
-use js_sys::wasm_bindgen::JsCast;
-use protoviz::descriptor::{FieldDescriptor, FieldLength, ProtoDescriptor};
-
-#[derive(Debug, Default)]
-pub struct FieldInput {
-    pub name: String,
-    pub length: String,
-    pub wrap: bool,
-    pub color: Option<HexColor>,
-}
+use js_sys::wasm_bindgen::{closure::Closure, JsCast, JsValue};
 
 pub fn download_file(data: &[u8], filename: &str, file_type: &str) -> bool {
     let js_byte_array = js_sys::Uint8Array::from(data);
@@ -33,7 +24,7 @@ pub fn download_file(data: &[u8], filename: &str, file_type: &str) -> bool {
         }
     };
 
-    let document = match web_sys::window().map(|window| window.document()).flatten() {
+    let document = match web_sys::window().and_then(|window| window.document()) {
         Some(document) => document,
         None => {
             error!("Failed to get document");
@@ -70,53 +61,191 @@ pub fn download_file(data: &[u8], filename: &str, file_type: &str) -> bool {
     true
 }
 
-pub fn create_field_descriptors(input_fields: &[FieldInput]) -> Vec<FieldDescriptor> {
-    input_fields
-        .iter()
-        .map(|field| {
-            FieldDescriptor {
-                name: field.name.clone(),
-                length: if !field.length.is_empty() {
-                    match field.length.parse::<usize>() {
-                        Ok(len) => FieldLength::Fixed(len),
-                        Err(_) => FieldLength::Variable(field.length.clone()),
-                    }
-                } else {
-                    FieldLength::Fixed(1) // If no length is provided, default to 1
-                },
-                wrap: field.wrap,
-                color: field.color,
+/// Whether the target of an event is an entry where the user types text
+fn is_text_entry(target: Option<web_sys::EventTarget>) -> bool {
+    let Some(target) = target else {
+        return false;
+    };
+    if target.is_instance_of::<web_sys::HtmlTextAreaElement>() {
+        return true;
+    }
+    target
+        .dyn_into::<web_sys::HtmlInputElement>()
+        .map(|input| !matches!(input.type_().as_str(), "checkbox" | "color" | "range" | "file"))
+        .unwrap_or(false)
+}
+
+/// Listen to keydown events on the whole page, ignoring the ones typed into text entries
+pub fn add_keydown_listener(mut callback: impl FnMut(&web_sys::KeyboardEvent) + 'static) -> bool {
+    let document = match web_sys::window().and_then(|window| window.document()) {
+        Some(document) => document,
+        None => {
+            error!("Failed to get document");
+            return false;
+        }
+    };
+
+    let closure = Closure::<dyn FnMut(web_sys::KeyboardEvent)>::new(move |evt: web_sys::KeyboardEvent| {
+        if !is_text_entry(evt.target()) {
+            callback(&evt);
+        }
+    });
+
+    if let Err(e) = document.add_event_listener_with_callback("keydown", closure.as_ref().unchecked_ref()) {
+        error!("Failed to add keydown listener: {:?}", e);
+        return false;
+    }
+
+    // The listener lives as long as the page
+    closure.forget();
+
+    true
+}
+
+/// Listen to the copy, cut or paste events of the page, outside of text entries
+pub fn add_clipboard_listener(
+    event: &str,
+    mut callback: impl FnMut(&web_sys::ClipboardEvent) + 'static,
+) -> bool {
+    let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+        error!("Failed to get document");
+        return false;
+    };
+
+    let closure = Closure::<dyn FnMut(web_sys::ClipboardEvent)>::new(move |evt: web_sys::ClipboardEvent| {
+        if !is_text_entry(evt.target()) {
+            callback(&evt);
+        }
+    });
+
+    if let Err(e) = document.add_event_listener_with_callback(event, closure.as_ref().unchecked_ref()) {
+        error!("Failed to add {} listener: {:?}", event, e);
+        return false;
+    }
+
+    // The listener lives as long as the page
+    closure.forget();
+
+    true
+}
+
+/// Keep the browser from opening the files dropped on the page, so the app can read them
+pub fn prevent_file_drop() -> bool {
+    let Some(window) = web_sys::window() else {
+        error!("Failed to get window");
+        return false;
+    };
+
+    let closure = Closure::<dyn FnMut(web_sys::Event)>::new(|evt: web_sys::Event| {
+        evt.prevent_default();
+    });
+
+    for event in ["dragover", "drop"] {
+        if let Err(e) = window.add_event_listener_with_callback(event, closure.as_ref().unchecked_ref()) {
+            error!("Failed to add {} listener: {:?}", event, e);
+            return false;
+        }
+    }
+
+    // The listener lives as long as the page
+    closure.forget();
+
+    true
+}
+
+/// Start dragging a field row: the drag only moves things around the page, but browsers like
+/// Firefox only start it with some data set
+pub fn start_row_drag(data: &DragData) {
+    let Some(event) = data
+        .downcast::<web_sys::MouseEvent>()
+        .and_then(|event| event.dyn_ref::<web_sys::DragEvent>())
+    else {
+        return;
+    };
+    let Some(transfer) = event.data_transfer() else {
+        return;
+    };
+
+    if let Err(e) = transfer.set_data("text/plain", "") {
+        error!("Failed to set drag data: {:?}", e);
+    }
+    transfer.set_effect_allowed("move");
+}
+
+/// Get the URL fragment of the page (without the leading '#'), if any
+pub fn get_location_hash() -> Option<String> {
+    let hash = web_sys::window()?.location().hash().ok()?;
+    let hash = hash.trim_start_matches('#');
+
+    if hash.is_empty() {
+        None
+    } else {
+        Some(hash.to_string())
+    }
+}
+
+/// Remove the URL fragment without reloading the page
+pub fn clear_location_hash() {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+
+    let url = match window.location().pathname() {
+        Ok(pathname) => pathname + &window.location().search().unwrap_or_default(),
+        Err(e) => {
+            error!("Failed to get pathname: {:?}", e);
+            return;
+        }
+    };
+
+    match window.history() {
+        Ok(history) => {
+            if let Err(e) = history.replace_state_with_url(&JsValue::NULL, "", Some(&url)) {
+                error!("Failed to clear URL fragment: {:?}", e);
             }
-        })
-        .collect()
+        }
+        Err(e) => error!("Failed to get history: {:?}", e),
+    }
 }
 
-pub fn update_field_inputs(descriptors: &[FieldDescriptor]) -> Vec<FieldInput> {
-    descriptors
-        .iter()
-        .map(|field| FieldInput {
-            name: field.name.clone(),
-            length: match &field.length {
-                FieldLength::Fixed(len) => len.to_string(),
-                FieldLength::Variable(len) => len.clone(),
-            },
-            wrap: field.wrap,
-            color: field.color,
-        })
-        .collect()
+/// Build a link to the page with the given URL fragment
+pub fn page_url_with_hash(hash: &str) -> Option<String> {
+    let location = web_sys::window()?.location();
+    let origin = location.origin().ok()?;
+    let pathname = location.pathname().ok()?;
+
+    Some(format!("{}{}#{}", origin, pathname, hash))
 }
 
-pub fn update_svg(descriptor: &ProtoDescriptor) -> String {
-    if descriptor.fields.is_empty() {
-        return String::new();
+/// Write text to the system clipboard, returning false on failure
+pub async fn copy_to_clipboard(text: &str) -> bool {
+    let Some(window) = web_sys::window() else {
+        error!("Failed to get window");
+        return false;
+    };
+
+    let promise = window.navigator().clipboard().write_text(text);
+    if let Err(e) = wasm_bindgen_futures::JsFuture::from(promise).await {
+        error!("Failed to write clipboard: {:?}", e);
+        return false;
     }
 
-    match protoviz::render(&descriptor) {
-        Ok(svg) => svg,
+    true
+}
+
+/// Read text from the system clipboard
+pub async fn read_from_clipboard() -> Option<String> {
+    let Some(window) = web_sys::window() else {
+        error!("Failed to get window");
+        return None;
+    };
+
+    let promise = window.navigator().clipboard().read_text();
+    match wasm_bindgen_futures::JsFuture::from(promise).await {
+        Ok(text) => text.as_string(),
         Err(e) => {
-            error!("Failed to render SVG: {}", e);
-            gloo_dialogs::alert(&format!("Failed to render SVG: {:?}", e));
-            String::new()
+            error!("Failed to read clipboard: {:?}", e);
+            None
         }
     }
 }
@@ -0,0 +1,258 @@
+//! Descriptor validation.
+//!
+//! The fields are checked before they are drawn, for problems protoviz rejects or draws silently
+//! wrong. Errors keep a field from being drawn as intended (a zero length, a length naming an
+//! unknown field), warnings point at likely mistakes (duplicate names, a field across the end of a
+//! row) and at lengths other protoviz tools can't draw (bits between two units). Lengths are
+//! counted in the unit of the document. The fields in a group are checked among themselves.
+
+use protoviz::descriptor::{FieldLength, ProtoDescriptor};
+
+use crate::expr::check_length;
+use crate::fields::{create_field_descriptors, with_fields, FieldInput};
+use crate::group::FieldPath;
+use crate::length::{fixed_bits, parse_bits};
+
+/// Bits in a row of a packet diagram laid out in rows, which fields shouldn't cross without
+/// wrapping
+const ROW_BITS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Problem {
+    fn error(message: impl Into<String>) -> Self {
+        Problem {
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        Problem {
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+/// Problems of each field of the descriptor, given the number of bits in a unit of the document
+pub fn validate(descriptor: &ProtoDescriptor, unit_bits: usize) -> Vec<Vec<Problem>> {
+    let fields = &descriptor.fields;
+    let names: Vec<&str> = fields.iter().map(|field| field.name.as_str()).collect();
+    // A diagram without wraps is a single row, as long as it needs to be
+    let in_rows = fields.iter().any(|field| field.wrap);
+
+    // Offset of the field in its row, while the lengths before it are known
+    let mut offset = Some(0);
+    let mut problems = Vec::with_capacity(fields.len());
+    for (i, field) in fields.iter().enumerate() {
+        let mut field_problems = Vec::new();
+
+        let name = field.name.trim();
+        if name.is_empty() {
+            field_problems.push(Problem::warning("empty name"));
+        } else if names
+            .iter()
+            .enumerate()
+            .any(|(j, other)| j != i && other.trim().eq_ignore_ascii_case(name))
+        {
+            field_problems.push(Problem::warning(format!("duplicate name '{}'", name)));
+        }
+
+        match &field.length {
+            FieldLength::Fixed(0) => field_problems.push(Problem::error("length 0")),
+            FieldLength::Variable(length) if length.trim().is_empty() => {
+                field_problems.push(Problem::error("empty length"))
+            }
+            FieldLength::Variable(length) => match parse_bits(length) {
+                Some(0) => field_problems.push(Problem::error("length 0")),
+                // Other protoviz tools can only draw whole units
+                Some(bits) if bits % unit_bits != 0 => {
+                    field_problems.push(Problem::warning(format!(
+                        "{} bits isn't a whole number of {}-bit units",
+                        bits, unit_bits
+                    )))
+                }
+                Some(_) => {}
+                None => field_problems.extend(
+                    check_length(length, &names, i)
+                        .into_iter()
+                        .map(Problem::error),
+                ),
+            },
+            FieldLength::Fixed(_) => {}
+        }
+
+        // Protoviz starts a new row only after a wrapped field. A field at the start of a row can
+        // be longer than it.
+        let bits = fixed_bits(&field.length, unit_bits);
+        if let (true, Some(start), Some(bits)) = (in_rows, offset, bits) {
+            if start % ROW_BITS != 0 && start % ROW_BITS + bits > ROW_BITS {
+                field_problems.push(Problem::warning(format!(
+                    "field crosses {}-bit boundary without wrap",
+                    ROW_BITS
+                )));
+            }
+        }
+        offset = match (offset, bits) {
+            _ if field.wrap => Some(0),
+            (Some(start), Some(bits)) => start.checked_add(bits),
+            _ => None,
+        };
+
+        problems.push(field_problems);
+    }
+    problems
+}
+
+/// Problems of the fields and of the fields in their groups, with the path of their field
+pub fn validate_fields(
+    descriptor: &ProtoDescriptor,
+    fields: &[FieldInput],
+    unit_bits: usize,
+) -> Vec<(FieldPath, Problem)> {
+    fn visit(
+        descriptor: &ProtoDescriptor,
+        fields: &[FieldInput],
+        unit_bits: usize,
+        parent: &mut FieldPath,
+        all: &mut Vec<(FieldPath, Problem)>,
+    ) {
+        let group = with_fields(descriptor, create_field_descriptors(fields));
+        for (i, (field, problems)) in fields.iter().zip(validate(&group, unit_bits)).enumerate() {
+            parent.push(i);
+            all.extend(
+                problems
+                    .into_iter()
+                    .map(|problem| (parent.clone(), problem)),
+            );
+            visit(descriptor, &field.children, unit_bits, parent, all);
+            parent.pop();
+        }
+    }
+
+    let mut all = Vec::new();
+    visit(descriptor, fields, unit_bits, &mut Vec::new(), &mut all);
+    all
+}
+
+#[cfg(test)]
+mod tests {
+    use protoviz::descriptor::FieldDescriptor;
+
+    use super::*;
+    use crate::templates::TEMPLATES;
+
+    fn field(name: &str, length: FieldLength, wrap: bool) -> FieldDescriptor {
+        FieldDescriptor {
+            name: name.to_string(),
+            length,
+            wrap,
+            color: None,
+        }
+    }
+
+    fn messages(fields: Vec<FieldDescriptor>, unit_bits: usize) -> Vec<Vec<String>> {
+        let descriptor = ProtoDescriptor {
+            fields,
+            ..Default::default()
+        };
+        validate(&descriptor, unit_bits)
+            .into_iter()
+            .map(|problems| {
+                problems
+                    .into_iter()
+                    .map(|problem| problem.message)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn templates_are_valid_in_their_unit() {
+        for template in TEMPLATES {
+            let descriptor = template.descriptor().unwrap();
+            let problems: Vec<_> = validate(&descriptor, template.unit_bits)
+                .into_iter()
+                .flatten()
+                .collect();
+            assert!(problems.is_empty(), "{}: {:?}", template.name, problems);
+        }
+    }
+
+    #[test]
+    fn checks_names_and_lengths() {
+        let problems = messages(
+            vec![
+                field("Type", FieldLength::Fixed(8), false),
+                field("type", FieldLength::Fixed(0), false),
+                field(" ", FieldLength::Variable(" ".to_string()), false),
+                field("Data", FieldLength::Variable("Size".to_string()), false),
+            ],
+            1,
+        );
+        assert_eq!(problems[0], ["duplicate name 'Type'"]);
+        assert_eq!(problems[1], ["duplicate name 'type'", "length 0"]);
+        assert_eq!(problems[2], ["empty name", "empty length"]);
+        assert_eq!(problems[3].len(), 1);
+    }
+
+    #[test]
+    fn checks_row_boundaries_in_the_unit() {
+        let fields = || {
+            vec![
+                field("Type", FieldLength::Fixed(2), false),
+                field("Length", FieldLength::Fixed(3), true),
+            ]
+        };
+        // 2 + 3 bytes cross the end of a 32-bit row, 2 + 3 bits don't
+        assert_eq!(
+            messages(fields(), 8)[1],
+            ["field crosses 32-bit boundary without wrap"]
+        );
+        assert!(messages(fields(), 1).iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn checks_row_boundaries_only_in_rows() {
+        // A field starting a row may be longer than it
+        let long = vec![
+            field("Address", FieldLength::Fixed(6), true),
+            field("Port", FieldLength::Fixed(2), true),
+        ];
+        assert!(messages(long, 8).iter().all(Vec::is_empty));
+
+        // Without wraps, the diagram is a single row
+        let single_row = vec![
+            field("Type", FieldLength::Fixed(2), false),
+            field("Length", FieldLength::Fixed(3), false),
+        ];
+        assert!(messages(single_row, 8).iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn warns_about_bits_between_units() {
+        let fields = || {
+            vec![
+                field("Version", FieldLength::Variable("4b".to_string()), false),
+                field("Flags", FieldLength::Variable("16b".to_string()), false),
+                field("Padding", FieldLength::Variable("0b".to_string()), false),
+            ]
+        };
+        let problems = messages(fields(), 8);
+        assert_eq!(problems[0], ["4 bits isn't a whole number of 8-bit units"]);
+        assert!(problems[1].is_empty());
+        assert_eq!(problems[2], ["length 0"]);
+        assert!(messages(fields(), 1)[0].is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+//! Tests of the `protoviz-web-cli` command line tool.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const DOCUMENT: &str = r#"{
+    "unit_bits": 1,
+    "frames": [
+        {"name": "Request", "fields": [
+            {"name": "Type", "length": 8},
+            {"name": "Length", "length": 8, "wrap": true},
+            {"name": "Data", "length": "Length"}
+        ]},
+        {"name": "Reply", "fields": [
+            {"name": "Status", "length": 16}
+        ]}
+    ]
+}"#;
+
+const DESCRIPTOR: &str = r#"{"fields": [{"name": "Type", "length": 8}]}"#;
+
+/// Empty directory for a test
+fn test_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("protoviz-web-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(args: &[&str], dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_protoviz-web-cli"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn writes_an_image_per_frame() {
+    let dir = test_dir("frames");
+    fs::write(dir.join("protocol.json"), DOCUMENT).unwrap();
+    fs::write(dir.join("single.json"), DESCRIPTOR).unwrap();
+
+    let output = run(&["protocol.json", "single.json"], &dir);
+    assert!(output.status.success(), "{}", stderr(&output));
+    for image in ["protocol-request.svg", "protocol-reply.svg", "single.svg"] {
+        let svg = fs::read_to_string(dir.join(image)).unwrap();
+        assert!(svg.trim_start().starts_with("<svg"), "{}", image);
+    }
+    let reply = fs::read_to_string(dir.join("protocol-reply.svg")).unwrap();
+    assert!(reply.contains("Status"));
+}
+
+#[test]
+fn writes_to_the_output_directory() {
+    let dir = test_dir("out-dir");
+    fs::write(dir.join("single.json"), DESCRIPTOR).unwrap();
+
+    let output = run(&["--out-dir", "images/svg", "single.json"], &dir);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(dir.join("images/svg/single.svg").is_file());
+    assert!(!dir.join("single.svg").exists());
+
+    let output = run(
+        &["-o", "images/png", "--format", "png", "single.json"],
+        &dir,
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let png = fs::read(dir.join("images/png/single.png")).unwrap();
+    assert_eq!(png[..4], [0x89, b'P', b'N', b'G']);
+}
+
+#[test]
+fn checks_that_images_are_current() {
+    let dir = test_dir("check");
+    fs::write(dir.join("single.json"), DESCRIPTOR).unwrap();
+
+    let output = run(&["--check", "single.json"], &dir);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("single.svg: missing"));
+    assert!(!dir.join("single.svg").exists());
+
+    assert!(run(&["single.json"], &dir).status.success());
+    let output = run(&["--check", "single.json"], &dir);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+
+    // Documents changed since the images were written
+    fs::write(dir.join("single.json"), DESCRIPTOR.replace("Type", "Kind")).unwrap();
+    let output = run(&["--check", "single.json"], &dir);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("single.svg: out of date"));
+}
+
+#[test]
+fn overrides_the_style() {
+    let dir = test_dir("style");
+    fs::write(dir.join("single.json"), DESCRIPTOR).unwrap();
+
+    // Colors made only of digits stay colors, with or without '#'
+    let output = run(
+        &[
+            "--style",
+            "field_color=112233",
+            "-s",
+            "text_color=#abcdef",
+            "--style=unit_width=30",
+            "single.json",
+        ],
+        &dir,
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let svg = fs::read_to_string(dir.join("single.svg"))
+        .unwrap()
+        .to_lowercase();
+    assert!(svg.contains("#112233"), "{}", svg);
+    assert!(svg.contains("#abcdef"), "{}", svg);
+}
+
+#[test]
+fn rejects_invalid_styles() {
+    let dir = test_dir("bad-style");
+    fs::write(dir.join("single.json"), DESCRIPTOR).unwrap();
+
+    for (style, error) in [
+        (
+            "unit_width",
+            "invalid style 'unit_width', expected KEY=VALUE",
+        ),
+        ("size=3", "unknown style property 'size'"),
+        (
+            "unit_width=wide",
+            "invalid unit_width 'wide', expected a number",
+        ),
+        (
+            "field_color=#12345g",
+            "invalid field_color '#12345g', expected a hex color",
+        ),
+    ] {
+        let output = run(&["--style", style, "single.json"], &dir);
+        assert_eq!(output.status.code(), Some(2), "{}", style);
+        assert!(
+            stderr(&output).contains(error),
+            "{}: {}",
+            style,
+            stderr(&output)
+        );
+    }
+    assert!(!dir.join("single.svg").exists());
+}
+
+#[test]
+fn reports_invalid_documents() {
+    let dir = test_dir("invalid");
+    fs::write(
+        dir.join("empty.json"),
+        r#"{"fields": [{"name": "Type", "length": 0}]}"#,
+    )
+    .unwrap();
+
+    let output = run(&["empty.json", "missing.json"], &dir);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = stderr(&output);
+    assert!(stderr.contains("empty.json: "), "{}", stderr);
+    assert!(stderr.contains("missing.json: "), "{}", stderr);
+
+    let output = run(&[], &dir);
+    assert_eq!(output.status.code(), Some(2));
+}